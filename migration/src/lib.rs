@@ -1,5 +1,7 @@
 pub use sea_orm_migration::prelude::*;
 
+mod util;
+
 mod m20220101_000001_create_table;
 mod m20250213_073143_create_drivers_table;
 mod m20250213_164001_create_users_table;
@@ -12,6 +14,28 @@ mod m20250221_071147_create_support_tickets;
 mod m20250221_101629_add_firstname_lastname;
 mod m20250224_111441_create_user_profiles;
 mod m20250225_070801_create_recent_locations;
+mod m20250303_094512_create_refresh_tokens;
+mod m20250306_101204_add_role_to_users;
+mod m20250310_082233_create_password_reset_tokens;
+mod m20250312_140517_add_email_verification;
+mod m20250317_093048_create_phone_verifications;
+mod m20250320_111902_link_drivers_to_users;
+mod m20250324_090415_create_login_attempts;
+mod m20250327_101530_create_totp_mfa;
+mod m20250331_083412_create_oidc_identities;
+mod m20250403_094220_create_user_sessions;
+mod m20250408_103355_add_account_deletion;
+mod m20250414_091207_create_data_exports;
+mod m20250417_140936_create_account_suspensions;
+mod m20250422_101844_create_legal_documents;
+mod m20250428_150312_create_impersonation_audit;
+mod m20250502_093017_constrain_ride_status;
+mod m20250506_112540_create_ride_fares;
+mod m20250512_101423_add_ride_quoted_fare;
+mod m20250519_094512_create_ride_offers;
+mod m20250526_101500_add_driver_location_updated_at;
+mod m20250527_090000_make_user_phone_optional;
+mod m20250602_093000_add_ride_quote_id;
 
 pub struct Migrator;
 
@@ -31,6 +55,28 @@ impl MigratorTrait for Migrator {
             Box::new(m20250221_101629_add_firstname_lastname::Migration),
             Box::new(m20250224_111441_create_user_profiles::Migration),
             Box::new(m20250225_070801_create_recent_locations::Migration),
+            Box::new(m20250303_094512_create_refresh_tokens::Migration),
+            Box::new(m20250306_101204_add_role_to_users::Migration),
+            Box::new(m20250310_082233_create_password_reset_tokens::Migration),
+            Box::new(m20250312_140517_add_email_verification::Migration),
+            Box::new(m20250317_093048_create_phone_verifications::Migration),
+            Box::new(m20250320_111902_link_drivers_to_users::Migration),
+            Box::new(m20250324_090415_create_login_attempts::Migration),
+            Box::new(m20250327_101530_create_totp_mfa::Migration),
+            Box::new(m20250331_083412_create_oidc_identities::Migration),
+            Box::new(m20250403_094220_create_user_sessions::Migration),
+            Box::new(m20250408_103355_add_account_deletion::Migration),
+            Box::new(m20250414_091207_create_data_exports::Migration),
+            Box::new(m20250417_140936_create_account_suspensions::Migration),
+            Box::new(m20250422_101844_create_legal_documents::Migration),
+            Box::new(m20250428_150312_create_impersonation_audit::Migration),
+            Box::new(m20250502_093017_constrain_ride_status::Migration),
+            Box::new(m20250506_112540_create_ride_fares::Migration),
+            Box::new(m20250512_101423_add_ride_quoted_fare::Migration),
+            Box::new(m20250519_094512_create_ride_offers::Migration),
+            Box::new(m20250526_101500_add_driver_location_updated_at::Migration),
+            Box::new(m20250527_090000_make_user_phone_optional::Migration),
+            Box::new(m20250602_093000_add_ride_quote_id::Migration),
         ]
     }
 }
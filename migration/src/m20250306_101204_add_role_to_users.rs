@@ -0,0 +1,50 @@
+use sea_orm_migration::prelude::*;
+use sea_orm::DbErr;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Existing accounts become riders; staff roles are granted explicitly.
+        if !manager.has_column(Users::Table.as_ref(), Users::Role.as_ref()).await? {
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(Users::Table)
+                        .add_column(ColumnDef::new(Users::Role).string().not_null().default("rider"))
+                        .to_owned(),
+                )
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Users::Table)
+                    .drop_column(Users::Role)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(Iden)]
+enum Users {
+    Table,
+    Role,
+}
+
+impl AsRef<str> for Users {
+    fn as_ref(&self) -> &str {
+        match self {
+            Users::Table => "users",
+            Users::Role => "role",
+        }
+    }
+}
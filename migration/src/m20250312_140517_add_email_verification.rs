@@ -0,0 +1,102 @@
+use sea_orm_migration::prelude::*;
+use sea_orm::DbErr;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        if !manager.has_column(Users::Table.as_ref(), Users::EmailVerifiedAt.as_ref()).await? {
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(Users::Table)
+                        .add_column(ColumnDef::new(Users::EmailVerifiedAt).timestamp().null())
+                        .to_owned(),
+                )
+                .await?;
+
+            // Accounts created before verification existed keep their ability to book rides.
+            manager
+                .exec_stmt(
+                    Query::update()
+                        .table(Users::Table)
+                        .value(Users::EmailVerifiedAt, Expr::current_timestamp())
+                        .to_owned(),
+                )
+                .await?;
+        }
+
+        manager
+            .create_table(
+                Table::create()
+                    .table(EmailVerificationTokens::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(EmailVerificationTokens::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(EmailVerificationTokens::UserId).integer().not_null())
+                    .col(ColumnDef::new(EmailVerificationTokens::TokenHash).string().not_null().unique_key())
+                    .col(ColumnDef::new(EmailVerificationTokens::ExpiresAt).timestamp().not_null())
+                    .col(ColumnDef::new(EmailVerificationTokens::UsedAt).timestamp().null())
+                    .col(ColumnDef::new(EmailVerificationTokens::CreatedAt).timestamp().not_null().default(Expr::current_timestamp()))
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk-email_verification_tokens-user")
+                            .from(EmailVerificationTokens::Table, EmailVerificationTokens::UserId)
+                            .to(Users::Table, Users::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(EmailVerificationTokens::Table).to_owned())
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Users::Table)
+                    .drop_column(Users::EmailVerifiedAt)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(Iden)]
+enum EmailVerificationTokens {
+    Table,
+    Id,
+    UserId,
+    TokenHash,
+    ExpiresAt,
+    UsedAt,
+    CreatedAt,
+}
+
+#[derive(Iden)]
+enum Users {
+    Table,
+    Id,
+    EmailVerifiedAt,
+}
+
+impl AsRef<str> for Users {
+    fn as_ref(&self) -> &str {
+        match self {
+            Users::Table => "users",
+            Users::Id => "id",
+            Users::EmailVerifiedAt => "email_verified_at",
+        }
+    }
+}
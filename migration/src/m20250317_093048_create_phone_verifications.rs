@@ -0,0 +1,119 @@
+use sea_orm_migration::prelude::*;
+use sea_orm::DbErr;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(PhoneVerifications::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(PhoneVerifications::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(PhoneVerifications::SubjectType).string().not_null())
+                    .col(ColumnDef::new(PhoneVerifications::SubjectId).integer().not_null())
+                    .col(ColumnDef::new(PhoneVerifications::Phone).string().not_null())
+                    .col(ColumnDef::new(PhoneVerifications::CodeHash).string().not_null())
+                    .col(ColumnDef::new(PhoneVerifications::Attempts).integer().not_null().default(0))
+                    .col(ColumnDef::new(PhoneVerifications::ExpiresAt).timestamp().not_null())
+                    .col(ColumnDef::new(PhoneVerifications::ConsumedAt).timestamp().null())
+                    .col(ColumnDef::new(PhoneVerifications::CreatedAt).timestamp().not_null().default(Expr::current_timestamp()))
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx-phone_verifications-subject")
+                    .table(PhoneVerifications::Table)
+                    .col(PhoneVerifications::SubjectType)
+                    .col(PhoneVerifications::SubjectId)
+                    .to_owned(),
+            )
+            .await?;
+
+        if !manager.has_column("users", "phone_verified_at").await? {
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(Users::Table)
+                        .add_column(ColumnDef::new(Users::PhoneVerifiedAt).timestamp().null())
+                        .to_owned(),
+                )
+                .await?;
+        }
+
+        if !manager.has_column("drivers", "phone_verified_at").await? {
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(Drivers::Table)
+                        .add_column(ColumnDef::new(Drivers::PhoneVerifiedAt).timestamp().null())
+                        .to_owned(),
+                )
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Drivers::Table)
+                    .drop_column(Drivers::PhoneVerifiedAt)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Users::Table)
+                    .drop_column(Users::PhoneVerifiedAt)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .drop_table(Table::drop().table(PhoneVerifications::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(Iden)]
+enum PhoneVerifications {
+    Table,
+    Id,
+    SubjectType,
+    SubjectId,
+    Phone,
+    CodeHash,
+    Attempts,
+    ExpiresAt,
+    ConsumedAt,
+    CreatedAt,
+}
+
+#[derive(Iden)]
+enum Users {
+    Table,
+    PhoneVerifiedAt,
+}
+
+#[derive(Iden)]
+enum Drivers {
+    Table,
+    PhoneVerifiedAt,
+}
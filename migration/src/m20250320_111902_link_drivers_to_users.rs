@@ -0,0 +1,57 @@
+use sea_orm_migration::prelude::*;
+use sea_orm::DbErr;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // A driver's credential lives on the linked `users` row.
+        if !manager.has_column("drivers", "user_id").await? {
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(Drivers::Table)
+                        .add_column(ColumnDef::new(Drivers::UserId).integer().null().unique_key())
+                        .add_foreign_key(
+                            TableForeignKey::new()
+                                .name("fk-drivers-user")
+                                .from_tbl(Drivers::Table)
+                                .from_col(Drivers::UserId)
+                                .to_tbl(Users::Table)
+                                .to_col(Users::Id)
+                                .on_delete(ForeignKeyAction::SetNull),
+                        )
+                        .to_owned(),
+                )
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Drivers::Table)
+                    .drop_foreign_key(Alias::new("fk-drivers-user"))
+                    .drop_column(Drivers::UserId)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(Iden)]
+enum Drivers {
+    Table,
+    UserId,
+}
+
+#[derive(Iden)]
+enum Users {
+    Table,
+    Id,
+}
@@ -0,0 +1,60 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(LoginAttempts::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(LoginAttempts::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(LoginAttempts::ThrottleKey).string().not_null().unique_key())
+                    .col(ColumnDef::new(LoginAttempts::UserId).integer().null())
+                    .col(ColumnDef::new(LoginAttempts::FailedCount).integer().not_null().default(0))
+                    .col(ColumnDef::new(LoginAttempts::LastFailedAt).timestamp().not_null())
+                    .col(ColumnDef::new(LoginAttempts::LockedUntil).timestamp().null())
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk-login_attempts-user")
+                            .from(LoginAttempts::Table, LoginAttempts::UserId)
+                            .to(Users::Table, Users::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(LoginAttempts::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(Iden)]
+enum LoginAttempts {
+    Table,
+    Id,
+    ThrottleKey,
+    UserId,
+    FailedCount,
+    LastFailedAt,
+    LockedUntil,
+}
+
+#[derive(Iden)]
+enum Users {
+    Table,
+    Id,
+}
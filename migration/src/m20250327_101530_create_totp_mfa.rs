@@ -0,0 +1,139 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(UserTotp::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(UserTotp::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(UserTotp::UserId).integer().not_null().unique_key())
+                    .col(ColumnDef::new(UserTotp::Secret).string().not_null())
+                    .col(ColumnDef::new(UserTotp::EnabledAt).timestamp().null())
+                    .col(ColumnDef::new(UserTotp::LastUsedStep).big_integer().null())
+                    .col(ColumnDef::new(UserTotp::CreatedAt).timestamp().not_null().default(Expr::current_timestamp()))
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk-user_totp-user")
+                            .from(UserTotp::Table, UserTotp::UserId)
+                            .to(Users::Table, Users::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_table(
+                Table::create()
+                    .table(MfaRecoveryCodes::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(MfaRecoveryCodes::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(MfaRecoveryCodes::UserId).integer().not_null())
+                    .col(ColumnDef::new(MfaRecoveryCodes::CodeHash).string().not_null())
+                    .col(ColumnDef::new(MfaRecoveryCodes::UsedAt).timestamp().null())
+                    .col(ColumnDef::new(MfaRecoveryCodes::CreatedAt).timestamp().not_null().default(Expr::current_timestamp()))
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk-mfa_recovery_codes-user")
+                            .from(MfaRecoveryCodes::Table, MfaRecoveryCodes::UserId)
+                            .to(Users::Table, Users::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx-mfa_recovery_codes-user_id")
+                    .table(MfaRecoveryCodes::Table)
+                    .col(MfaRecoveryCodes::UserId)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_table(
+                Table::create()
+                    .table(MfaRequiredRoles::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(MfaRequiredRoles::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(MfaRequiredRoles::Role).string().not_null().unique_key())
+                    .col(ColumnDef::new(MfaRequiredRoles::CreatedAt).timestamp().not_null().default(Expr::current_timestamp()))
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(MfaRequiredRoles::Table).to_owned())
+            .await?;
+        manager
+            .drop_table(Table::drop().table(MfaRecoveryCodes::Table).to_owned())
+            .await?;
+        manager
+            .drop_table(Table::drop().table(UserTotp::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(Iden)]
+enum UserTotp {
+    Table,
+    Id,
+    UserId,
+    Secret,
+    EnabledAt,
+    LastUsedStep,
+    CreatedAt,
+}
+
+#[derive(Iden)]
+enum MfaRecoveryCodes {
+    Table,
+    Id,
+    UserId,
+    CodeHash,
+    UsedAt,
+    CreatedAt,
+}
+
+#[derive(Iden)]
+enum MfaRequiredRoles {
+    Table,
+    Id,
+    Role,
+    CreatedAt,
+}
+
+#[derive(Iden)]
+enum Users {
+    Table,
+    Id,
+}
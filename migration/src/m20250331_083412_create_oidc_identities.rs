@@ -0,0 +1,111 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(UserIdentities::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(UserIdentities::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(UserIdentities::UserId).integer().not_null())
+                    .col(ColumnDef::new(UserIdentities::Provider).string().not_null())
+                    .col(ColumnDef::new(UserIdentities::Subject).string().not_null())
+                    .col(ColumnDef::new(UserIdentities::Email).string().null())
+                    .col(ColumnDef::new(UserIdentities::CreatedAt).timestamp().not_null().default(Expr::current_timestamp()))
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk-user_identities-user")
+                            .from(UserIdentities::Table, UserIdentities::UserId)
+                            .to(Users::Table, Users::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx-user_identities-provider-subject")
+                    .table(UserIdentities::Table)
+                    .col(UserIdentities::Provider)
+                    .col(UserIdentities::Subject)
+                    .unique()
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_table(
+                Table::create()
+                    .table(OidcLoginStates::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(OidcLoginStates::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(OidcLoginStates::StateHash).string().not_null().unique_key())
+                    .col(ColumnDef::new(OidcLoginStates::Provider).string().not_null())
+                    .col(ColumnDef::new(OidcLoginStates::Nonce).string().not_null())
+                    .col(ColumnDef::new(OidcLoginStates::CodeVerifier).string().not_null())
+                    .col(ColumnDef::new(OidcLoginStates::ExpiresAt).timestamp().not_null())
+                    .col(ColumnDef::new(OidcLoginStates::UsedAt).timestamp().null())
+                    .col(ColumnDef::new(OidcLoginStates::CreatedAt).timestamp().not_null().default(Expr::current_timestamp()))
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(OidcLoginStates::Table).to_owned())
+            .await?;
+        manager
+            .drop_table(Table::drop().table(UserIdentities::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(Iden)]
+enum UserIdentities {
+    Table,
+    Id,
+    UserId,
+    Provider,
+    Subject,
+    Email,
+    CreatedAt,
+}
+
+#[derive(Iden)]
+enum OidcLoginStates {
+    Table,
+    Id,
+    StateHash,
+    Provider,
+    Nonce,
+    CodeVerifier,
+    ExpiresAt,
+    UsedAt,
+    CreatedAt,
+}
+
+#[derive(Iden)]
+enum Users {
+    Table,
+    Id,
+}
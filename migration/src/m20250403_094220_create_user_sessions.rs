@@ -0,0 +1,151 @@
+use sea_orm_migration::prelude::*;
+use sea_orm::DbErr;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(UserSessions::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(UserSessions::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(UserSessions::UserId).integer().not_null())
+                    .col(ColumnDef::new(UserSessions::FamilyId).string().not_null().unique_key())
+                    .col(ColumnDef::new(UserSessions::Device).string().null())
+                    .col(ColumnDef::new(UserSessions::UserAgent).string().null())
+                    .col(ColumnDef::new(UserSessions::IpAddress).string().null())
+                    .col(ColumnDef::new(UserSessions::CreatedAt).timestamp().not_null().default(Expr::current_timestamp()))
+                    .col(ColumnDef::new(UserSessions::LastSeenAt).timestamp().not_null().default(Expr::current_timestamp()))
+                    .col(ColumnDef::new(UserSessions::RevokedAt).timestamp().null())
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk-user_sessions-user")
+                            .from(UserSessions::Table, UserSessions::UserId)
+                            .to(Users::Table, Users::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx-user_sessions-user_id")
+                    .table(UserSessions::Table)
+                    .col(UserSessions::UserId)
+                    .to_owned(),
+            )
+            .await?;
+
+        if !manager.has_column(RefreshTokens::Table.as_ref(), RefreshTokens::SessionId.as_ref()).await? {
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(RefreshTokens::Table)
+                        .add_column(ColumnDef::new(RefreshTokens::SessionId).integer().null())
+                        .to_owned(),
+                )
+                .await?;
+
+            // Every existing token family becomes a session. A family whose tokens are
+            // all revoked is a session that has already ended.
+            let db = manager.get_connection();
+            db.execute_unprepared(
+                "INSERT INTO user_sessions (user_id, family_id, device, created_at, last_seen_at, revoked_at)
+                 SELECT user_id, family_id, MAX(device), MIN(created_at), MAX(created_at),
+                        CASE WHEN BOOL_AND(revoked_at IS NOT NULL) THEN MAX(revoked_at) END
+                 FROM refresh_tokens
+                 GROUP BY user_id, family_id",
+            )
+            .await?;
+            db.execute_unprepared(
+                "UPDATE refresh_tokens SET session_id = user_sessions.id
+                 FROM user_sessions
+                 WHERE user_sessions.family_id = refresh_tokens.family_id",
+            )
+            .await?;
+
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(RefreshTokens::Table)
+                        .modify_column(ColumnDef::new(RefreshTokens::SessionId).integer().not_null())
+                        .add_foreign_key(
+                            TableForeignKey::new()
+                                .name("fk-refresh_tokens-session")
+                                .from_tbl(RefreshTokens::Table)
+                                .from_col(RefreshTokens::SessionId)
+                                .to_tbl(UserSessions::Table)
+                                .to_col(UserSessions::Id)
+                                .on_delete(ForeignKeyAction::Cascade),
+                        )
+                        .to_owned(),
+                )
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(RefreshTokens::Table)
+                    .drop_foreign_key(Alias::new("fk-refresh_tokens-session"))
+                    .drop_column(RefreshTokens::SessionId)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .drop_table(Table::drop().table(UserSessions::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(Iden)]
+enum UserSessions {
+    Table,
+    Id,
+    UserId,
+    FamilyId,
+    Device,
+    UserAgent,
+    IpAddress,
+    CreatedAt,
+    LastSeenAt,
+    RevokedAt,
+}
+
+#[derive(Iden)]
+enum RefreshTokens {
+    Table,
+    SessionId,
+}
+
+impl AsRef<str> for RefreshTokens {
+    fn as_ref(&self) -> &str {
+        match self {
+            RefreshTokens::Table => "refresh_tokens",
+            RefreshTokens::SessionId => "session_id",
+        }
+    }
+}
+
+#[derive(Iden)]
+enum Users {
+    Table,
+    Id,
+}
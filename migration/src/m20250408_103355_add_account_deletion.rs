@@ -0,0 +1,58 @@
+use sea_orm_migration::prelude::*;
+use sea_orm::DbErr;
+use crate::util::ride_table;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        if !manager.has_column(Users::Table.as_ref(), Users::DeletionScheduledFor.as_ref()).await? {
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(Users::Table)
+                        .add_column(ColumnDef::new(Users::DeletionScheduledFor).timestamp().null())
+                        .to_owned(),
+                )
+                .await?;
+        }
+
+        // Rides of deleted accounts are kept for financial records without the user link.
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Alias::new(ride_table(manager).await?))
+                    .modify_column(ColumnDef::new(Alias::new("user_id")).integer().null())
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Users::Table)
+                    .drop_column(Users::DeletionScheduledFor)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(Iden)]
+enum Users {
+    Table,
+    DeletionScheduledFor,
+}
+
+impl AsRef<str> for Users {
+    fn as_ref(&self) -> &str {
+        match self {
+            Users::Table => "users",
+            Users::DeletionScheduledFor => "deletion_scheduled_for",
+        }
+    }
+}
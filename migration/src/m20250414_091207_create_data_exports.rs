@@ -0,0 +1,77 @@
+use sea_orm_migration::prelude::*;
+use sea_orm::DbErr;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(DataExports::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(DataExports::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(DataExports::UserId).integer().not_null())
+                    .col(ColumnDef::new(DataExports::Status).string().not_null().default("pending"))
+                    .col(ColumnDef::new(DataExports::Archive).binary().null())
+                    .col(ColumnDef::new(DataExports::DownloadTokenHash).string().null().unique_key())
+                    .col(ColumnDef::new(DataExports::Error).string().null())
+                    .col(ColumnDef::new(DataExports::RequestedAt).timestamp().not_null().default(Expr::current_timestamp()))
+                    .col(ColumnDef::new(DataExports::CompletedAt).timestamp().null())
+                    .col(ColumnDef::new(DataExports::ExpiresAt).timestamp().null())
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk-data_exports-user")
+                            .from(DataExports::Table, DataExports::UserId)
+                            .to(Users::Table, Users::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx-data_exports-user_id")
+                    .table(DataExports::Table)
+                    .col(DataExports::UserId)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(DataExports::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(Iden)]
+enum DataExports {
+    Table,
+    Id,
+    UserId,
+    Status,
+    Archive,
+    DownloadTokenHash,
+    Error,
+    RequestedAt,
+    CompletedAt,
+    ExpiresAt,
+}
+
+#[derive(Iden)]
+enum Users {
+    Table,
+    Id,
+}
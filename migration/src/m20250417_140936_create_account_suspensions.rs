@@ -0,0 +1,91 @@
+use sea_orm_migration::prelude::*;
+use sea_orm::DbErr;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(AccountSuspensions::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(AccountSuspensions::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(AccountSuspensions::UserId).integer().not_null())
+                    .col(ColumnDef::new(AccountSuspensions::Status).string().not_null().default("active"))
+                    .col(ColumnDef::new(AccountSuspensions::Reason).text().not_null())
+                    .col(ColumnDef::new(AccountSuspensions::SuspendedBy).integer().null())
+                    .col(ColumnDef::new(AccountSuspensions::CreatedAt).timestamp().not_null().default(Expr::current_timestamp()))
+                    .col(ColumnDef::new(AccountSuspensions::ExpiresAt).timestamp().null())
+                    .col(ColumnDef::new(AccountSuspensions::LiftedBy).integer().null())
+                    .col(ColumnDef::new(AccountSuspensions::LiftedAt).timestamp().null())
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk-account_suspensions-user")
+                            .from(AccountSuspensions::Table, AccountSuspensions::UserId)
+                            .to(Users::Table, Users::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk-account_suspensions-suspended_by")
+                            .from(AccountSuspensions::Table, AccountSuspensions::SuspendedBy)
+                            .to(Users::Table, Users::Id)
+                            .on_delete(ForeignKeyAction::SetNull),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk-account_suspensions-lifted_by")
+                            .from(AccountSuspensions::Table, AccountSuspensions::LiftedBy)
+                            .to(Users::Table, Users::Id)
+                            .on_delete(ForeignKeyAction::SetNull),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx-account_suspensions-user_id")
+                    .table(AccountSuspensions::Table)
+                    .col(AccountSuspensions::UserId)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(AccountSuspensions::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(Iden)]
+enum AccountSuspensions {
+    Table,
+    Id,
+    UserId,
+    Status,
+    Reason,
+    SuspendedBy,
+    CreatedAt,
+    ExpiresAt,
+    LiftedBy,
+    LiftedAt,
+}
+
+#[derive(Iden)]
+enum Users {
+    Table,
+    Id,
+}
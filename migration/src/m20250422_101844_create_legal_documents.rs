@@ -0,0 +1,165 @@
+use sea_orm_migration::prelude::*;
+use sea_orm::DbErr;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(LegalDocuments::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(LegalDocuments::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(LegalDocuments::Kind).string().not_null())
+                    .col(ColumnDef::new(LegalDocuments::Version).string().not_null())
+                    .col(ColumnDef::new(LegalDocuments::Content).text().not_null())
+                    .col(ColumnDef::new(LegalDocuments::PublishedAt).timestamp().not_null())
+                    .col(ColumnDef::new(LegalDocuments::CreatedAt).timestamp().not_null().default(Expr::current_timestamp()))
+                    .index(
+                        Index::create()
+                            .name("idx-legal_documents-kind-version")
+                            .col(LegalDocuments::Kind)
+                            .col(LegalDocuments::Version)
+                            .unique(),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_table(
+                Table::create()
+                    .table(LegalAcceptances::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(LegalAcceptances::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(LegalAcceptances::UserId).integer().not_null())
+                    .col(ColumnDef::new(LegalAcceptances::DocumentId).integer().not_null())
+                    .col(ColumnDef::new(LegalAcceptances::IpAddress).string().null())
+                    .col(ColumnDef::new(LegalAcceptances::AcceptedAt).timestamp().not_null().default(Expr::current_timestamp()))
+                    .index(
+                        Index::create()
+                            .name("idx-legal_acceptances-user-document")
+                            .col(LegalAcceptances::UserId)
+                            .col(LegalAcceptances::DocumentId)
+                            .unique(),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk-legal_acceptances-user")
+                            .from(LegalAcceptances::Table, LegalAcceptances::UserId)
+                            .to(Users::Table, Users::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk-legal_acceptances-document")
+                            .from(LegalAcceptances::Table, LegalAcceptances::DocumentId)
+                            .to(LegalDocuments::Table, LegalDocuments::Id)
+                            .on_delete(ForeignKeyAction::Restrict),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_table(
+                Table::create()
+                    .table(MarketingConsents::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(MarketingConsents::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(MarketingConsents::UserId).integer().not_null())
+                    .col(ColumnDef::new(MarketingConsents::Granted).boolean().not_null())
+                    .col(ColumnDef::new(MarketingConsents::IpAddress).string().null())
+                    .col(ColumnDef::new(MarketingConsents::RecordedAt).timestamp().not_null().default(Expr::current_timestamp()))
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk-marketing_consents-user")
+                            .from(MarketingConsents::Table, MarketingConsents::UserId)
+                            .to(Users::Table, Users::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx-marketing_consents-user_id")
+                    .table(MarketingConsents::Table)
+                    .col(MarketingConsents::UserId)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(MarketingConsents::Table).to_owned())
+            .await?;
+        manager
+            .drop_table(Table::drop().table(LegalAcceptances::Table).to_owned())
+            .await?;
+        manager
+            .drop_table(Table::drop().table(LegalDocuments::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(Iden)]
+enum LegalDocuments {
+    Table,
+    Id,
+    Kind,
+    Version,
+    Content,
+    PublishedAt,
+    CreatedAt,
+}
+
+#[derive(Iden)]
+enum LegalAcceptances {
+    Table,
+    Id,
+    UserId,
+    DocumentId,
+    IpAddress,
+    AcceptedAt,
+}
+
+#[derive(Iden)]
+enum MarketingConsents {
+    Table,
+    Id,
+    UserId,
+    Granted,
+    IpAddress,
+    RecordedAt,
+}
+
+#[derive(Iden)]
+enum Users {
+    Table,
+    Id,
+}
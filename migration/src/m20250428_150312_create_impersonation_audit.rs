@@ -0,0 +1,123 @@
+use sea_orm_migration::prelude::*;
+use sea_orm::DbErr;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(ImpersonationSessions::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(ImpersonationSessions::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(ImpersonationSessions::TokenId).string().not_null().unique_key())
+                    .col(ColumnDef::new(ImpersonationSessions::ActorUserId).integer().null())
+                    .col(ColumnDef::new(ImpersonationSessions::TargetUserId).integer().null())
+                    .col(ColumnDef::new(ImpersonationSessions::Reason).text().not_null())
+                    .col(ColumnDef::new(ImpersonationSessions::CreatedAt).timestamp().not_null().default(Expr::current_timestamp()))
+                    .col(ColumnDef::new(ImpersonationSessions::ExpiresAt).timestamp().not_null())
+                    // The audit trail outlives both accounts.
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk-impersonation_sessions-actor")
+                            .from(ImpersonationSessions::Table, ImpersonationSessions::ActorUserId)
+                            .to(Users::Table, Users::Id)
+                            .on_delete(ForeignKeyAction::SetNull),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk-impersonation_sessions-target")
+                            .from(ImpersonationSessions::Table, ImpersonationSessions::TargetUserId)
+                            .to(Users::Table, Users::Id)
+                            .on_delete(ForeignKeyAction::SetNull),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_table(
+                Table::create()
+                    .table(ImpersonationAuditLogs::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(ImpersonationAuditLogs::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(ImpersonationAuditLogs::SessionId).integer().not_null())
+                    .col(ColumnDef::new(ImpersonationAuditLogs::Method).string().not_null())
+                    .col(ColumnDef::new(ImpersonationAuditLogs::Path).text().not_null())
+                    .col(ColumnDef::new(ImpersonationAuditLogs::Allowed).boolean().not_null())
+                    .col(ColumnDef::new(ImpersonationAuditLogs::CreatedAt).timestamp().not_null().default(Expr::current_timestamp()))
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk-impersonation_audit_logs-session")
+                            .from(ImpersonationAuditLogs::Table, ImpersonationAuditLogs::SessionId)
+                            .to(ImpersonationSessions::Table, ImpersonationSessions::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx-impersonation_audit_logs-session_id")
+                    .table(ImpersonationAuditLogs::Table)
+                    .col(ImpersonationAuditLogs::SessionId)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(ImpersonationAuditLogs::Table).to_owned())
+            .await?;
+        manager
+            .drop_table(Table::drop().table(ImpersonationSessions::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(Iden)]
+enum ImpersonationSessions {
+    Table,
+    Id,
+    TokenId,
+    ActorUserId,
+    TargetUserId,
+    Reason,
+    CreatedAt,
+    ExpiresAt,
+}
+
+#[derive(Iden)]
+enum ImpersonationAuditLogs {
+    Table,
+    Id,
+    SessionId,
+    Method,
+    Path,
+    Allowed,
+    CreatedAt,
+}
+
+#[derive(Iden)]
+enum Users {
+    Table,
+    Id,
+}
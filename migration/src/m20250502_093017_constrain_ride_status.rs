@@ -0,0 +1,50 @@
+use sea_orm_migration::prelude::*;
+use sea_orm::DbErr;
+use crate::util::ride_table;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let table = ride_table(manager).await?;
+        let db = manager.get_connection();
+
+        // Statuses used to be free text from the client. Map the spellings seen in the
+        // wild, then place anything else by its timestamps.
+        db.execute_unprepared(&format!(
+            "UPDATE {table} SET status = CASE
+                 WHEN lower(status) IN ('requested', 'pending', 'booked', 'scheduled') THEN 'requested'
+                 WHEN lower(status) IN ('accepted', 'confirmed', 'assigned') THEN 'accepted'
+                 WHEN lower(status) IN ('driver_arrived', 'arrived') THEN 'driver_arrived'
+                 WHEN lower(status) IN ('in_progress', 'ongoing', 'started') THEN 'in_progress'
+                 WHEN lower(status) IN ('completed', 'finished', 'done') THEN 'completed'
+                 WHEN lower(status) IN ('cancelled', 'canceled') THEN 'cancelled'
+                 WHEN end_time IS NOT NULL THEN 'completed'
+                 WHEN start_time IS NOT NULL THEN 'in_progress'
+                 ELSE 'requested'
+             END"
+        ))
+        .await?;
+
+        db.execute_unprepared(&format!(
+            "ALTER TABLE {table} ADD CONSTRAINT chk_ride_status CHECK (status IN
+                 ('requested', 'accepted', 'driver_arrived', 'in_progress', 'completed', 'cancelled'))"
+        ))
+        .await?;
+        db.execute_unprepared(&format!("ALTER TABLE {table} ALTER COLUMN status SET DEFAULT 'requested'"))
+            .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let table = ride_table(manager).await?;
+        let db = manager.get_connection();
+        db.execute_unprepared(&format!("ALTER TABLE {table} ALTER COLUMN status DROP DEFAULT"))
+            .await?;
+        db.execute_unprepared(&format!("ALTER TABLE {table} DROP CONSTRAINT IF EXISTS chk_ride_status"))
+            .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,69 @@
+use sea_orm_migration::prelude::*;
+use sea_orm::DbErr;
+use crate::util::ride_table;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let rides = ride_table(manager).await?;
+        manager
+            .create_table(
+                Table::create()
+                    .table(RideFares::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(RideFares::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(RideFares::RideId).integer().not_null().unique_key())
+                    .col(ColumnDef::new(RideFares::VehicleId).integer().not_null())
+                    .col(ColumnDef::new(RideFares::BaseFare).decimal_len(10, 2).not_null())
+                    .col(ColumnDef::new(RideFares::PerKilometerRate).decimal_len(10, 2).not_null())
+                    .col(ColumnDef::new(RideFares::PerMinuteRate).decimal_len(10, 2).not_null())
+                    .col(ColumnDef::new(RideFares::DistanceKm).decimal_len(10, 2).not_null())
+                    .col(ColumnDef::new(RideFares::DurationMinutes).decimal_len(10, 2).not_null())
+                    .col(ColumnDef::new(RideFares::DistanceFare).decimal_len(10, 2).not_null())
+                    .col(ColumnDef::new(RideFares::TimeFare).decimal_len(10, 2).not_null())
+                    .col(ColumnDef::new(RideFares::Total).decimal_len(10, 2).not_null())
+                    .col(ColumnDef::new(RideFares::CalculatedAt).timestamp().not_null().default(Expr::current_timestamp()))
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk-ride_fares-ride")
+                            .from(RideFares::Table, RideFares::RideId)
+                            .to(Alias::new(rides), Alias::new("id"))
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(RideFares::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(Iden)]
+enum RideFares {
+    Table,
+    Id,
+    RideId,
+    VehicleId,
+    BaseFare,
+    PerKilometerRate,
+    PerMinuteRate,
+    DistanceKm,
+    DurationMinutes,
+    DistanceFare,
+    TimeFare,
+    Total,
+    CalculatedAt,
+}
@@ -0,0 +1,36 @@
+use sea_orm_migration::prelude::*;
+use sea_orm::DbErr;
+use crate::util::ride_table;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let rides = ride_table(manager).await?;
+        if manager.has_column(rides, "quoted_fare").await? {
+            return Ok(());
+        }
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Alias::new(rides))
+                    .add_column(ColumnDef::new(Alias::new("quoted_fare")).decimal_len(10, 2).null())
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Alias::new(ride_table(manager).await?))
+                    .drop_column(Alias::new("quoted_fare"))
+                    .to_owned(),
+            )
+            .await
+    }
+}
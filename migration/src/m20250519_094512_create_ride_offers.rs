@@ -0,0 +1,137 @@
+use sea_orm_migration::prelude::*;
+use sea_orm::DbErr;
+use crate::util::ride_table;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let table = ride_table(manager).await?;
+        let db = manager.get_connection();
+
+        // A ride has no driver until one accepts its offer.
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Alias::new(table))
+                    .modify_column(ColumnDef::new(Alias::new("driver_id")).integer().null())
+                    .modify_column(ColumnDef::new(Alias::new("vehicle_id")).integer().null())
+                    .to_owned(),
+            )
+            .await?;
+
+        db.execute_unprepared(&format!("ALTER TABLE {table} DROP CONSTRAINT IF EXISTS chk_ride_status"))
+            .await?;
+        db.execute_unprepared(&format!(
+            "ALTER TABLE {table} ADD CONSTRAINT chk_ride_status CHECK (status IN
+                 ('requested', 'accepted', 'driver_arrived', 'in_progress', 'completed', 'cancelled', 'no_drivers'))"
+        ))
+        .await?;
+
+        manager
+            .create_table(
+                Table::create()
+                    .table(RideOffers::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(RideOffers::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(RideOffers::RideId).integer().not_null())
+                    .col(ColumnDef::new(RideOffers::DriverId).integer().not_null())
+                    .col(ColumnDef::new(RideOffers::VehicleId).integer().not_null())
+                    .col(ColumnDef::new(RideOffers::Status).string().not_null().default("pending"))
+                    .col(ColumnDef::new(RideOffers::PickupDistanceKm).double().not_null())
+                    .col(ColumnDef::new(RideOffers::DeclineReason).string().null())
+                    .col(ColumnDef::new(RideOffers::OfferedAt).timestamp().not_null().default(Expr::current_timestamp()))
+                    .col(ColumnDef::new(RideOffers::ExpiresAt).timestamp().not_null())
+                    .col(ColumnDef::new(RideOffers::RespondedAt).timestamp().null())
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk-ride_offers-ride")
+                            .from(RideOffers::Table, RideOffers::RideId)
+                            .to(Alias::new(table), Alias::new("id"))
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk-ride_offers-driver")
+                            .from(RideOffers::Table, RideOffers::DriverId)
+                            .to(Drivers::Table, Drivers::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        // Each driver is offered a given ride at most once.
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx-ride_offers-ride_id-driver_id")
+                    .table(RideOffers::Table)
+                    .col(RideOffers::RideId)
+                    .col(RideOffers::DriverId)
+                    .unique()
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx-ride_offers-status-expires_at")
+                    .table(RideOffers::Table)
+                    .col(RideOffers::Status)
+                    .col(RideOffers::ExpiresAt)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let table = ride_table(manager).await?;
+        let db = manager.get_connection();
+
+        manager
+            .drop_table(Table::drop().table(RideOffers::Table).to_owned())
+            .await?;
+
+        db.execute_unprepared(&format!("UPDATE {table} SET status = 'cancelled' WHERE status = 'no_drivers'"))
+            .await?;
+        db.execute_unprepared(&format!("ALTER TABLE {table} DROP CONSTRAINT IF EXISTS chk_ride_status"))
+            .await?;
+        db.execute_unprepared(&format!(
+            "ALTER TABLE {table} ADD CONSTRAINT chk_ride_status CHECK (status IN
+                 ('requested', 'accepted', 'driver_arrived', 'in_progress', 'completed', 'cancelled'))"
+        ))
+        .await?;
+        Ok(())
+    }
+}
+
+#[derive(Iden)]
+enum RideOffers {
+    Table,
+    Id,
+    RideId,
+    DriverId,
+    VehicleId,
+    Status,
+    PickupDistanceKm,
+    DeclineReason,
+    OfferedAt,
+    ExpiresAt,
+    RespondedAt,
+}
+
+#[derive(Iden)]
+enum Drivers {
+    Table,
+    Id,
+}
@@ -0,0 +1,42 @@
+use sea_orm_migration::prelude::*;
+use sea_orm::DbErr;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // `current_lat`/`current_lng` default to (0, 0); dispatch needs to know whether
+        // the driver has ever reported a real position.
+        if !manager.has_column("drivers", "location_updated_at").await? {
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(Drivers::Table)
+                        .add_column(ColumnDef::new(Drivers::LocationUpdatedAt).timestamp().null())
+                        .to_owned(),
+                )
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Drivers::Table)
+                    .drop_column(Drivers::LocationUpdatedAt)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(Iden)]
+enum Drivers {
+    Table,
+    LocationUpdatedAt,
+}
@@ -0,0 +1,49 @@
+use sea_orm_migration::prelude::*;
+use sea_orm::DbErr;
+use crate::util::ride_table;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let rides = ride_table(manager).await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Alias::new(rides))
+                    .add_column(ColumnDef::new(Alias::new("quote_id")).string().null())
+                    .to_owned(),
+            )
+            .await?;
+
+        // A quote can be booked once; the index also settles two bookings racing on it.
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx-ride-quote_id")
+                    .table(Alias::new(rides))
+                    .col(Alias::new("quote_id"))
+                    .unique()
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let rides = ride_table(manager).await?;
+        manager
+            .drop_index(Index::drop().name("idx-ride-quote_id").table(Alias::new(rides)).to_owned())
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Alias::new(rides))
+                    .drop_column(Alias::new("quote_id"))
+                    .to_owned(),
+            )
+            .await
+    }
+}
@@ -0,0 +1,11 @@
+use sea_orm_migration::prelude::*;
+use sea_orm::DbErr;
+
+/// Rides live in `ride` on databases that predate the `rides` migration; use whichever exists.
+pub(crate) async fn ride_table(manager: &SchemaManager<'_>) -> Result<&'static str, DbErr> {
+    if manager.has_table("ride").await? {
+        Ok("ride")
+    } else {
+        Ok("rides")
+    }
+}
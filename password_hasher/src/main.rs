@@ -1,8 +1,29 @@
-use bcrypt::{hash, DEFAULT_COST};
+// Shares the API's hashing module so hashes made here match what login expects.
+#[path = "../../src/password.rs"]
+#[allow(dead_code)]
+mod password;
+
+use std::env;
 
 fn main() {
-    let password = "newpassword"; // Change this to your desired password
-    let hashed_password = hash(password, DEFAULT_COST).unwrap();
-    println!("Hashed password: {}", hashed_password);
-}
+    let plain = match env::args().nth(1) {
+        Some(plain) => plain,
+        None => {
+            eprintln!("Usage: password_hasher <password>");
+            std::process::exit(1);
+        }
+    };
 
+    if let Err(e) = password::init_params() {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+
+    match password::hash_password(&plain) {
+        Ok(hashed_password) => println!("Hashed password: {}", hashed_password),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,168 @@
+//! Account deletion. A request schedules the deletion; once the grace period has
+//! passed, `purge_user` removes the account in a single transaction.
+
+use chrono::{Duration, Utc};
+use log::{error, info};
+use sea_orm::sea_query::Expr;
+use sea_orm::{ColumnTrait, Condition, DatabaseConnection, DbErr, EntityTrait, PaginatorTrait, QueryFilter, TransactionTrait};
+use std::env;
+use std::sync::Arc;
+use crate::entities::helpsupport;
+use crate::entities::{driverentity, payment, recentlocation, rideentity, rideoffer, settings, userentity, userprofile};
+use crate::offers;
+use crate::rides;
+
+const DEFAULT_GRACE_DAYS: i64 = 14;
+const PURGE_INTERVAL_SECONDS: u64 = 60 * 60;
+const REDACTED: &str = "[deleted]";
+
+/// Days between a deletion request and the purge, from `ACCOUNT_DELETION_GRACE_DAYS`.
+pub fn deletion_grace_period() -> Duration {
+    let days = env::var("ACCOUNT_DELETION_GRACE_DAYS")
+        .ok()
+        .and_then(|days| days.parse().ok())
+        .unwrap_or(DEFAULT_GRACE_DAYS);
+    Duration::days(days)
+}
+
+/// Deletes the user's personal rows and the account itself, and anonymizes their rides
+/// and, for a driver, their driver profile.
+///
+/// Rides keep their amounts, times and status for financial records; the rider link,
+/// review and addresses are removed and coordinates are rounded to about a kilometre.
+/// Does nothing if the deletion was cancelled or is not yet due, and waits for a later
+/// pass while the user has a ride under way or a driver offer outstanding.
+pub async fn purge_user(db: &DatabaseConnection, user_id: i32) -> Result<bool, DbErr> {
+    let txn = db.begin().await?;
+    let now = Utc::now().naive_utc();
+
+    let due = userentity::Entity::find_by_id(user_id)
+        .filter(userentity::Column::DeletionScheduledFor.lte(now))
+        .one(&txn)
+        .await?;
+    if due.is_none() {
+        return Ok(false);
+    }
+
+    let driver = driverentity::Entity::find()
+        .filter(driverentity::Column::UserId.eq(user_id))
+        .one(&txn)
+        .await?;
+    let mut involved = Condition::any().add(rideentity::Column::UserId.eq(user_id));
+    if let Some(driver) = &driver {
+        involved = involved.add(rideentity::Column::DriverId.eq(driver.id));
+    }
+    let active_rides = rideentity::Entity::find()
+        .filter(involved)
+        .filter(rideentity::Column::Status.is_in(rides::ACTIVE_STATUSES))
+        .count(&txn)
+        .await?;
+    let pending_offers = match &driver {
+        Some(driver) => {
+            rideoffer::Entity::find()
+                .filter(rideoffer::Column::DriverId.eq(driver.id))
+                .filter(offers::outstanding())
+                .count(&txn)
+                .await?
+        }
+        None => 0,
+    };
+    if active_rides > 0 || pending_offers > 0 {
+        info!("Deferring purge of account {}: a ride is still under way", user_id);
+        return Ok(false);
+    }
+
+    settings::Entity::delete_many()
+        .filter(settings::Column::UserId.eq(user_id))
+        .exec(&txn)
+        .await?;
+    userprofile::Entity::delete_many()
+        .filter(userprofile::Column::UserId.eq(user_id))
+        .exec(&txn)
+        .await?;
+    recentlocation::Entity::delete_many()
+        .filter(recentlocation::Column::UserId.eq(user_id))
+        .exec(&txn)
+        .await?;
+    helpsupport::Entity::delete_many()
+        .filter(helpsupport::Column::UserId.eq(user_id))
+        .exec(&txn)
+        .await?;
+    payment::Entity::delete_many()
+        .filter(payment::Column::UserId.eq(user_id))
+        .exec(&txn)
+        .await?;
+
+    rideentity::Entity::update_many()
+        .col_expr(rideentity::Column::UserId, Expr::value(Option::<i32>::None))
+        .col_expr(rideentity::Column::Review, Expr::value(Option::<String>::None))
+        .col_expr(rideentity::Column::PickupLocation, Expr::value(REDACTED))
+        .col_expr(rideentity::Column::DropoffLocation, Expr::value(REDACTED))
+        .col_expr(rideentity::Column::PickupLat, Expr::cust("ROUND(pickup_lat::numeric, 2)::double precision"))
+        .col_expr(rideentity::Column::PickupLng, Expr::cust("ROUND(pickup_lng::numeric, 2)::double precision"))
+        .col_expr(rideentity::Column::DropoffLat, Expr::cust("ROUND(dropoff_lat::numeric, 2)::double precision"))
+        .col_expr(rideentity::Column::DropoffLng, Expr::cust("ROUND(dropoff_lng::numeric, 2)::double precision"))
+        .filter(rideentity::Column::UserId.eq(user_id))
+        .exec(&txn)
+        .await?;
+
+    // The driver row stays for the rides that reference it, stripped of personal data
+    // and taken offline. Emails are unique, so each gets its own placeholder.
+    if let Some(driver) = &driver {
+        driverentity::Entity::update_many()
+            .col_expr(driverentity::Column::FirstName, Expr::value(REDACTED))
+            .col_expr(driverentity::Column::LastName, Expr::value(REDACTED))
+            .col_expr(driverentity::Column::Email, Expr::value(format!("deleted-driver-{}@invalid", driver.id)))
+            .col_expr(driverentity::Column::Phone, Expr::value(REDACTED))
+            .col_expr(driverentity::Column::PhoneVerifiedAt, Expr::value(Option::<chrono::NaiveDateTime>::None))
+            .col_expr(driverentity::Column::Photo, Expr::value(""))
+            .col_expr(driverentity::Column::AboutMe, Expr::value(""))
+            .col_expr(driverentity::Column::FromLocation, Expr::value(REDACTED))
+            .col_expr(driverentity::Column::LicenseNumber, Expr::value(REDACTED))
+            .col_expr(driverentity::Column::CurrentLat, Expr::value(0.0))
+            .col_expr(driverentity::Column::CurrentLng, Expr::value(0.0))
+            .col_expr(driverentity::Column::LocationUpdatedAt, Expr::value(Option::<chrono::NaiveDateTime>::None))
+            .col_expr(driverentity::Column::AvailabilityStatus, Expr::value("offline"))
+            .col_expr(driverentity::Column::UpdatedAt, Expr::value(now))
+            .filter(driverentity::Column::Id.eq(driver.id))
+            .exec(&txn)
+            .await?;
+    }
+
+    // Tokens, sessions and other auth rows cascade with the user.
+    userentity::Entity::delete_by_id(user_id).exec(&txn).await?;
+    txn.commit().await?;
+
+    info!("Purged account {}", user_id);
+    Ok(true)
+}
+
+/// Purges every account whose grace period has ended.
+pub async fn purge_due_deletions(db: &DatabaseConnection) -> Result<usize, DbErr> {
+    let due = userentity::Entity::find()
+        .filter(userentity::Column::DeletionScheduledFor.lte(Utc::now().naive_utc()))
+        .all(db)
+        .await?;
+
+    let mut purged = 0;
+    for user in due {
+        if purge_user(db, user.id).await? {
+            purged += 1;
+        }
+    }
+    Ok(purged)
+}
+
+/// Background loop that runs `purge_due_deletions` every hour.
+pub async fn run_deletion_purge(db: Arc<DatabaseConnection>) {
+    let db = db.as_ref();
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(PURGE_INTERVAL_SECONDS));
+    loop {
+        interval.tick().await;
+        match purge_due_deletions(db).await {
+            Ok(0) => {}
+            Ok(purged) => info!("Purged {} account(s) past their deletion grace period", purged),
+            Err(e) => error!("Account deletion purge failed: {}", e),
+        }
+    }
+}
@@ -1,19 +1,226 @@
-use jsonwebtoken::{encode, decode, EncodingKey, DecodingKey, Header, Validation};
+use jsonwebtoken::{encode, decode, decode_header, Algorithm, EncodingKey, DecodingKey, Header, Validation};
 use chrono::{Utc, Duration};
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+use serde::de::DeserializeOwned;
 use serde::{Serialize, Deserialize};
-use jsonwebtoken::errors::Error;
+use jsonwebtoken::errors::{Error, ErrorKind};
+use std::collections::HashMap;
+use std::env;
+use std::str::FromStr;
+use std::sync::OnceLock;
 
-const SECRET_KEY: &[u8] = b"your_secret_key";  
+pub const ACCESS_TOKEN_MINUTES: i64 = 7;
+pub const REFRESH_TOKEN_MINUTES: i64 = 15 * 24 * 60;
+/// Lifetime of the token that bridges the password step and the second-factor step of a login.
+pub const MFA_PENDING_TOKEN_MINUTES: i64 = 5;
+/// Lifetime of a read-only token an admin uses to see the app as another user.
+pub const IMPERSONATION_TOKEN_MINUTES: i64 = 15;
 
-#[derive(Debug, Serialize, Deserialize)]
+const DEFAULT_ISSUER: &str = "arrively";
+const DEFAULT_AUDIENCE: &str = "arrively-api";
+
+/// Account roles. Stored on `users.role` and carried in every token.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    #[default]
+    Rider,
+    Driver,
+    SupportAgent,
+    Admin,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Rider => "rider",
+            Role::Driver => "driver",
+            Role::SupportAgent => "support_agent",
+            Role::Admin => "admin",
+        }
+    }
+}
+
+impl FromStr for Role {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "rider" => Ok(Role::Rider),
+            "driver" => Ok(Role::Driver),
+            "support_agent" => Ok(Role::SupportAgent),
+            "admin" => Ok(Role::Admin),
+            other => Err(format!("Unknown role '{}'", other)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AuthTokenClaims {  
     pub sub: String, 
     pub exp: usize,  
+    pub iat: usize,
+    pub iss: String,
+    pub aud: String,
     pub token_type: String, 
+    pub jti: String,
+    pub role: Role,
+    /// Whether the session was established with a second factor.
+    #[serde(default)]
+    pub mfa: bool,
+    /// Set on impersonation tokens: the admin acting as `sub` (RFC 8693 actor claim).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub act: Option<ActorClaim>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ActorClaim {
+    pub sub: String,
+}
+
+struct VerificationKey {
+    algorithm: Algorithm,
+    key: DecodingKey,
 }
 
-fn generate_token(email: &str, expiry_minutes: i64, token_type: &str, secret: &[u8]) -> Result<String, Error> {
-    let expiration = Utc::now()
+/// Signing and verification keys loaded from the environment at startup.
+///
+/// `JWT_KEY_IDS` lists every key id that is still accepted for validation and
+/// `JWT_SIGNING_KEY_ID` picks the one new tokens are signed with (the first id
+/// by default). Each key is described by `JWT_KEY_<ID>_ALGORITHM` (`HS256`,
+/// `RS256` or `EdDSA`) plus either `JWT_KEY_<ID>_SECRET` for HS256 or
+/// `JWT_KEY_<ID>_PUBLIC_KEY` / `JWT_KEY_<ID>_PRIVATE_KEY` PEM file paths for
+/// the asymmetric algorithms. Only the signing key needs a private key, so a
+/// retired key can stay listed until every token signed with it has expired.
+pub struct JwtKeys {
+    signing_kid: String,
+    signing_algorithm: Algorithm,
+    signing_key: EncodingKey,
+    verification_keys: HashMap<String, VerificationKey>,
+    issuer: String,
+    audience: String,
+}
+
+static JWT_KEYS: OnceLock<JwtKeys> = OnceLock::new();
+
+fn key_env_var(kid: &str, suffix: &str) -> String {
+    let normalized: String = kid
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' })
+        .collect();
+    format!("JWT_KEY_{}_{}", normalized, suffix)
+}
+
+fn read_key_file(kid: &str, suffix: &str) -> Result<Vec<u8>, String> {
+    let var = key_env_var(kid, suffix);
+    let path = env::var(&var).map_err(|_| format!("{} must be set for key '{}'", var, kid))?;
+    std::fs::read(&path).map_err(|e| format!("Failed to read {} ({}): {}", var, path, e))
+}
+
+fn parse_algorithm(kid: &str) -> Result<Algorithm, String> {
+    let var = key_env_var(kid, "ALGORITHM");
+    match env::var(&var).unwrap_or_else(|_| "HS256".to_string()).as_str() {
+        "HS256" => Ok(Algorithm::HS256),
+        "RS256" => Ok(Algorithm::RS256),
+        "EdDSA" => Ok(Algorithm::EdDSA),
+        other => Err(format!("Unsupported algorithm '{}' in {}", other, var)),
+    }
+}
+
+fn load_decoding_key(kid: &str, algorithm: Algorithm) -> Result<DecodingKey, String> {
+    match algorithm {
+        Algorithm::HS256 => {
+            let var = key_env_var(kid, "SECRET");
+            let secret = env::var(&var).map_err(|_| format!("{} must be set for key '{}'", var, kid))?;
+            Ok(DecodingKey::from_secret(secret.as_bytes()))
+        }
+        Algorithm::RS256 => DecodingKey::from_rsa_pem(&read_key_file(kid, "PUBLIC_KEY")?)
+            .map_err(|e| format!("Invalid RSA public key for '{}': {}", kid, e)),
+        Algorithm::EdDSA => DecodingKey::from_ed_pem(&read_key_file(kid, "PUBLIC_KEY")?)
+            .map_err(|e| format!("Invalid EdDSA public key for '{}': {}", kid, e)),
+        _ => Err(format!("Unsupported algorithm for key '{}'", kid)),
+    }
+}
+
+fn load_encoding_key(kid: &str, algorithm: Algorithm) -> Result<EncodingKey, String> {
+    match algorithm {
+        Algorithm::HS256 => {
+            let var = key_env_var(kid, "SECRET");
+            let secret = env::var(&var).map_err(|_| format!("{} must be set for key '{}'", var, kid))?;
+            Ok(EncodingKey::from_secret(secret.as_bytes()))
+        }
+        Algorithm::RS256 => EncodingKey::from_rsa_pem(&read_key_file(kid, "PRIVATE_KEY")?)
+            .map_err(|e| format!("Invalid RSA private key for '{}': {}", kid, e)),
+        Algorithm::EdDSA => EncodingKey::from_ed_pem(&read_key_file(kid, "PRIVATE_KEY")?)
+            .map_err(|e| format!("Invalid EdDSA private key for '{}': {}", kid, e)),
+        _ => Err(format!("Unsupported algorithm for key '{}'", kid)),
+    }
+}
+
+impl JwtKeys {
+    pub fn from_env() -> Result<Self, String> {
+        let kids: Vec<String> = env::var("JWT_KEY_IDS")
+            .map_err(|_| "JWT_KEY_IDS must be set".to_string())?
+            .split(',')
+            .map(|kid| kid.trim().to_string())
+            .filter(|kid| !kid.is_empty())
+            .collect();
+
+        if kids.is_empty() {
+            return Err("JWT_KEY_IDS must list at least one key id".to_string());
+        }
+
+        let signing_kid = env::var("JWT_SIGNING_KEY_ID").unwrap_or_else(|_| kids[0].clone());
+        if !kids.contains(&signing_kid) {
+            return Err(format!("Signing key '{}' is not listed in JWT_KEY_IDS", signing_kid));
+        }
+
+        let mut verification_keys = HashMap::new();
+        for kid in &kids {
+            let algorithm = parse_algorithm(kid)?;
+            let key = load_decoding_key(kid, algorithm)?;
+            verification_keys.insert(kid.clone(), VerificationKey { algorithm, key });
+        }
+
+        let signing_algorithm = verification_keys[&signing_kid].algorithm;
+        let signing_key = load_encoding_key(&signing_kid, signing_algorithm)?;
+
+        Ok(JwtKeys {
+            signing_kid,
+            signing_algorithm,
+            signing_key,
+            verification_keys,
+            issuer: env::var("JWT_ISSUER").unwrap_or_else(|_| DEFAULT_ISSUER.to_string()),
+            audience: env::var("JWT_AUDIENCE").unwrap_or_else(|_| DEFAULT_AUDIENCE.to_string()),
+        })
+    }
+}
+
+/// Loads the JWT key set from the environment. Must run once before any token is issued.
+pub fn init_keys() -> Result<(), String> {
+    let keys = JwtKeys::from_env()?;
+    JWT_KEYS
+        .set(keys)
+        .map_err(|_| "JWT keys are already initialised".to_string())
+}
+
+fn keys() -> &'static JwtKeys {
+    JWT_KEYS.get().expect("auth::init_keys must be called before issuing or validating tokens")
+}
+
+fn generate_token(
+    email: &str,
+    role: Role,
+    mfa: bool,
+    expiry_minutes: i64,
+    token_type: &str,
+    jti: &str,
+    act: Option<ActorClaim>,
+) -> Result<String, Error> {
+    let keys = keys();
+    let now = Utc::now();
+    let expiration = now
         .checked_add_signed(Duration::minutes(expiry_minutes))
         .expect("valid timestamp")
         .timestamp() as usize;
@@ -21,31 +228,119 @@ fn generate_token(email: &str, expiry_minutes: i64, token_type: &str, secret: &[
     let claims = AuthTokenClaims {  
         sub: email.to_owned(),
         exp: expiration,
+        iat: now.timestamp() as usize,
+        iss: keys.issuer.clone(),
+        aud: keys.audience.clone(),
         token_type: token_type.to_string(),
+        jti: jti.to_string(),
+        role,
+        mfa,
+        act,
     };
 
-    encode(&Header::default(), &claims, &EncodingKey::from_secret(secret))
+    sign_claims(&claims)
+}
+
+/// The `iss` every token we sign carries.
+pub fn token_issuer() -> String {
+    keys().issuer.clone()
+}
+
+/// Signs `claims` with the current signing key, naming it in the `kid` header.
+///
+/// Also used for signed payloads that are not auth tokens, such as fare quotes. Those
+/// must set their own `aud` so they can never pass as an auth token.
+pub fn sign_claims<T: Serialize>(claims: &T) -> Result<String, Error> {
+    let keys = keys();
+    let mut header = Header::new(keys.signing_algorithm);
+    header.kid = Some(keys.signing_kid.clone());
+
+    encode(&header, claims, &keys.signing_key)
+}
+
+/// Verifies a token from `sign_claims` against the key named by its `kid`, plus
+/// expiry, our issuer and `audience`.
+pub fn verify_claims<T: DeserializeOwned>(token: &str, audience: &str) -> Result<T, Error> {
+    let keys = keys();
+    let header = decode_header(token)?;
+    let kid = header.kid.ok_or(ErrorKind::InvalidToken)?;
+    let verification_key = keys.verification_keys.get(&kid).ok_or(ErrorKind::InvalidToken)?;
+
+    let mut validation = Validation::new(verification_key.algorithm);
+    validation.set_issuer(&[&keys.issuer]);
+    validation.set_audience(&[audience]);
+    validation.set_required_spec_claims(&["exp", "iss", "aud"]);
+
+    Ok(decode::<T>(token, &verification_key.key, &validation)?.claims)
+}
+
+fn random_hex(len: usize) -> String {
+    let mut bytes = vec![0u8; len];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Returns a random 128-bit identifier, hex encoded, for use as a token `jti`.
+pub fn generate_token_id() -> String {
+    random_hex(16)
+}
+
+/// Returns a random 256-bit opaque token for links sent to users (password reset and the like).
+/// Only its `hash_token` digest should be persisted.
+pub fn generate_secret_token() -> String {
+    random_hex(32)
+}
+
+/// SHA-256 digest, hex encoded, used to store single-use secrets.
+pub fn hash_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+pub fn generate_access_token(email: &str, role: Role, mfa: bool) -> Result<String, Error> {
+    generate_token(email, role, mfa, ACCESS_TOKEN_MINUTES, "access", &generate_token_id(), None) // 7 minutes expiry
 }
 
-#[allow(dead_code)] // Suppress warning for unused function
-pub fn generate_access_token(email: &str) -> Result<String, Error> {
-    generate_token(email, 7, "access", SECRET_KEY) // 7 minutes expiry
+/// The caller supplies the `jti` so it can be persisted alongside the token.
+pub fn generate_refresh_token(email: &str, role: Role, mfa: bool, jti: &str) -> Result<String, Error> {
+    generate_token(email, role, mfa, REFRESH_TOKEN_MINUTES, "refresh", jti, None) // 15 days expiry
 }
 
-#[allow(dead_code)] // Suppress warning for unused function
-pub fn generate_refresh_token(email: &str) -> Result<String, Error> {
-    generate_token(email, 15 * 24 * 60, "refresh", SECRET_KEY) // 15 days expiry
+/// Issued after a correct password when a second factor is still required.
+/// It is not accepted as an access token.
+pub fn generate_mfa_pending_token(email: &str, role: Role) -> Result<String, Error> {
+    generate_token(email, role, false, MFA_PENDING_TOKEN_MINUTES, "mfa_pending", &generate_token_id(), None)
+}
+
+/// An access token for `email` carrying `actor_email` as its `act` claim. The caller
+/// supplies the `jti` so the impersonation can be looked up and audited.
+pub fn generate_impersonation_token(email: &str, role: Role, actor_email: &str, jti: &str) -> Result<String, Error> {
+    let actor = ActorClaim {
+        sub: actor_email.to_string(),
+    };
+    generate_token(email, role, false, IMPERSONATION_TOKEN_MINUTES, "access", jti, Some(actor))
 }
 
 impl AuthTokenClaims {
-    #[allow(dead_code)] // Suppress warning for unused function
+    /// Validates the signature against the key named by the token's `kid`, plus expiry, issuer and audience.
     pub fn validate_token(token: &str) -> Result<Self, Error> {
-        let token_data = decode::<AuthTokenClaims>(
-            token,
-            &DecodingKey::from_secret(SECRET_KEY),  
-            &Validation::default(),
-        )?;
+        verify_claims(token, &keys().audience)
+    }
+
+    /// Validates the token and additionally requires it to be a refresh token.
+    pub fn validate_refresh_token(token: &str) -> Result<Self, Error> {
+        Self::validate_token_of_type(token, "refresh")
+    }
+
+    /// Validates the token and additionally requires it to be an `mfa_pending` token.
+    pub fn validate_mfa_pending_token(token: &str) -> Result<Self, Error> {
+        Self::validate_token_of_type(token, "mfa_pending")
+    }
 
-        Ok(token_data.claims)
+    fn validate_token_of_type(token: &str, token_type: &str) -> Result<Self, Error> {
+        let claims = Self::validate_token(token)?;
+        if claims.token_type != token_type {
+            return Err(ErrorKind::InvalidToken.into());
+        }
+        Ok(claims)
     }
 }
@@ -1,20 +1,41 @@
-use actix_web::{delete, get, post,put, web, HttpRequest, HttpResponse, Responder};
+use actix_web::{delete, get, post,put, web, HttpRequest, HttpResponse, Responder, ResponseError};
 use regex::Regex;
-use sea_orm::{ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, Set };
-use bcrypt::{hash, DEFAULT_COST};
+use sea_orm::{ActiveEnum, ActiveModelTrait, ColumnTrait, ConnectionTrait, DatabaseConnection, DbErr, EntityTrait, PaginatorTrait, QueryFilter, QueryOrder, Set, SqlErr, TransactionTrait };
+use sea_orm::sea_query::Expr;
+use crate::accounts;
+use crate::exports;
+use crate::legal;
+use crate::suspension;
+use crate::password;
 use serde::{Deserialize, Serialize};
-use crate::auth::AuthTokenClaims;
+use crate::auth::{generate_access_token, generate_impersonation_token, generate_mfa_pending_token, generate_refresh_token, generate_secret_token, generate_token_id, hash_token, AuthTokenClaims, Role, IMPERSONATION_TOKEN_MINUTES, REFRESH_TOKEN_MINUTES};
 use crate::entities::userentity::{self, Entity as UserEntity};
-use crate::entities::{self, driverentity, vehicleentity};
+use crate::entities::{accountsuspension, dataexport, impersonationauditlog, impersonationsession, legalacceptance, legaldocument, emailverificationtoken, mfarecoverycode, mfarequiredrole, oidcloginstate, passwordresettoken, phoneverification, refreshtoken, useridentity, usersession, usertotp};
+use crate::sms::SmsSender;
+use crate::throttle;
+use crate::totp;
+use crate::oidc::{IdTokenClaims, OidcClient};
+use rand::Rng;
+use crate::mailer::{app_base_url, Email, Mailer};
+use crate::middleware::{authenticate_if_present, AuthError, AuthenticatedUser, RequireRole};
+use crate::entities::{self, driverentity, rideoffer, vehicleentity};
 use crate::db::establish_connection_pool;
 use serde_json::json;
 //use chrono::Utc;
 //use crate::entities::payment::{ActiveModel as PaymentActiveModel, Entity as PaymentEntity};
-use crate::entities::rideentity::{self, Entity as RideEntity};
+use crate::entities::rideentity::{self, Entity as RideEntity, RideStatus};
+use crate::rides::{self, RideAction};
+use crate::fares;
+use crate::geo;
+use crate::quotes;
+use crate::dispatch;
+use crate::drivers;
+use crate::offers;
+use crate::routing::{Coordinates, RoutingProvider};
 use rust_decimal::Decimal;
 use chrono::{DateTime as ChronoDateTime, Utc};
 use crate::entities::settings::{self};
-use log::{error, info};
+use log::{error, info, warn};
 use crate::entities::helpsupport::NewTicketRequest;
 
 use actix_web::Error;
@@ -32,6 +53,11 @@ pub struct NewUser {
     pub password: String,
     pub city: i32,
     pub phone_number: String,
+    /// Ids of the current legal documents the user accepted; all of them are required.
+    #[serde(default)]
+    pub accepted_documents: Vec<i32>,
+    #[serde(default)]
+    pub marketing_opt_in: bool,
 }
 
 fn is_valid_email(email: &str) -> bool {
@@ -48,123 +74,2302 @@ fn validate_phone(phone: &str) -> Result<(), String> {
     }
 }
 
+const EMAIL_VERIFICATION_TOKEN_HOURS: i64 = 24;
+
+/// Issues a fresh verification token for `user` and emails them the link.
+/// Earlier unused tokens are invalidated so only the latest link works.
+async fn send_verification_email(
+    db: &DatabaseConnection,
+    mailer: &dyn Mailer,
+    user: &userentity::Model,
+) -> Result<(), Error> {
+    let now = Utc::now().naive_utc();
+
+    emailverificationtoken::Entity::update_many()
+        .col_expr(emailverificationtoken::Column::UsedAt, Expr::value(now))
+        .filter(emailverificationtoken::Column::UserId.eq(user.id))
+        .filter(emailverificationtoken::Column::UsedAt.is_null())
+        .exec(db)
+        .await
+        .map_err(database_error)?;
+
+    let token = generate_secret_token();
+    let verification_token = emailverificationtoken::ActiveModel {
+        user_id: Set(user.id),
+        token_hash: Set(hash_token(&token)),
+        expires_at: Set(now + chrono::Duration::hours(EMAIL_VERIFICATION_TOKEN_HOURS)),
+        used_at: Set(None),
+        created_at: Set(now),
+        ..Default::default()
+    };
+    verification_token.insert(db).await.map_err(database_error)?;
+
+    let email = Email {
+        to: user.email.clone(),
+        subject: "Confirm your Arrively email address".to_string(),
+        body: format!(
+            "Hi {},\n\nPlease confirm your email address by opening the link below. It expires in {} hours.\n\n{}/v1/auth/verify-email?token={}",
+            user.first_name, EMAIL_VERIFICATION_TOKEN_HOURS, app_base_url(), token
+        ),
+    };
+    if let Err(e) = mailer.send(&email).await {
+        error!("Failed to send verification email to user {}: {}", user.id, e);
+    }
+
+    Ok(())
+}
+
+/// Rejects a sign-up that has not accepted every current legal document, listing the
+/// ones still missing.
+fn unaccepted_documents(required: &[legaldocument::Model], accepted: &[i32]) -> Option<HttpResponse> {
+    let missing: Vec<_> = required
+        .iter()
+        .filter(|document| !accepted.contains(&document.id))
+        .map(|document| serde_json::json!({
+            "id": document.id,
+            "kind": document.kind,
+            "version": document.version
+        }))
+        .collect();
+    if missing.is_empty() {
+        return None;
+    }
+    Some(HttpResponse::BadRequest().json(serde_json::json!({
+        "error": "You must accept the terms of service and privacy policy",
+        "documents": missing
+    })))
+}
+
+/// Records the legal acceptances and marketing choice made while signing up.
+async fn record_signup_consents<C: ConnectionTrait>(
+    conn: &C,
+    user_id: i32,
+    required: &[legaldocument::Model],
+    marketing_opt_in: bool,
+    ip_address: &str,
+) -> Result<(), Error> {
+    let document_ids: Vec<i32> = required.iter().map(|document| document.id).collect();
+    legal::record_acceptances(conn, user_id, &document_ids, ip_address)
+        .await
+        .map_err(database_error)?;
+    if marketing_opt_in {
+        legal::record_marketing_consent(conn, user_id, true, ip_address)
+            .await
+            .map_err(database_error)?;
+    }
+    Ok(())
+}
+
 #[post("/users/register")]
 async fn register_user(
+    req: HttpRequest,
     new_user: web::Json<NewUser>,
     db: web::Data<DatabaseConnection>,
+    mailer: web::Data<dyn Mailer>,
+) -> Result<HttpResponse, Error> {
+    if !is_valid_email(&new_user.email) {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "Incorrect email format"
+        })));
+    }
+
+    if validate_phone(&new_user.phone_number).is_err() {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "Invalid phone number format"
+        })));
+    }
+
+    let existing_email = UserEntity::find()
+        .filter(userentity::Column::Email.eq(new_user.email.clone()))
+        .one(db.as_ref())
+        .await
+        .map_err(|e| {
+            eprintln!("Database query error: {:?}", e);
+            actix_web::error::ErrorInternalServerError("Database error")
+        })?;
+
+    if existing_email.is_some() {
+        return Ok(HttpResponse::Conflict().json(serde_json::json!({
+            "error": "Email already exists"
+        })));
+    }
+
+    let existing_phone = UserEntity::find()
+        .filter(userentity::Column::PhoneNumber.eq(new_user.phone_number.clone()))
+        .one(db.as_ref())
+        .await
+        .map_err(|e| {
+            eprintln!("Database query error: {:?}", e);
+            actix_web::error::ErrorInternalServerError("Database error")
+        })?;
+
+    if existing_phone.is_some() {
+        return Ok(HttpResponse::Conflict().json(serde_json::json!({
+            "error": "Phone number already exists"
+        })));
+    }
+
+    if let Err(message) = password::check_policy(&new_user.password, &new_user.email) {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({ "error": message })));
+    }
+
+    let required = legal::current_documents(db.as_ref()).await.map_err(database_error)?;
+    if let Some(rejection) = unaccepted_documents(&required, &new_user.accepted_documents) {
+        return Ok(rejection);
+    }
+
+    let password_hash = hash_new_password(&new_user.password)?;
+
+    let new_user_active_model = userentity::ActiveModel {
+        first_name: Set(new_user.first_name.clone()),
+        last_name: Set(new_user.last_name.clone()),
+        email: Set(new_user.email.clone()),
+        password: Set(password_hash),
+        city: Set(new_user.city),
+        phone_number: Set(Some(new_user.phone_number.clone())),
+        role: Set(Role::Rider.as_str().to_string()),
+        ..Default::default()
+    };
+
+    let ip_address = throttle::client_ip(&req);
+    let txn = db.begin().await.map_err(database_error)?;
+    let inserted = match new_user_active_model.insert(&txn).await {
+        Ok(user) => {
+            record_signup_consents(&txn, user.id, &required, new_user.marketing_opt_in, &ip_address).await?;
+            txn.commit().await.map_err(database_error)?;
+            Ok(user)
+        }
+        Err(e) => Err(e),
+    };
+
+    match inserted {
+        Ok(user) => {
+            eprintln!("User successfully inserted into database");
+            send_verification_email(db.as_ref(), mailer.get_ref(), &user).await?;
+            Ok(HttpResponse::Created().json(serde_json::json!({ 
+                "message": "User registered successfully. Check your email to verify your address."
+            })))
+        }
+        Err(e) => {
+            eprintln!("Database insertion error: {:?}", e);
+            Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Error registering user"
+            })))
+        }
+    }
+}
+
+fn hash_new_password(plain: &str) -> Result<String, Error> {
+    password::hash_password(plain).map_err(|e| {
+        eprintln!("Password hashing error: {}", e);
+        actix_web::error::ErrorInternalServerError("Failed to hash password")
+    })
+}
+
+//user login
+
+
+#[derive(Deserialize)]
+pub struct LoginRequest {
+    pub email: String,
+    pub password: String,
+    pub device: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct RefreshTokenRequest {
+    pub refresh_token: String,
+}
+
+/// Where a login or refresh came from, recorded on the session.
+struct ClientInfo {
+    device: Option<String>,
+    user_agent: Option<String>,
+    ip_address: String,
+}
+
+impl ClientInfo {
+    fn from_request(req: &HttpRequest, device: Option<String>) -> Self {
+        let user_agent = req
+            .headers()
+            .get(actix_web::http::header::USER_AGENT)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.chars().take(512).collect());
+
+        ClientInfo {
+            device,
+            user_agent,
+            ip_address: throttle::client_ip(req),
+        }
+    }
+}
+
+fn database_error(e: DbErr) -> Error {
+    eprintln!("Database error: {:?}", e);
+    actix_web::error::ErrorInternalServerError("Database error")
+}
+
+fn invalid_refresh_token() -> HttpResponse {
+    HttpResponse::Unauthorized().json(serde_json::json!({
+        "error": "Invalid refresh token"
+    }))
+}
+
+/// Mints a refresh token for `user` in `session` and records it in the token store.
+async fn store_refresh_token<C: ConnectionTrait>(
+    conn: &C,
+    user: &userentity::Model,
+    role: Role,
+    mfa: bool,
+    jti: &str,
+    session: &usersession::Model,
+) -> Result<String, Error> {
+    let refresh_token = generate_refresh_token(&user.email, role, mfa, jti).map_err(|e| {
+        eprintln!("Token generation error: {:?}", e);
+        actix_web::error::ErrorInternalServerError("Failed to generate token")
+    })?;
+
+    let now = Utc::now().naive_utc();
+    let stored_token = refreshtoken::ActiveModel {
+        jti: Set(jti.to_string()),
+        user_id: Set(user.id),
+        family_id: Set(session.family_id.clone()),
+        session_id: Set(session.id),
+        device: Set(session.device.clone()),
+        expires_at: Set(now + chrono::Duration::minutes(REFRESH_TOKEN_MINUTES)),
+        revoked_at: Set(None),
+        replaced_by: Set(None),
+        created_at: Set(now),
+        ..Default::default()
+    };
+    stored_token.insert(conn).await.map_err(database_error)?;
+
+    Ok(refresh_token)
+}
+
+/// Unknown values in `users.role` fall back to the least privileged role.
+fn user_role(user: &userentity::Model) -> Role {
+    user.role.parse().unwrap_or_default()
+}
+
+/// The role a session is issued with. Driver sessions need a linked driver profile;
+/// every other session gets the role stored on the account.
+async fn session_role<C: ConnectionTrait>(
+    conn: &C,
+    user: &userentity::Model,
+    requested: Role,
+) -> Result<Role, Error> {
+    let account_role = user_role(user);
+    if requested != Role::Driver && account_role != Role::Driver {
+        return Ok(account_role);
+    }
+
+    let driver = driverentity::Entity::find()
+        .filter(driverentity::Column::UserId.eq(user.id))
+        .one(conn)
+        .await
+        .map_err(database_error)?;
+
+    Ok(match driver {
+        Some(_) => Role::Driver,
+        None if account_role == Role::Driver => Role::Rider,
+        None => account_role,
+    })
+}
+
+fn token_pair(user: &userentity::Model, role: Role, mfa: bool, refresh_token: &str) -> Result<serde_json::Value, Error> {
+    let access_token = generate_access_token(&user.email, role, mfa).map_err(|e| {
+        eprintln!("Token generation error: {:?}", e);
+        actix_web::error::ErrorInternalServerError("Failed to generate token")
+    })?;
+
+    Ok(serde_json::json!({
+        "access_token": access_token,
+        "refresh_token": refresh_token,
+        "token_type": "Bearer"
+    }))
+}
+
+/// Ends one login session: revokes every live token in its refresh-token family.
+async fn revoke_refresh_token_family<C: ConnectionTrait>(conn: &C, family_id: &str) -> Result<u64, DbErr> {
+    let now = Utc::now().naive_utc();
+    usersession::Entity::update_many()
+        .col_expr(usersession::Column::RevokedAt, Expr::value(now))
+        .filter(usersession::Column::FamilyId.eq(family_id))
+        .filter(usersession::Column::RevokedAt.is_null())
+        .exec(conn)
+        .await?;
+
+    let result = refreshtoken::Entity::update_many()
+        .col_expr(refreshtoken::Column::RevokedAt, Expr::value(now))
+        .filter(refreshtoken::Column::FamilyId.eq(family_id))
+        .filter(refreshtoken::Column::RevokedAt.is_null())
+        .exec(conn)
+        .await?;
+    Ok(result.rows_affected)
+}
+
+/// Ends every session of the user and revokes all of their live refresh tokens.
+async fn revoke_user_refresh_tokens<C: ConnectionTrait>(conn: &C, user_id: i32) -> Result<u64, DbErr> {
+    let now = Utc::now().naive_utc();
+    usersession::Entity::update_many()
+        .col_expr(usersession::Column::RevokedAt, Expr::value(now))
+        .filter(usersession::Column::UserId.eq(user_id))
+        .filter(usersession::Column::RevokedAt.is_null())
+        .exec(conn)
+        .await?;
+
+    let result = refreshtoken::Entity::update_many()
+        .col_expr(refreshtoken::Column::RevokedAt, Expr::value(now))
+        .filter(refreshtoken::Column::UserId.eq(user_id))
+        .filter(refreshtoken::Column::RevokedAt.is_null())
+        .exec(conn)
+        .await?;
+    Ok(result.rows_affected)
+}
+
+/// Looks up the stored row for a presented refresh token, if the JWT itself is valid.
+async fn find_stored_refresh_token<C: ConnectionTrait>(
+    conn: &C,
+    token: &str,
+) -> Result<Option<(refreshtoken::Model, AuthTokenClaims)>, Error> {
+    let claims = match AuthTokenClaims::validate_refresh_token(token) {
+        Ok(claims) => claims,
+        Err(_) => return Ok(None),
+    };
+
+    let stored = refreshtoken::Entity::find()
+        .filter(refreshtoken::Column::Jti.eq(claims.jti.clone()))
+        .one(conn)
+        .await
+        .map_err(database_error)?;
+
+    Ok(stored.map(|stored| (stored, claims)))
+}
+
+fn invalid_credentials() -> HttpResponse {
+    HttpResponse::Unauthorized().json(serde_json::json!({
+        "error": "Invalid email or password"
+    }))
+}
+
+fn too_many_login_attempts(retry_after: i64) -> HttpResponse {
+    HttpResponse::TooManyRequests()
+        .insert_header(("Retry-After", retry_after.to_string()))
+        .json(serde_json::json!({
+            "error": "Too many failed login attempts. Try again later.",
+            "retry_after_seconds": retry_after
+        }))
+}
+
+/// Checks `email`/`password` behind the login throttle. Failures count against both
+/// the account and the client IP; a successful login clears the account's counter.
+async fn authenticate_login(
+    db: &DatabaseConnection,
+    req: &HttpRequest,
+    email: &str,
+    password: &str,
+) -> Result<Result<userentity::Model, HttpResponse>, Error> {
+    let account_key = throttle::account_key(email);
+    let ip_key = throttle::ip_key(&throttle::client_ip(req));
+
+    if let Some(wait) = throttle::retry_after(db, &[account_key.clone(), ip_key.clone()])
+        .await
+        .map_err(database_error)?
+    {
+        return Ok(Err(too_many_login_attempts(wait)));
+    }
+
+    let user = UserEntity::find()
+        .filter(userentity::Column::Email.eq(email))
+        .one(db)
+        .await
+        .map_err(database_error)?;
+
+    let password_matches = match &user {
+        Some(user) => password::verify_password(password, &user.password).map_err(|e| {
+            eprintln!("Password verification error: {}", e);
+            actix_web::error::ErrorInternalServerError("Failed to verify password")
+        })?,
+        None => {
+            password::verify_dummy_password(password);
+            false
+        }
+    };
+
+    match user {
+        Some(user) if password_matches => {
+            throttle::clear(db, &account_key).await.map_err(database_error)?;
+            if password::needs_rehash(&user.password) {
+                return Ok(Ok(rehash_password(db, user, password).await?));
+            }
+            Ok(Ok(user))
+        }
+        user => {
+            let user_id = user.map(|user| user.id);
+            throttle::record_failure(db, &account_key, user_id, throttle::MAX_ACCOUNT_FAILURES)
+                .await
+                .map_err(database_error)?;
+            throttle::record_failure(db, &ip_key, None, throttle::MAX_IP_FAILURES)
+                .await
+                .map_err(database_error)?;
+            Ok(Err(invalid_credentials()))
+        }
+    }
+}
+
+/// Replaces a legacy or outdated hash with one using the current Argon2id parameters.
+/// Only called right after `plain` has been verified against it.
+async fn rehash_password(
+    db: &DatabaseConnection,
+    user: userentity::Model,
+    plain: &str,
+) -> Result<userentity::Model, Error> {
+    let user_id = user.id;
+    let mut active_user: userentity::ActiveModel = user.into();
+    active_user.password = Set(hash_new_password(plain)?);
+    let updated = active_user.update(db).await.map_err(database_error)?;
+    info!("Upgraded password hash for user {}", user_id);
+    Ok(updated)
+}
+
+/// Starts a new login session for the client and returns its token pair.
+async fn start_session(
+    db: &DatabaseConnection,
+    user: &userentity::Model,
+    role: Role,
+    mfa: bool,
+    client: ClientInfo,
+) -> Result<serde_json::Value, Error> {
+    // Every login starts a new token family; its id is the first token's jti.
+    let jti = generate_token_id();
+    let now = Utc::now().naive_utc();
+
+    let txn = db.begin().await.map_err(database_error)?;
+    let session = usersession::ActiveModel {
+        user_id: Set(user.id),
+        family_id: Set(jti.clone()),
+        device: Set(client.device),
+        user_agent: Set(client.user_agent),
+        ip_address: Set(Some(client.ip_address)),
+        created_at: Set(now),
+        last_seen_at: Set(now),
+        revoked_at: Set(None),
+        ..Default::default()
+    }
+    .insert(&txn)
+    .await
+    .map_err(database_error)?;
+    let refresh_token = store_refresh_token(&txn, user, role, mfa, &jti, &session).await?;
+    txn.commit().await.map_err(database_error)?;
+
+    token_pair(user, role, mfa, &refresh_token)
+}
+
+/// Finishes a login whose password was verified. Accounts with 2FA enabled, or whose
+/// role requires it, get a short-lived `mfa_pending` token instead of a session.
+async fn complete_login(
+    db: &DatabaseConnection,
+    user: &userentity::Model,
+    requested_role: Role,
+    client: ClientInfo,
+) -> Result<HttpResponse, Error> {
+    if let Some(suspended) = suspension::active_suspension(db, user.id).await.map_err(database_error)? {
+        return Ok(HttpResponse::Forbidden().json(serde_json::json!({
+            "error": "Account suspended",
+            "suspended_until": suspended.expires_at
+        })));
+    }
+
+    let role = session_role(db, user, requested_role).await?;
+    let totp_enabled = enabled_totp(db, user.id).await?.is_some();
+
+    if totp_enabled || mfa_required_for(db, role).await? {
+        let mfa_token = generate_mfa_pending_token(&user.email, role).map_err(|e| {
+            eprintln!("Token generation error: {:?}", e);
+            actix_web::error::ErrorInternalServerError("Failed to generate token")
+        })?;
+        return Ok(HttpResponse::Ok().json(serde_json::json!({
+            "mfa_required": true,
+            "enrollment_required": !totp_enabled,
+            "mfa_token": mfa_token
+        })));
+    }
+
+    Ok(HttpResponse::Ok().json(start_session(db, user, role, false, client).await?))
+}
+
+#[post("/auth/login")]
+async fn login_user(
+    req: HttpRequest,
+    credentials: web::Json<LoginRequest>,
+    db: web::Data<DatabaseConnection>,
+) -> Result<HttpResponse, Error> {
+    let user = match authenticate_login(db.as_ref(), &req, &credentials.email, &credentials.password).await? {
+        Ok(user) => user,
+        Err(rejection) => return Ok(rejection),
+    };
+
+    let client = ClientInfo::from_request(&req, credentials.device.clone());
+    complete_login(db.as_ref(), &user, user_role(&user), client).await
+}
+
+#[post("/auth/refresh")]
+async fn refresh_access_token(
+    req: HttpRequest,
+    payload: web::Json<RefreshTokenRequest>,
+    db: web::Data<DatabaseConnection>,
+) -> Result<HttpResponse, Error> {
+    let txn = db.begin().await.map_err(database_error)?;
+
+    let (stored, claims) = match find_stored_refresh_token(&txn, &payload.refresh_token).await? {
+        Some(found) => found,
+        None => return Ok(invalid_refresh_token()),
+    };
+
+    let now = Utc::now().naive_utc();
+    if stored.expires_at <= now {
+        return Ok(invalid_refresh_token());
+    }
+
+    let new_jti = generate_token_id();
+
+    // Mark the presented token as used. The `revoked_at IS NULL` guard makes this the
+    // single point where a token can be redeemed, so two concurrent refreshes cannot both win.
+    let rotated = refreshtoken::Entity::update_many()
+        .col_expr(refreshtoken::Column::RevokedAt, Expr::value(now))
+        .col_expr(refreshtoken::Column::ReplacedBy, Expr::value(new_jti.clone()))
+        .filter(refreshtoken::Column::Id.eq(stored.id))
+        .filter(refreshtoken::Column::RevokedAt.is_null())
+        .exec(&txn)
+        .await
+        .map_err(database_error)?;
+
+    if rotated.rows_affected == 0 {
+        // The token was already rotated or revoked: treat this as token theft.
+        let revoked = revoke_refresh_token_family(&txn, &stored.family_id)
+            .await
+            .map_err(database_error)?;
+        txn.commit().await.map_err(database_error)?;
+        warn!(
+            "Refresh token reuse detected for user {}; revoked {} token(s) in family {}",
+            stored.user_id, revoked, stored.family_id
+        );
+        return Ok(invalid_refresh_token());
+    }
+
+    let user = match UserEntity::find_by_id(stored.user_id)
+        .one(&txn)
+        .await
+        .map_err(database_error)?
+    {
+        Some(user) => user,
+        None => return Ok(invalid_refresh_token()),
+    };
+
+    // Suspended accounts cannot mint new access tokens. Returning before the commit leaves
+    // the presented token unrotated, so it works again once the suspension is lifted.
+    if let Some(suspended) = suspension::active_suspension(&txn, user.id).await.map_err(database_error)? {
+        return Ok(HttpResponse::Forbidden().json(serde_json::json!({
+            "error": "Account suspended",
+            "suspended_until": suspended.expires_at
+        })));
+    }
+
+    let role = session_role(&txn, &user, claims.role).await?;
+
+    // Sessions from before 2FA became mandatory for the role must sign in again.
+    if !claims.mfa && mfa_required_for(&txn, role).await? {
+        return Ok(HttpResponse::Unauthorized().json(serde_json::json!({
+            "error": "Two-factor authentication is required for your account. Sign in again."
+        })));
+    }
+
+    let session = match usersession::Entity::find_by_id(stored.session_id)
+        .one(&txn)
+        .await
+        .map_err(database_error)?
+    {
+        Some(session) if session.revoked_at.is_none() => session,
+        _ => return Ok(invalid_refresh_token()),
+    };
+
+    let client = ClientInfo::from_request(&req, None);
+    let mut active_session: usersession::ActiveModel = session.into();
+    active_session.last_seen_at = Set(now);
+    active_session.ip_address = Set(Some(client.ip_address));
+    if client.user_agent.is_some() {
+        active_session.user_agent = Set(client.user_agent);
+    }
+    let session = active_session.update(&txn).await.map_err(database_error)?;
+
+    let refresh_token = store_refresh_token(&txn, &user, role, claims.mfa, &new_jti, &session).await?;
+    txn.commit().await.map_err(database_error)?;
+
+    Ok(HttpResponse::Ok().json(token_pair(&user, role, claims.mfa, &refresh_token)?))
+}
+
+#[post("/auth/logout")]
+async fn logout_user(
+    payload: web::Json<RefreshTokenRequest>,
+    db: web::Data<DatabaseConnection>,
+) -> Result<HttpResponse, Error> {
+    let stored = match find_stored_refresh_token(db.as_ref(), &payload.refresh_token).await? {
+        Some((stored, _)) => stored,
+        None => return Ok(invalid_refresh_token()),
+    };
+
+    revoke_refresh_token_family(db.as_ref(), &stored.family_id)
+        .await
+        .map_err(database_error)?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "message": "Logged out successfully"
+    })))
+}
+
+#[post("/auth/logout-all")]
+async fn logout_all_sessions(
+    payload: web::Json<RefreshTokenRequest>,
+    db: web::Data<DatabaseConnection>,
+) -> Result<HttpResponse, Error> {
+    let stored = match find_stored_refresh_token(db.as_ref(), &payload.refresh_token).await? {
+        Some((stored, _)) if stored.revoked_at.is_none() => stored,
+        _ => return Ok(invalid_refresh_token()),
+    };
+
+    let revoked = revoke_user_refresh_tokens(db.as_ref(), stored.user_id)
+        .await
+        .map_err(database_error)?;
+    info!("Revoked {} refresh token(s) for user {}", revoked, stored.user_id);
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "message": "Logged out of all sessions",
+        "revoked_sessions": revoked
+    })))
+}
+
+//sessions
+
+
+async fn active_sessions_for(db: &DatabaseConnection, user_id: i32) -> Result<Vec<usersession::Model>, Error> {
+    usersession::Entity::find()
+        .filter(usersession::Column::UserId.eq(user_id))
+        .filter(usersession::Column::RevokedAt.is_null())
+        .order_by_desc(usersession::Column::LastSeenAt)
+        .all(db)
+        .await
+        .map_err(database_error)
+}
+
+#[get("/me/sessions")]
+async fn get_my_sessions(
+    auth: AuthenticatedUser,
+    db: web::Data<DatabaseConnection>,
+) -> Result<HttpResponse, Error> {
+    Ok(HttpResponse::Ok().json(active_sessions_for(db.as_ref(), auth.user.id).await?))
+}
+
+/// Signs one of the caller's devices out.
+#[delete("/me/sessions/{id}")]
+async fn revoke_my_session(
+    auth: AuthenticatedUser,
+    session_id: web::Path<i32>,
+    db: web::Data<DatabaseConnection>,
+) -> Result<HttpResponse, Error> {
+    let session = usersession::Entity::find_by_id(session_id.into_inner())
+        .filter(usersession::Column::UserId.eq(auth.user.id))
+        .filter(usersession::Column::RevokedAt.is_null())
+        .one(db.as_ref())
+        .await
+        .map_err(database_error)?;
+
+    let session = match session {
+        Some(session) => session,
+        None => return Ok(HttpResponse::NotFound().json(json!({"error": "Session not found"}))),
+    };
+
+    revoke_refresh_token_family(db.as_ref(), &session.family_id)
+        .await
+        .map_err(database_error)?;
+    info!("User {} revoked session {}", auth.user.id, session.id);
+
+    Ok(HttpResponse::Ok().json(json!({"message": "Session revoked"})))
+}
+
+#[get("/users/{id}/sessions", wrap = "RequireRole::any_of(&[Role::SupportAgent])")]
+async fn get_user_sessions(
+    user_id: web::Path<i32>,
+    db: web::Data<DatabaseConnection>,
+) -> Result<HttpResponse, Error> {
+    Ok(HttpResponse::Ok().json(active_sessions_for(db.as_ref(), user_id.into_inner()).await?))
+}
+
+//two-factor authentication
+
+
+/// A TOTP code or, in its place, one of the account's recovery codes.
+#[derive(Deserialize)]
+pub struct SecondFactor {
+    #[serde(default)]
+    pub code: Option<String>,
+    #[serde(default)]
+    pub recovery_code: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct MfaLoginRequest {
+    pub mfa_token: String,
+    #[serde(flatten)]
+    pub factor: SecondFactor,
+    pub device: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct MfaTokenRequest {
+    pub mfa_token: String,
+}
+
+#[derive(Deserialize)]
+pub struct ConfirmTotpRequest {
+    pub code: String,
+}
+
+#[derive(Deserialize)]
+pub struct MfaEnrollmentConfirmRequest {
+    pub mfa_token: String,
+    pub code: String,
+    pub device: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct MfaRequiredRolesRequest {
+    pub roles: Vec<String>,
+}
+
+async fn enabled_totp<C: ConnectionTrait>(conn: &C, user_id: i32) -> Result<Option<usertotp::Model>, Error> {
+    usertotp::Entity::find()
+        .filter(usertotp::Column::UserId.eq(user_id))
+        .filter(usertotp::Column::EnabledAt.is_not_null())
+        .one(conn)
+        .await
+        .map_err(database_error)
+}
+
+async fn mfa_required_for<C: ConnectionTrait>(conn: &C, role: Role) -> Result<bool, Error> {
+    let required = mfarequiredrole::Entity::find()
+        .filter(mfarequiredrole::Column::Role.eq(role.as_str()))
+        .one(conn)
+        .await
+        .map_err(database_error)?;
+    Ok(required.is_some())
+}
+
+fn invalid_mfa_token() -> HttpResponse {
+    HttpResponse::Unauthorized().json(json!({"error": "Invalid or expired MFA token"}))
+}
+
+/// Resolves the user behind an `mfa_pending` token.
+async fn mfa_pending_user(
+    db: &DatabaseConnection,
+    mfa_token: &str,
+) -> Result<Option<(userentity::Model, AuthTokenClaims)>, Error> {
+    let claims = match AuthTokenClaims::validate_mfa_pending_token(mfa_token) {
+        Ok(claims) => claims,
+        Err(_) => return Ok(None),
+    };
+
+    let user = UserEntity::find()
+        .filter(userentity::Column::Email.eq(claims.sub.clone()))
+        .one(db)
+        .await
+        .map_err(database_error)?;
+
+    Ok(user.map(|user| (user, claims)))
+}
+
+/// Accepts a TOTP code from the current window (once per time step) or an unused recovery code.
+async fn second_factor_matches(
+    db: &DatabaseConnection,
+    totp_row: &usertotp::Model,
+    factor: &SecondFactor,
+) -> Result<bool, Error> {
+    if let Some(code) = &factor.code {
+        let step = totp::verify(&totp_row.secret, code, totp_row.last_used_step).map_err(|e| {
+            eprintln!("TOTP verification error: {}", e);
+            actix_web::error::ErrorInternalServerError("Failed to verify code")
+        })?;
+        let step = match step {
+            Some(step) => step,
+            None => return Ok(false),
+        };
+
+        // Guarded so the same code cannot be redeemed twice concurrently.
+        let claimed = usertotp::Entity::update_many()
+            .col_expr(usertotp::Column::LastUsedStep, Expr::value(step))
+            .filter(usertotp::Column::Id.eq(totp_row.id))
+            .filter(
+                usertotp::Column::LastUsedStep
+                    .is_null()
+                    .or(usertotp::Column::LastUsedStep.lt(step)),
+            )
+            .exec(db)
+            .await
+            .map_err(database_error)?;
+        return Ok(claimed.rows_affected == 1);
+    }
+
+    if let Some(recovery_code) = &factor.recovery_code {
+        let code_hash = hash_token(&totp::normalize_recovery_code(recovery_code));
+        let consumed = mfarecoverycode::Entity::update_many()
+            .col_expr(mfarecoverycode::Column::UsedAt, Expr::value(Utc::now().naive_utc()))
+            .filter(mfarecoverycode::Column::UserId.eq(totp_row.user_id))
+            .filter(mfarecoverycode::Column::CodeHash.eq(code_hash))
+            .filter(mfarecoverycode::Column::UsedAt.is_null())
+            .exec(db)
+            .await
+            .map_err(database_error)?;
+        return Ok(consumed.rows_affected == 1);
+    }
+
+    Ok(false)
+}
+
+/// Checks a second factor behind the same throttle as passwords, keyed by user.
+/// Returns `Ok(Err(response))` when the factor is rejected.
+async fn check_second_factor(
+    db: &DatabaseConnection,
+    user: &userentity::Model,
+    totp_row: &usertotp::Model,
+    factor: &SecondFactor,
+) -> Result<Result<(), HttpResponse>, Error> {
+    let key = format!("mfa:{}", user.id);
+    if let Some(wait) = throttle::retry_after(db, std::slice::from_ref(&key))
+        .await
+        .map_err(database_error)?
+    {
+        return Ok(Err(too_many_login_attempts(wait)));
+    }
+
+    if second_factor_matches(db, totp_row, factor).await? {
+        throttle::clear(db, &key).await.map_err(database_error)?;
+        return Ok(Ok(()));
+    }
+
+    throttle::record_failure(db, &key, Some(user.id), throttle::MAX_ACCOUNT_FAILURES)
+        .await
+        .map_err(database_error)?;
+    Ok(Err(HttpResponse::Unauthorized().json(json!({
+        "error": "Invalid authentication code"
+    }))))
+}
+
+/// Replaces all of a user's recovery codes and returns the new plaintext codes.
+async fn replace_recovery_codes<C: ConnectionTrait>(conn: &C, user_id: i32) -> Result<Vec<String>, Error> {
+    mfarecoverycode::Entity::delete_many()
+        .filter(mfarecoverycode::Column::UserId.eq(user_id))
+        .exec(conn)
+        .await
+        .map_err(database_error)?;
+
+    let now = Utc::now().naive_utc();
+    let codes = totp::generate_recovery_codes();
+    let rows = codes.iter().map(|code| mfarecoverycode::ActiveModel {
+        user_id: Set(user_id),
+        code_hash: Set(hash_token(code)),
+        used_at: Set(None),
+        created_at: Set(now),
+        ..Default::default()
+    });
+    mfarecoverycode::Entity::insert_many(rows)
+        .exec(conn)
+        .await
+        .map_err(database_error)?;
+
+    Ok(codes)
+}
+
+/// Starts (or restarts) TOTP enrollment and returns the secret and provisioning URI.
+async fn begin_totp_enrollment(db: &DatabaseConnection, user: &userentity::Model) -> Result<HttpResponse, Error> {
+    if enabled_totp(db, user.id).await?.is_some() {
+        return Ok(HttpResponse::Conflict().json(json!({
+            "error": "Two-factor authentication is already enabled"
+        })));
+    }
+
+    let secret = totp::generate_secret();
+    let provisioning_uri = totp::provisioning_uri(&secret, &user.email).map_err(|e| {
+        eprintln!("TOTP provisioning error: {}", e);
+        actix_web::error::ErrorInternalServerError("Failed to start enrollment")
+    })?;
+
+    let txn = db.begin().await.map_err(database_error)?;
+    usertotp::Entity::delete_many()
+        .filter(usertotp::Column::UserId.eq(user.id))
+        .exec(&txn)
+        .await
+        .map_err(database_error)?;
+    usertotp::ActiveModel {
+        user_id: Set(user.id),
+        secret: Set(secret.clone()),
+        enabled_at: Set(None),
+        last_used_step: Set(None),
+        created_at: Set(Utc::now().naive_utc()),
+        ..Default::default()
+    }
+    .insert(&txn)
+    .await
+    .map_err(database_error)?;
+    txn.commit().await.map_err(database_error)?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "secret": secret,
+        "provisioning_uri": provisioning_uri
+    })))
+}
+
+/// Enables a pending enrollment once the user proves their authenticator works.
+/// Returns the new recovery codes, or `Ok(Err(response))` when the code is rejected.
+async fn confirm_totp_enrollment(
+    db: &DatabaseConnection,
+    user: &userentity::Model,
+    code: &str,
+) -> Result<Result<Vec<String>, HttpResponse>, Error> {
+    let pending = usertotp::Entity::find()
+        .filter(usertotp::Column::UserId.eq(user.id))
+        .filter(usertotp::Column::EnabledAt.is_null())
+        .one(db)
+        .await
+        .map_err(database_error)?;
+    let pending = match pending {
+        Some(pending) => pending,
+        None => {
+            return Ok(Err(HttpResponse::BadRequest().json(json!({
+                "error": "No two-factor enrollment in progress"
+            }))));
+        }
+    };
+
+    let factor = SecondFactor {
+        code: Some(code.to_string()),
+        recovery_code: None,
+    };
+    if let Err(rejection) = check_second_factor(db, user, &pending, &factor).await? {
+        return Ok(Err(rejection));
+    }
+
+    let txn = db.begin().await.map_err(database_error)?;
+    usertotp::Entity::update_many()
+        .col_expr(usertotp::Column::EnabledAt, Expr::value(Utc::now().naive_utc()))
+        .filter(usertotp::Column::Id.eq(pending.id))
+        .exec(&txn)
+        .await
+        .map_err(database_error)?;
+    let codes = replace_recovery_codes(&txn, user.id).await?;
+    txn.commit().await.map_err(database_error)?;
+    info!("Two-factor authentication enabled for user {}", user.id);
+
+    Ok(Ok(codes))
+}
+
+/// Second step of a login: exchanges an `mfa_pending` token and a code for a session.
+#[post("/auth/mfa/verify")]
+async fn verify_mfa_login(
+    req: HttpRequest,
+    payload: web::Json<MfaLoginRequest>,
+    db: web::Data<DatabaseConnection>,
+) -> Result<HttpResponse, Error> {
+    let (user, claims) = match mfa_pending_user(db.as_ref(), &payload.mfa_token).await? {
+        Some(found) => found,
+        None => return Ok(invalid_mfa_token()),
+    };
+
+    let totp_row = match enabled_totp(db.as_ref(), user.id).await? {
+        Some(totp_row) => totp_row,
+        None => {
+            return Ok(HttpResponse::BadRequest().json(json!({
+                "error": "Two-factor authentication is not set up for this account"
+            })));
+        }
+    };
+
+    if let Err(rejection) = check_second_factor(db.as_ref(), &user, &totp_row, &payload.factor).await? {
+        return Ok(rejection);
+    }
+
+    let role = session_role(db.as_ref(), &user, claims.role).await?;
+    let client = ClientInfo::from_request(&req, payload.device.clone());
+    Ok(HttpResponse::Ok().json(start_session(db.as_ref(), &user, role, true, client).await?))
+}
+
+/// Enrollment during login, for accounts whose role requires 2FA but that have none yet.
+#[post("/auth/mfa/enroll")]
+async fn begin_mfa_enrollment(
+    payload: web::Json<MfaTokenRequest>,
+    db: web::Data<DatabaseConnection>,
+) -> Result<HttpResponse, Error> {
+    match mfa_pending_user(db.as_ref(), &payload.mfa_token).await? {
+        Some((user, _)) => begin_totp_enrollment(db.as_ref(), &user).await,
+        None => Ok(invalid_mfa_token()),
+    }
+}
+
+#[post("/auth/mfa/enroll/confirm")]
+async fn confirm_mfa_enrollment(
+    req: HttpRequest,
+    payload: web::Json<MfaEnrollmentConfirmRequest>,
+    db: web::Data<DatabaseConnection>,
+) -> Result<HttpResponse, Error> {
+    let (user, claims) = match mfa_pending_user(db.as_ref(), &payload.mfa_token).await? {
+        Some(found) => found,
+        None => return Ok(invalid_mfa_token()),
+    };
+
+    let recovery_codes = match confirm_totp_enrollment(db.as_ref(), &user, &payload.code).await? {
+        Ok(codes) => codes,
+        Err(rejection) => return Ok(rejection),
+    };
+
+    let role = session_role(db.as_ref(), &user, claims.role).await?;
+    let client = ClientInfo::from_request(&req, payload.device.clone());
+    let mut session = start_session(db.as_ref(), &user, role, true, client).await?;
+    session["recovery_codes"] = json!(recovery_codes);
+    Ok(HttpResponse::Ok().json(session))
+}
+
+#[post("/me/mfa/totp")]
+async fn begin_my_totp_enrollment(
+    auth: AuthenticatedUser,
+    db: web::Data<DatabaseConnection>,
+) -> Result<HttpResponse, Error> {
+    begin_totp_enrollment(db.as_ref(), &auth.user).await
+}
+
+#[post("/me/mfa/totp/confirm")]
+async fn confirm_my_totp_enrollment(
+    auth: AuthenticatedUser,
+    payload: web::Json<ConfirmTotpRequest>,
+    db: web::Data<DatabaseConnection>,
+) -> Result<HttpResponse, Error> {
+    match confirm_totp_enrollment(db.as_ref(), &auth.user, &payload.code).await? {
+        Ok(recovery_codes) => Ok(HttpResponse::Ok().json(json!({
+            "message": "Two-factor authentication enabled. Store these recovery codes somewhere safe.",
+            "recovery_codes": recovery_codes
+        }))),
+        Err(rejection) => Ok(rejection),
+    }
+}
+
+#[delete("/me/mfa/totp")]
+async fn disable_my_totp(
+    auth: AuthenticatedUser,
+    payload: web::Json<SecondFactor>,
+    db: web::Data<DatabaseConnection>,
+) -> Result<HttpResponse, Error> {
+    if mfa_required_for(db.as_ref(), auth.role()).await? {
+        return Ok(HttpResponse::Conflict().json(json!({
+            "error": "Two-factor authentication is required for your role"
+        })));
+    }
+
+    let totp_row = match enabled_totp(db.as_ref(), auth.user.id).await? {
+        Some(totp_row) => totp_row,
+        None => return Ok(HttpResponse::NotFound().json(json!({"error": "Two-factor authentication is not enabled"}))),
+    };
+
+    if let Err(rejection) = check_second_factor(db.as_ref(), &auth.user, &totp_row, &payload).await? {
+        return Ok(rejection);
+    }
+
+    let txn = db.begin().await.map_err(database_error)?;
+    usertotp::Entity::delete_by_id(totp_row.id)
+        .exec(&txn)
+        .await
+        .map_err(database_error)?;
+    mfarecoverycode::Entity::delete_many()
+        .filter(mfarecoverycode::Column::UserId.eq(auth.user.id))
+        .exec(&txn)
+        .await
+        .map_err(database_error)?;
+    txn.commit().await.map_err(database_error)?;
+    info!("Two-factor authentication disabled for user {}", auth.user.id);
+
+    Ok(HttpResponse::Ok().json(json!({"message": "Two-factor authentication disabled"})))
+}
+
+#[post("/me/mfa/recovery-codes")]
+async fn regenerate_my_recovery_codes(
+    auth: AuthenticatedUser,
+    payload: web::Json<SecondFactor>,
+    db: web::Data<DatabaseConnection>,
+) -> Result<HttpResponse, Error> {
+    let totp_row = match enabled_totp(db.as_ref(), auth.user.id).await? {
+        Some(totp_row) => totp_row,
+        None => return Ok(HttpResponse::NotFound().json(json!({"error": "Two-factor authentication is not enabled"}))),
+    };
+
+    if let Err(rejection) = check_second_factor(db.as_ref(), &auth.user, &totp_row, &payload).await? {
+        return Ok(rejection);
+    }
+
+    let recovery_codes = replace_recovery_codes(db.as_ref(), auth.user.id).await?;
+    Ok(HttpResponse::Ok().json(json!({ "recovery_codes": recovery_codes })))
+}
+
+#[get("/admin/mfa/required-roles", wrap = "RequireRole::any_of(&[Role::Admin])")]
+async fn get_mfa_required_roles(db: web::Data<DatabaseConnection>) -> Result<HttpResponse, Error> {
+    let roles: Vec<String> = mfarequiredrole::Entity::find()
+        .all(db.as_ref())
+        .await
+        .map_err(database_error)?
+        .into_iter()
+        .map(|row| row.role)
+        .collect();
+    Ok(HttpResponse::Ok().json(json!({ "roles": roles })))
+}
+
+/// Replaces the set of roles that must use 2FA.
+#[put("/admin/mfa/required-roles", wrap = "RequireRole::any_of(&[Role::Admin])")]
+async fn set_mfa_required_roles(
+    auth: AuthenticatedUser,
+    payload: web::Json<MfaRequiredRolesRequest>,
+    db: web::Data<DatabaseConnection>,
+) -> Result<HttpResponse, Error> {
+    let mut roles = Vec::new();
+    for role in &payload.roles {
+        match role.parse::<Role>() {
+            Ok(role) if !roles.contains(&role) => roles.push(role),
+            Ok(_) => {}
+            Err(message) => return Ok(HttpResponse::BadRequest().json(json!({ "error": message }))),
+        }
+    }
+
+    let txn = db.begin().await.map_err(database_error)?;
+    mfarequiredrole::Entity::delete_many()
+        .exec(&txn)
+        .await
+        .map_err(database_error)?;
+    let now = Utc::now().naive_utc();
+    for role in &roles {
+        mfarequiredrole::ActiveModel {
+            role: Set(role.as_str().to_string()),
+            created_at: Set(now),
+            ..Default::default()
+        }
+        .insert(&txn)
+        .await
+        .map_err(database_error)?;
+    }
+    txn.commit().await.map_err(database_error)?;
+
+    let roles: Vec<&str> = roles.iter().map(|role| role.as_str()).collect();
+    info!("Admin {} set roles requiring 2FA to {:?}", auth.user.id, roles);
+    Ok(HttpResponse::Ok().json(json!({ "roles": roles })))
+}
+
+
+//social login (OpenID Connect)
+
+
+const OIDC_STATE_TTL_MINUTES: i64 = 10;
+
+#[derive(Deserialize)]
+pub struct OidcCallbackRequest {
+    pub code: String,
+    pub state: String,
+    pub device: Option<String>,
+    /// Only used when the sign-in creates a new account.
+    #[serde(default)]
+    pub city: Option<i32>,
+    /// Ids of the current legal documents accepted; required when the sign-in creates
+    /// a new account.
+    #[serde(default)]
+    pub accepted_documents: Vec<i32>,
+    #[serde(default)]
+    pub marketing_opt_in: bool,
+}
+
+fn unknown_identity_provider() -> HttpResponse {
+    HttpResponse::NotFound().json(json!({"error": "Unknown identity provider"}))
+}
+
+/// Starts a social login: returns the provider URL to redirect the user to.
+#[get("/auth/oidc/{provider}/authorize")]
+async fn oidc_authorize(
+    provider: web::Path<String>,
+    db: web::Data<DatabaseConnection>,
+    oidc: web::Data<OidcClient>,
+) -> Result<HttpResponse, Error> {
+    let provider = match oidc.provider(&provider) {
+        Some(provider) => provider,
+        None => return Ok(unknown_identity_provider()),
+    };
+
+    let state = generate_secret_token();
+    let nonce = generate_secret_token();
+    let code_verifier = generate_secret_token();
+    let authorization_url = oidc
+        .authorization_url(provider, &state, &nonce, &code_verifier)
+        .map_err(|e| {
+            eprintln!("OIDC configuration error: {}", e);
+            actix_web::error::ErrorInternalServerError("Identity provider is misconfigured")
+        })?;
+
+    let now = Utc::now().naive_utc();
+    oidcloginstate::ActiveModel {
+        state_hash: Set(hash_token(&state)),
+        provider: Set(provider.name.clone()),
+        nonce: Set(nonce),
+        code_verifier: Set(code_verifier),
+        expires_at: Set(now + chrono::Duration::minutes(OIDC_STATE_TTL_MINUTES)),
+        used_at: Set(None),
+        created_at: Set(now),
+        ..Default::default()
+    }
+    .insert(db.as_ref())
+    .await
+    .map_err(database_error)?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "authorization_url": authorization_url,
+        "state": state
+    })))
+}
+
+/// Finds the user for a validated ID token: an already linked identity, else an
+/// account with the same verified email (which gets linked), else a new account.
+/// Returns `Ok(Err(response))` when no account can be resolved.
+async fn resolve_oidc_user(
+    db: &DatabaseConnection,
+    provider: &str,
+    claims: &IdTokenClaims,
+    signup: &OidcCallbackRequest,
+    ip_address: &str,
+) -> Result<Result<userentity::Model, HttpResponse>, Error> {
+    let identity = useridentity::Entity::find()
+        .filter(useridentity::Column::Provider.eq(provider))
+        .filter(useridentity::Column::Subject.eq(claims.sub.clone()))
+        .one(db)
+        .await
+        .map_err(database_error)?;
+
+    if let Some(identity) = identity {
+        let user = UserEntity::find_by_id(identity.user_id)
+            .one(db)
+            .await
+            .map_err(database_error)?;
+        if let Some(user) = user {
+            return Ok(Ok(user));
+        }
+    }
+
+    let email = match claims.verified_email() {
+        Some(email) => email.to_string(),
+        None => {
+            return Ok(Err(HttpResponse::Forbidden().json(json!({
+                "error": "Your account with this provider has no verified email address"
+            }))));
+        }
+    };
+
+    let now = Utc::now().naive_utc();
+    let txn = db.begin().await.map_err(database_error)?;
+
+    let existing = UserEntity::find()
+        .filter(userentity::Column::Email.eq(email.clone()))
+        .one(&txn)
+        .await
+        .map_err(database_error)?;
+
+    let user = match existing {
+        Some(user) if user.email_verified_at.is_some() => user,
+        Some(_) => {
+            // Whoever registered this unverified account may not own the address; linking
+            // would hand them a verified account the provider's user signs in to.
+            return Ok(Err(HttpResponse::Conflict().json(json!({
+                "error": "An account with this email exists but has not been verified. Sign in with your password and verify your email first, or reset the password."
+            }))));
+        }
+        None => {
+            let city = match signup.city {
+                Some(city) => city,
+                None => {
+                    return Ok(Err(HttpResponse::BadRequest().json(json!({
+                        "error": "city is required to create an account"
+                    }))));
+                }
+            };
+            let required = legal::current_documents(&txn).await.map_err(database_error)?;
+            if let Some(rejection) = unaccepted_documents(&required, &signup.accepted_documents) {
+                return Ok(Err(rejection));
+            }
+
+            // Social accounts have no usable password until the user sets one via reset.
+            let user = userentity::ActiveModel {
+                first_name: Set(claims.given_name.clone().unwrap_or_default()),
+                last_name: Set(claims.family_name.clone().unwrap_or_default()),
+                email: Set(email.clone()),
+                password: Set(hash_new_password(&generate_secret_token())?),
+                city: Set(city),
+                phone_number: Set(None),
+                role: Set(Role::Rider.as_str().to_string()),
+                email_verified_at: Set(Some(now)),
+                ..Default::default()
+            }
+            .insert(&txn)
+            .await
+            .map_err(database_error)?;
+            record_signup_consents(&txn, user.id, &required, signup.marketing_opt_in, ip_address).await?;
+            info!("Created user {} from {} sign-in", user.id, provider);
+            user
+        }
+    };
+
+    useridentity::ActiveModel {
+        user_id: Set(user.id),
+        provider: Set(provider.to_string()),
+        subject: Set(claims.sub.clone()),
+        email: Set(Some(email)),
+        created_at: Set(now),
+        ..Default::default()
+    }
+    .insert(&txn)
+    .await
+    .map_err(database_error)?;
+    txn.commit().await.map_err(database_error)?;
+    info!("Linked {} identity to user {}", provider, user.id);
+
+    Ok(Ok(user))
+}
+
+/// Completes a social login: exchanges the code, validates the ID token and signs the
+/// user in with our own tokens.
+#[post("/auth/oidc/{provider}/callback")]
+async fn oidc_callback(
+    req: HttpRequest,
+    provider: web::Path<String>,
+    payload: web::Json<OidcCallbackRequest>,
+    db: web::Data<DatabaseConnection>,
+    oidc: web::Data<OidcClient>,
+) -> Result<HttpResponse, Error> {
+    let provider = match oidc.provider(&provider) {
+        Some(provider) => provider,
+        None => return Ok(unknown_identity_provider()),
+    };
+
+    let invalid_state = || HttpResponse::BadRequest().json(json!({"error": "Invalid or expired login state"}));
+    let now = Utc::now().naive_utc();
+
+    let login_state = match oidcloginstate::Entity::find()
+        .filter(oidcloginstate::Column::StateHash.eq(hash_token(&payload.state)))
+        .filter(oidcloginstate::Column::Provider.eq(provider.name.clone()))
+        .one(db.as_ref())
+        .await
+        .map_err(database_error)?
+    {
+        Some(login_state) if login_state.used_at.is_none() && login_state.expires_at > now => login_state,
+        _ => return Ok(invalid_state()),
+    };
+
+    let consumed = oidcloginstate::Entity::update_many()
+        .col_expr(oidcloginstate::Column::UsedAt, Expr::value(now))
+        .filter(oidcloginstate::Column::Id.eq(login_state.id))
+        .filter(oidcloginstate::Column::UsedAt.is_null())
+        .exec(db.as_ref())
+        .await
+        .map_err(database_error)?;
+    if consumed.rows_affected == 0 {
+        return Ok(invalid_state());
+    }
+
+    let claims = match oidc.exchange_code(provider, &payload.code, &login_state.code_verifier).await {
+        Ok(id_token) => oidc.validate_id_token(provider, &id_token, &login_state.nonce).await,
+        Err(e) => Err(e),
+    };
+    let claims = match claims {
+        Ok(claims) => claims,
+        Err(e) => {
+            warn!("{} sign-in rejected: {}", provider.name, e);
+            return Ok(HttpResponse::Unauthorized().json(json!({
+                "error": "Sign-in with the identity provider failed"
+            })));
+        }
+    };
+
+    let user = match resolve_oidc_user(db.as_ref(), &provider.name, &claims, &payload, &throttle::client_ip(&req)).await? {
+        Ok(user) => user,
+        Err(rejection) => return Ok(rejection),
+    };
+
+    let client = ClientInfo::from_request(&req, payload.device.clone());
+    complete_login(db.as_ref(), &user, user_role(&user), client).await
+}
+
+
+//email verification
+
+
+#[derive(Deserialize)]
+pub struct VerifyEmailQuery {
+    pub token: String,
+}
+
+#[get("/auth/verify-email")]
+async fn verify_email(
+    query: web::Query<VerifyEmailQuery>,
+    db: web::Data<DatabaseConnection>,
+) -> Result<HttpResponse, Error> {
+    let invalid_token = || {
+        HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "Invalid or expired verification token"
+        }))
+    };
+
+    let now = Utc::now().naive_utc();
+    let txn = db.begin().await.map_err(database_error)?;
+
+    let verification_token = match emailverificationtoken::Entity::find()
+        .filter(emailverificationtoken::Column::TokenHash.eq(hash_token(&query.token)))
+        .one(&txn)
+        .await
+        .map_err(database_error)?
+    {
+        Some(token) if token.used_at.is_none() && token.expires_at > now => token,
+        _ => return Ok(invalid_token()),
+    };
+
+    let consumed = emailverificationtoken::Entity::update_many()
+        .col_expr(emailverificationtoken::Column::UsedAt, Expr::value(now))
+        .filter(emailverificationtoken::Column::Id.eq(verification_token.id))
+        .filter(emailverificationtoken::Column::UsedAt.is_null())
+        .exec(&txn)
+        .await
+        .map_err(database_error)?;
+    if consumed.rows_affected == 0 {
+        return Ok(invalid_token());
+    }
+
+    UserEntity::update_many()
+        .col_expr(userentity::Column::EmailVerifiedAt, Expr::value(now))
+        .filter(userentity::Column::Id.eq(verification_token.user_id))
+        .filter(userentity::Column::EmailVerifiedAt.is_null())
+        .exec(&txn)
+        .await
+        .map_err(database_error)?;
+    txn.commit().await.map_err(database_error)?;
+    info!("Email verified for user {}", verification_token.user_id);
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "message": "Email address verified successfully"
+    })))
+}
+
+#[post("/auth/verify-email/resend")]
+async fn resend_verification_email(
+    auth: AuthenticatedUser,
+    db: web::Data<DatabaseConnection>,
+    mailer: web::Data<dyn Mailer>,
+) -> Result<HttpResponse, Error> {
+    if auth.user.email_verified_at.is_some() {
+        return Ok(HttpResponse::Conflict().json(serde_json::json!({
+            "error": "Email address is already verified"
+        })));
+    }
+
+    send_verification_email(db.as_ref(), mailer.get_ref(), &auth.user).await?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "message": "Verification email sent"
+    })))
+}
+
+//password reset
+
+
+const PASSWORD_RESET_TOKEN_MINUTES: i64 = 30;
+
+#[derive(Deserialize)]
+pub struct ForgotPasswordRequest {
+    pub email: String,
+}
+
+#[derive(Deserialize)]
+pub struct ResetPasswordRequest {
+    pub token: String,
+    pub new_password: String,
+}
+
+#[post("/auth/password/forgot")]
+async fn forgot_password(
+    payload: web::Json<ForgotPasswordRequest>,
+    db: web::Data<DatabaseConnection>,
+    mailer: web::Data<dyn Mailer>,
+) -> Result<HttpResponse, Error> {
+    // The response is identical whether or not the account exists, so this
+    // endpoint cannot be used to discover registered emails.
+    let response = HttpResponse::Ok().json(serde_json::json!({
+        "message": "If an account exists for that email, a reset link has been sent"
+    }));
+
+    let user = match UserEntity::find()
+        .filter(userentity::Column::Email.eq(payload.email.clone()))
+        .one(db.as_ref())
+        .await
+        .map_err(database_error)?
+    {
+        Some(user) => user,
+        None => return Ok(response),
+    };
+
+    let now = Utc::now().naive_utc();
+
+    // Only the most recently requested link stays usable.
+    passwordresettoken::Entity::update_many()
+        .col_expr(passwordresettoken::Column::UsedAt, Expr::value(now))
+        .filter(passwordresettoken::Column::UserId.eq(user.id))
+        .filter(passwordresettoken::Column::UsedAt.is_null())
+        .exec(db.as_ref())
+        .await
+        .map_err(database_error)?;
+
+    let token = generate_secret_token();
+    let reset_token = passwordresettoken::ActiveModel {
+        user_id: Set(user.id),
+        token_hash: Set(hash_token(&token)),
+        expires_at: Set(now + chrono::Duration::minutes(PASSWORD_RESET_TOKEN_MINUTES)),
+        used_at: Set(None),
+        created_at: Set(now),
+        ..Default::default()
+    };
+    reset_token.insert(db.as_ref()).await.map_err(database_error)?;
+
+    let email = Email {
+        to: user.email.clone(),
+        subject: "Reset your Arrively password".to_string(),
+        body: format!(
+            "Hi {},\n\nUse the link below to choose a new password. It expires in {} minutes.\n\n{}/reset-password?token={}\n\nIf you did not ask for this, you can ignore this email.",
+            user.first_name, PASSWORD_RESET_TOKEN_MINUTES, app_base_url(), token
+        ),
+    };
+    if let Err(e) = mailer.send(&email).await {
+        error!("Failed to send password reset email to user {}: {}", user.id, e);
+    }
+
+    Ok(response)
+}
+
+#[post("/auth/password/reset")]
+async fn reset_password(
+    payload: web::Json<ResetPasswordRequest>,
+    db: web::Data<DatabaseConnection>,
+) -> Result<HttpResponse, Error> {
+    let invalid_token = || {
+        HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "Invalid or expired reset token"
+        }))
+    };
+
+    let now = Utc::now().naive_utc();
+    let txn = db.begin().await.map_err(database_error)?;
+
+    let reset_token = match passwordresettoken::Entity::find()
+        .filter(passwordresettoken::Column::TokenHash.eq(hash_token(&payload.token)))
+        .one(&txn)
+        .await
+        .map_err(database_error)?
+    {
+        Some(reset_token) if reset_token.used_at.is_none() && reset_token.expires_at > now => reset_token,
+        _ => return Ok(invalid_token()),
+    };
+
+    // Consume the token; the guard makes a second concurrent reset with the same token fail.
+    let consumed = passwordresettoken::Entity::update_many()
+        .col_expr(passwordresettoken::Column::UsedAt, Expr::value(now))
+        .filter(passwordresettoken::Column::Id.eq(reset_token.id))
+        .filter(passwordresettoken::Column::UsedAt.is_null())
+        .exec(&txn)
+        .await
+        .map_err(database_error)?;
+    if consumed.rows_affected == 0 {
+        return Ok(invalid_token());
+    }
+
+    let user = match UserEntity::find_by_id(reset_token.user_id)
+        .one(&txn)
+        .await
+        .map_err(database_error)?
+    {
+        Some(user) => user,
+        None => return Ok(invalid_token()),
+    };
+
+    // Returning before commit rolls back, so a rejected password leaves the token usable.
+    if let Err(message) = password::check_policy(&payload.new_password, &user.email) {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({ "error": message })));
+    }
+
+    let password_hash = hash_new_password(&payload.new_password)?;
+
+    let user_id = user.id;
+    let mut active_user: userentity::ActiveModel = user.into();
+    active_user.password = Set(password_hash);
+    active_user.update(&txn).await.map_err(database_error)?;
+
+    // A password reset signs the account out everywhere.
+    revoke_user_refresh_tokens(&txn, user_id).await.map_err(database_error)?;
+    txn.commit().await.map_err(database_error)?;
+    info!("Password reset completed for user {}", user_id);
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "message": "Password has been reset successfully"
+    })))
+}
+
+#[get("/users", wrap = "RequireRole::any_of(&[Role::SupportAgent])")]
+async fn get_users(db: web::Data<DatabaseConnection>) -> Result<HttpResponse, actix_web::Error> {
+    let users = UserEntity::find()
+        .all(db.as_ref())
+        .await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Error fetching users"))?
+        .into_iter()
+        .map(|user| serde_json::json!({
+            "id": user.id,
+            "email": user.email,
+        }))
+        .collect::<Vec<_>>();
+
+    Ok(HttpResponse::Ok().json(users))
+}
+
+#[derive(Deserialize)]
+pub struct UpdateUserRole {
+    pub role: String,
+}
+
+#[put("/admin/users/{id}/role", wrap = "RequireRole::any_of(&[Role::Admin])")]
+async fn update_user_role(
+    db: web::Data<DatabaseConnection>,
+    user_id: web::Path<i32>,
+    payload: web::Json<UpdateUserRole>,
+) -> Result<HttpResponse, Error> {
+    let role = match payload.role.parse::<Role>() {
+        Ok(role) => role,
+        Err(message) => {
+            return Ok(HttpResponse::BadRequest().json(serde_json::json!({ "error": message })));
+        }
+    };
+
+    let user = match UserEntity::find_by_id(user_id.into_inner())
+        .one(db.as_ref())
+        .await
+        .map_err(database_error)?
+    {
+        Some(user) => user,
+        None => {
+            return Ok(HttpResponse::NotFound().json(serde_json::json!({
+                "error": "User not found"
+            })));
+        }
+    };
+
+    let mut active_user: userentity::ActiveModel = user.into();
+    active_user.role = Set(role.as_str().to_string());
+    let updated = active_user.update(db.as_ref()).await.map_err(database_error)?;
+    info!("Changed role of user {} to {}", updated.id, updated.role);
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "message": "User role updated successfully",
+        "id": updated.id,
+        "role": updated.role
+    })))
+}
+
+/// Clears a user's login lockout and failed-attempt counter.
+#[post("/admin/users/{id}/unlock", wrap = "RequireRole::any_of(&[Role::Admin])")]
+async fn unlock_user_login(
+    auth: AuthenticatedUser,
+    db: web::Data<DatabaseConnection>,
+    user_id: web::Path<i32>,
 ) -> Result<HttpResponse, Error> {
-    if !is_valid_email(&new_user.email) {
+    let user = match UserEntity::find_by_id(user_id.into_inner())
+        .one(db.as_ref())
+        .await
+        .map_err(database_error)?
+    {
+        Some(user) => user,
+        None => {
+            return Ok(HttpResponse::NotFound().json(serde_json::json!({
+                "error": "User not found"
+            })));
+        }
+    };
+
+    let cleared = throttle::clear(db.as_ref(), &throttle::account_key(&user.email))
+        .await
+        .map_err(database_error)?;
+    info!("Admin {} unlocked login for user {}", auth.user.id, user.id);
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "message": "User login unlocked",
+        "id": user.id,
+        "was_locked": cleared > 0
+    })))
+}
+
+#[derive(Deserialize)]
+pub struct SuspendUserRequest {
+    pub reason: String,
+    /// Omit for a suspension that lasts until an admin reinstates the account.
+    pub expires_at: Option<ChronoDateTime<Utc>>,
+}
+
+/// Suspends a rider or driver. Their sessions are ended, a driver is taken offline,
+/// and their tokens are refused until the suspension expires or is lifted.
+#[post("/admin/users/{id}/suspend", wrap = "RequireRole::any_of(&[Role::Admin])")]
+async fn suspend_user(
+    auth: AuthenticatedUser,
+    db: web::Data<DatabaseConnection>,
+    user_id: web::Path<i32>,
+    payload: web::Json<SuspendUserRequest>,
+) -> Result<HttpResponse, Error> {
+    let user_id = user_id.into_inner();
+    let reason = payload.reason.trim();
+    if reason.is_empty() {
         return Ok(HttpResponse::BadRequest().json(serde_json::json!({
-            "error": "Incorrect email format"
+            "error": "A reason is required"
         })));
     }
-
-    if let Err(_) = validate_phone(&new_user.phone_number) {
+    let now = Utc::now().naive_utc();
+    let expires_at = payload.expires_at.map(|at| at.naive_utc());
+    if expires_at.is_some_and(|at| at <= now) {
         return Ok(HttpResponse::BadRequest().json(serde_json::json!({
-            "error": "Invalid phone number format"
+            "error": "expires_at must be in the future"
+        })));
+    }
+    if user_id == auth.user.id {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "You cannot suspend your own account"
         })));
     }
 
-    let existing_email = UserEntity::find()
-        .filter(userentity::Column::Email.eq(new_user.email.clone()))
-        .one(db.as_ref())
+    let txn = db.begin().await.map_err(database_error)?;
+    if UserEntity::find_by_id(user_id).one(&txn).await.map_err(database_error)?.is_none() {
+        return Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "error": "User not found"
+        })));
+    }
+    if suspension::active_suspension(&txn, user_id).await.map_err(database_error)?.is_some() {
+        return Ok(HttpResponse::Conflict().json(serde_json::json!({
+            "error": "User is already suspended"
+        })));
+    }
+
+    let created = accountsuspension::ActiveModel {
+        user_id: Set(user_id),
+        status: Set(suspension::STATUS_ACTIVE.to_string()),
+        reason: Set(reason.to_string()),
+        suspended_by: Set(Some(auth.user.id)),
+        created_at: Set(now),
+        expires_at: Set(expires_at),
+        ..Default::default()
+    }
+    .insert(&txn)
+    .await
+    .map_err(database_error)?;
+
+    revoke_user_refresh_tokens(&txn, user_id).await.map_err(database_error)?;
+    driverentity::Entity::update_many()
+        .col_expr(driverentity::Column::AvailabilityStatus, Expr::value("offline"))
+        .filter(driverentity::Column::UserId.eq(user_id))
+        .exec(&txn)
         .await
-        .map_err(|e| {
-            eprintln!("Database query error: {:?}", e);
-            actix_web::error::ErrorInternalServerError("Database error")
-        })?;
+        .map_err(database_error)?;
+    txn.commit().await.map_err(database_error)?;
 
-    if existing_email.is_some() {
+    warn!("Admin {} suspended user {}: {}", auth.user.id, user_id, reason);
+    Ok(HttpResponse::Created().json(serde_json::json!({
+        "message": "User suspended",
+        "suspension": created
+    })))
+}
+
+/// Lifts every suspension in effect for the user.
+#[post("/admin/users/{id}/reinstate", wrap = "RequireRole::any_of(&[Role::Admin])")]
+async fn reinstate_user(
+    auth: AuthenticatedUser,
+    db: web::Data<DatabaseConnection>,
+    user_id: web::Path<i32>,
+) -> Result<HttpResponse, Error> {
+    let user_id = user_id.into_inner();
+    let result = accountsuspension::Entity::update_many()
+        .col_expr(accountsuspension::Column::Status, Expr::value(suspension::STATUS_LIFTED))
+        .col_expr(accountsuspension::Column::LiftedBy, Expr::value(auth.user.id))
+        .col_expr(accountsuspension::Column::LiftedAt, Expr::value(Utc::now().naive_utc()))
+        .filter(accountsuspension::Column::UserId.eq(user_id))
+        .filter(suspension::in_effect())
+        .exec(db.as_ref())
+        .await
+        .map_err(database_error)?;
+    if result.rows_affected == 0 {
         return Ok(HttpResponse::Conflict().json(serde_json::json!({
-            "error": "Email already exists"
+            "error": "User is not suspended"
         })));
     }
 
-    let existing_phone = UserEntity::find()
-        .filter(userentity::Column::PhoneNumber.eq(new_user.phone_number.clone()))
-        .one(db.as_ref())
+    info!("Admin {} reinstated user {}", auth.user.id, user_id);
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "message": "User reinstated",
+        "id": user_id
+    })))
+}
+
+#[get("/admin/users/{id}/suspensions", wrap = "RequireRole::any_of(&[Role::SupportAgent])")]
+async fn get_user_suspensions(
+    db: web::Data<DatabaseConnection>,
+    user_id: web::Path<i32>,
+) -> Result<HttpResponse, Error> {
+    let suspensions = accountsuspension::Entity::find()
+        .filter(accountsuspension::Column::UserId.eq(user_id.into_inner()))
+        .order_by_desc(accountsuspension::Column::CreatedAt)
+        .all(db.as_ref())
         .await
-        .map_err(|e| {
-            eprintln!("Database query error: {:?}", e);
-            actix_web::error::ErrorInternalServerError("Database error")
-        })?;
+        .map_err(database_error)?;
+    Ok(HttpResponse::Ok().json(suspensions))
+}
 
-    if existing_phone.is_some() {
-        return Ok(HttpResponse::Conflict().json(serde_json::json!({
-            "error": "Phone number already exists"
+#[derive(Deserialize)]
+pub struct ImpersonationRequest {
+    pub reason: String,
+}
+
+/// Mints a short-lived, read-only access token for a rider or driver so staff can see
+/// the app as they do. Every request made with it is audit-logged.
+#[post("/admin/users/{id}/impersonate", wrap = "RequireRole::any_of(&[Role::Admin])")]
+async fn impersonate_user(
+    auth: AuthenticatedUser,
+    db: web::Data<DatabaseConnection>,
+    user_id: web::Path<i32>,
+    payload: web::Json<ImpersonationRequest>,
+) -> Result<HttpResponse, Error> {
+    let reason = payload.reason.trim();
+    if reason.is_empty() {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "A reason is required"
         })));
     }
 
-    let password_hash = hash(&new_user.password, DEFAULT_COST).map_err(|e| {
-        eprintln!("Password hashing error: {:?}", e);
-        actix_web::error::ErrorInternalServerError("Failed to hash password")
+    let target = match UserEntity::find_by_id(user_id.into_inner())
+        .one(db.as_ref())
+        .await
+        .map_err(database_error)?
+    {
+        Some(user) => user,
+        None => {
+            return Ok(HttpResponse::NotFound().json(serde_json::json!({
+                "error": "User not found"
+            })));
+        }
+    };
+    let role = user_role(&target);
+    if matches!(role, Role::Admin | Role::SupportAgent) || target.id == auth.user.id {
+        return Ok(HttpResponse::Forbidden().json(serde_json::json!({
+            "error": "Only rider and driver accounts can be impersonated"
+        })));
+    }
+    let role = session_role(db.as_ref(), &target, role).await?;
+
+    let now = Utc::now().naive_utc();
+    let jti = generate_token_id();
+    let session = impersonationsession::ActiveModel {
+        token_id: Set(jti.clone()),
+        actor_user_id: Set(Some(auth.user.id)),
+        target_user_id: Set(Some(target.id)),
+        reason: Set(reason.to_string()),
+        created_at: Set(now),
+        expires_at: Set(now + chrono::Duration::minutes(IMPERSONATION_TOKEN_MINUTES)),
+        ..Default::default()
+    }
+    .insert(db.as_ref())
+    .await
+    .map_err(database_error)?;
+
+    let token = generate_impersonation_token(&target.email, role, &auth.user.email, &jti).map_err(|e| {
+        eprintln!("Token generation error: {:?}", e);
+        actix_web::error::ErrorInternalServerError("Failed to generate token")
     })?;
+    warn!(
+        "Admin {} started impersonating user {} (session {}): {}",
+        auth.user.id, target.id, session.id, reason
+    );
+
+    Ok(HttpResponse::Created().json(serde_json::json!({
+        "access_token": token,
+        "token_type": "Bearer",
+        "expires_in": IMPERSONATION_TOKEN_MINUTES * 60,
+        "read_only": true,
+        "session": session
+    })))
+}
 
-    let new_user_active_model = userentity::ActiveModel {
-        first_name: Set(new_user.first_name.clone()),
-        last_name: Set(new_user.last_name.clone()),
-        email: Set(new_user.email.clone()),
-        password: Set(password_hash),
-        city: Set(new_user.city),
-        phone_number: Set(new_user.phone_number.clone()),
-        ..Default::default()
+/// Impersonation sessions that targeted the user, with every request made in them.
+#[get("/admin/users/{id}/impersonations", wrap = "RequireRole::any_of(&[Role::Admin])")]
+async fn get_user_impersonations(
+    db: web::Data<DatabaseConnection>,
+    user_id: web::Path<i32>,
+) -> Result<HttpResponse, Error> {
+    let sessions = impersonationsession::Entity::find()
+        .filter(impersonationsession::Column::TargetUserId.eq(user_id.into_inner()))
+        .order_by_desc(impersonationsession::Column::CreatedAt)
+        .find_with_related(impersonationauditlog::Entity)
+        .all(db.as_ref())
+        .await
+        .map_err(database_error)?;
+
+    let sessions: Vec<_> = sessions
+        .into_iter()
+        .map(|(session, requests)| serde_json::json!({
+            "session": session,
+            "requests": requests
+        }))
+        .collect();
+    Ok(HttpResponse::Ok().json(sessions))
+}
+
+#[get("/me")]
+async fn get_current_user(auth: AuthenticatedUser) -> impl Responder {
+    let user = &auth.user;
+    HttpResponse::Ok().json(serde_json::json!({
+        "id": user.id,
+        "first_name": user.first_name,
+        "last_name": user.last_name,
+        "email": user.email,
+        "city": user.city,
+        "phone_number": user.phone_number,
+        "role": auth.claims.role,
+        "email_verified": user.email_verified_at.is_some(),
+        "deletion_scheduled_for": user.deletion_scheduled_for,
+    }))
+}
+
+/// Schedules the caller's account for deletion after the grace period. Until then the
+/// account keeps working and the request can be cancelled.
+#[delete("/me")]
+async fn delete_my_account(
+    auth: AuthenticatedUser,
+    db: web::Data<DatabaseConnection>,
+    mailer: web::Data<dyn Mailer>,
+) -> Result<HttpResponse, Error> {
+    let user = &auth.user;
+    let scheduled_for = Utc::now().naive_utc() + accounts::deletion_grace_period();
+
+    let result = UserEntity::update_many()
+        .col_expr(userentity::Column::DeletionScheduledFor, Expr::value(scheduled_for))
+        .filter(userentity::Column::Id.eq(user.id))
+        .filter(userentity::Column::DeletionScheduledFor.is_null())
+        .exec(db.as_ref())
+        .await
+        .map_err(database_error)?;
+    if result.rows_affected == 0 {
+        return Ok(HttpResponse::Conflict().json(serde_json::json!({
+            "error": "Account deletion is already scheduled"
+        })));
+    }
+    info!("User {} scheduled their account for deletion on {}", user.id, scheduled_for);
+
+    let email = Email {
+        to: user.email.clone(),
+        subject: "Your Arrively account will be deleted".to_string(),
+        body: format!(
+            "Hi {},\n\nWe received a request to delete your account. It will be deleted on {} UTC, together with your profile, settings, saved locations, payment methods and support tickets.\n\nIf you change your mind, sign in before then and cancel the deletion from your account settings.",
+            user.first_name,
+            scheduled_for.format("%Y-%m-%d %H:%M")
+        ),
     };
+    if let Err(e) = mailer.send(&email).await {
+        error!("Failed to send deletion notice to user {}: {}", user.id, e);
+    }
 
-    match new_user_active_model.insert(db.as_ref()).await {
-        Ok(_) => {
-            eprintln!("User successfully inserted into database");
-            return Ok(HttpResponse::Created().json(serde_json::json!({ 
-                "message": "User registered successfully"
-            })));
+    Ok(HttpResponse::Accepted().json(serde_json::json!({
+        "message": "Account scheduled for deletion",
+        "deletion_scheduled_for": scheduled_for
+    })))
+}
+
+#[post("/me/deletion/cancel")]
+async fn cancel_account_deletion(
+    auth: AuthenticatedUser,
+    db: web::Data<DatabaseConnection>,
+) -> Result<HttpResponse, Error> {
+    let result = UserEntity::update_many()
+        .col_expr(userentity::Column::DeletionScheduledFor, Expr::value(Option::<chrono::NaiveDateTime>::None))
+        .filter(userentity::Column::Id.eq(auth.user.id))
+        .filter(userentity::Column::DeletionScheduledFor.is_not_null())
+        .exec(db.as_ref())
+        .await
+        .map_err(database_error)?;
+    if result.rows_affected == 0 {
+        return Ok(HttpResponse::Conflict().json(serde_json::json!({
+            "error": "No account deletion is scheduled"
+        })));
+    }
+    info!("User {} cancelled their account deletion", auth.user.id);
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "message": "Account deletion cancelled"
+    })))
+}
+
+//data export API
+
+#[derive(Deserialize)]
+pub struct ExportDownloadQuery {
+    pub token: String,
+}
+
+/// Returns a download link for the caller's latest data export, or queues a new
+/// export when there is none that is current.
+#[get("/me/export")]
+async fn get_my_data_export(
+    auth: AuthenticatedUser,
+    db: web::Data<DatabaseConnection>,
+) -> Result<HttpResponse, Error> {
+    // Queues a job and hands out a download link, so it is not a read.
+    if auth.is_impersonated() {
+        return Err(AuthError::ReadOnlyImpersonation.into());
+    }
+
+    let now = Utc::now().naive_utc();
+    let latest = dataexport::Entity::find()
+        .filter(dataexport::Column::UserId.eq(auth.user.id))
+        .order_by_desc(dataexport::Column::RequestedAt)
+        .one(db.as_ref())
+        .await
+        .map_err(database_error)?;
+
+    match latest {
+        Some(export) if export.status == exports::STATUS_PENDING || export.status == exports::STATUS_PROCESSING => {
+            Ok(HttpResponse::Accepted().json(serde_json::json!({
+                "message": "Your export is being prepared",
+                "export": export
+            })))
         }
-        Err(e) => {
-            eprintln!("Database insertion error: {:?}", e);
-            return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
-                "error": "Error registering user"
-            })));
+        Some(export) if export.status == exports::STATUS_READY && export.expires_at.is_some_and(|at| at > now) => {
+            let token = exports::issue_download_token(db.as_ref(), export.id)
+                .await
+                .map_err(database_error)?;
+            Ok(HttpResponse::Ok().json(serde_json::json!({
+                "export": export,
+                "download_url": exports::download_url(&token)
+            })))
+        }
+        _ => {
+            let export = dataexport::ActiveModel {
+                user_id: Set(auth.user.id),
+                status: Set(exports::STATUS_PENDING.to_string()),
+                requested_at: Set(now),
+                ..Default::default()
+            }
+            .insert(db.as_ref())
+            .await
+            .map_err(database_error)?;
+            info!("User {} requested a data export", auth.user.id);
+
+            Ok(HttpResponse::Accepted().json(serde_json::json!({
+                "message": "Your export is being prepared. We will email you a download link when it is ready.",
+                "export": export
+            })))
         }
     }
 }
 
-#[get("/users")]
-async fn get_users(db: web::Data<DatabaseConnection>, req: HttpRequest) -> Result<HttpResponse, actix_web::Error> {
-    let auth_header = req.headers().get("Authorization");
-
-    if let Some(auth_value) = auth_header {
-        if let Ok(auth_str) = auth_value.to_str() {
-            if auth_str.starts_with("Bearer ") {
-                let token = &auth_str[7..];
-
-                match AuthTokenClaims::validate_token(token) {
-                    Ok(_) => {
-                        let users = UserEntity::find()
-                            .all(db.as_ref())
-                            .await
-                            .map_err(|_| actix_web::error::ErrorInternalServerError("Error fetching users"))?
-                            .into_iter()
-                            .map(|user| serde_json::json!({
-                                "id": user.id,
-                                "email": user.email,
-                            }))
-                            .collect::<Vec<_>>();
-
-                        return Ok(HttpResponse::Ok().json(users));
-                    },
-                    Err(_) => {
-                        return Ok(HttpResponse::Unauthorized().json(serde_json::json!({
-                            "error": "Invalid token"
-                        })));
-                    }
-                }
-            }
+#[get("/exports/download")]
+async fn download_data_export(
+    query: web::Query<ExportDownloadQuery>,
+    db: web::Data<DatabaseConnection>,
+) -> Result<HttpResponse, Error> {
+    let export = dataexport::Entity::find()
+        .filter(dataexport::Column::DownloadTokenHash.eq(hash_token(&query.token)))
+        .one(db.as_ref())
+        .await
+        .map_err(database_error)?;
+
+    let export = match export {
+        Some(export) => export,
+        None => {
+            return Ok(HttpResponse::NotFound().json(serde_json::json!({
+                "error": "Invalid download link"
+            })))
         }
-        return Ok(HttpResponse::Unauthorized().json(serde_json::json!({
-            "error": "Invalid token format"
+    };
+
+    let now = Utc::now().naive_utc();
+    let archive = match export.archive {
+        Some(archive) if export.status == exports::STATUS_READY && export.expires_at.is_some_and(|at| at > now) => archive,
+        _ => {
+            return Ok(HttpResponse::Gone().json(serde_json::json!({
+                "error": "This download link has expired"
+            })))
+        }
+    };
+
+    info!("Data export {} downloaded for user {}", export.id, export.user_id);
+    Ok(HttpResponse::Ok()
+        .content_type("application/zip")
+        .insert_header((
+            "Content-Disposition",
+            format!(
+                "attachment; filename=\"arrively-export-{}.zip\"",
+                export.requested_at.format("%Y%m%d")
+            ),
+        ))
+        .body(archive))
+}
+
+//legal documents API
+
+#[derive(Deserialize)]
+pub struct PublishLegalDocument {
+    pub kind: String,
+    pub version: String,
+    pub content: String,
+    /// Defaults to now. A future date schedules the version; users are asked to
+    /// accept it once it takes effect.
+    pub published_at: Option<ChronoDateTime<Utc>>,
+}
+
+#[derive(Deserialize)]
+pub struct AcceptLegalDocuments {
+    pub document_ids: Vec<i32>,
+}
+
+#[derive(Deserialize)]
+pub struct MarketingConsentRequest {
+    pub opt_in: bool,
+}
+
+/// The versions new users accept at registration.
+#[get("/legal/documents")]
+async fn get_legal_documents(db: web::Data<DatabaseConnection>) -> Result<HttpResponse, Error> {
+    let documents = legal::current_documents(db.as_ref()).await.map_err(database_error)?;
+    Ok(HttpResponse::Ok().json(documents))
+}
+
+#[post("/admin/legal/documents", wrap = "RequireRole::any_of(&[Role::Admin])")]
+async fn publish_legal_document(
+    auth: AuthenticatedUser,
+    db: web::Data<DatabaseConnection>,
+    payload: web::Json<PublishLegalDocument>,
+) -> Result<HttpResponse, Error> {
+    if !legal::DOCUMENT_KINDS.contains(&payload.kind.as_str()) {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "kind must be one of terms, privacy"
+        })));
+    }
+    if payload.version.trim().is_empty() || payload.content.trim().is_empty() {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "version and content are required"
+        })));
+    }
+
+    let existing = legaldocument::Entity::find()
+        .filter(legaldocument::Column::Kind.eq(payload.kind.clone()))
+        .filter(legaldocument::Column::Version.eq(payload.version.trim()))
+        .one(db.as_ref())
+        .await
+        .map_err(database_error)?;
+    if existing.is_some() {
+        return Ok(HttpResponse::Conflict().json(serde_json::json!({
+            "error": "This version already exists"
+        })));
+    }
+
+    let now = Utc::now().naive_utc();
+    let document = legaldocument::ActiveModel {
+        kind: Set(payload.kind.clone()),
+        version: Set(payload.version.trim().to_string()),
+        content: Set(payload.content.clone()),
+        published_at: Set(payload.published_at.map_or(now, |at| at.naive_utc())),
+        created_at: Set(now),
+        ..Default::default()
+    }
+    .insert(db.as_ref())
+    .await
+    .map_err(database_error)?;
+    info!(
+        "Admin {} published {} version {} effective {}",
+        auth.user.id, document.kind, document.version, document.published_at
+    );
+
+    Ok(HttpResponse::Created().json(document))
+}
+
+#[get("/me/legal")]
+async fn get_my_legal_status(
+    auth: AuthenticatedUser,
+    db: web::Data<DatabaseConnection>,
+) -> Result<HttpResponse, Error> {
+    let pending = legal::pending_documents(db.as_ref(), auth.user.id).await.map_err(database_error)?;
+    let accepted = legalacceptance::Entity::find()
+        .filter(legalacceptance::Column::UserId.eq(auth.user.id))
+        .order_by_desc(legalacceptance::Column::AcceptedAt)
+        .all(db.as_ref())
+        .await
+        .map_err(database_error)?;
+    let marketing_opt_in = legal::marketing_opt_in(db.as_ref(), auth.user.id).await.map_err(database_error)?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "pending": pending,
+        "accepted": accepted,
+        "marketing_opt_in": marketing_opt_in
+    })))
+}
+
+/// Accepts current document versions. Only current versions can be accepted.
+#[post("/me/legal/acceptances")]
+async fn accept_legal_documents(
+    req: HttpRequest,
+    auth: AuthenticatedUser,
+    db: web::Data<DatabaseConnection>,
+    payload: web::Json<AcceptLegalDocuments>,
+) -> Result<HttpResponse, Error> {
+    let current = legal::current_documents(db.as_ref()).await.map_err(database_error)?;
+    if let Some(unknown) = payload
+        .document_ids
+        .iter()
+        .find(|id| !current.iter().any(|document| document.id == **id))
+    {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": format!("Document {} is not a current version", unknown)
         })));
     }
 
-    Ok(HttpResponse::Unauthorized().json(serde_json::json!({
-        "error": "Missing token"
+    legal::record_acceptances(db.as_ref(), auth.user.id, &payload.document_ids, &throttle::client_ip(&req))
+        .await
+        .map_err(database_error)?;
+    let pending = legal::pending_documents(db.as_ref(), auth.user.id).await.map_err(database_error)?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "message": "Acceptance recorded",
+        "pending": pending
+    })))
+}
+
+/// Opts in to or out of marketing messages. Independent of the legal documents.
+#[put("/me/marketing-consent")]
+async fn update_marketing_consent(
+    req: HttpRequest,
+    auth: AuthenticatedUser,
+    db: web::Data<DatabaseConnection>,
+    payload: web::Json<MarketingConsentRequest>,
+) -> Result<HttpResponse, Error> {
+    let consent = legal::record_marketing_consent(db.as_ref(), auth.user.id, payload.opt_in, &throttle::client_ip(&req))
+        .await
+        .map_err(database_error)?;
+    info!("User {} set marketing consent to {}", auth.user.id, consent.granted);
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "marketing_opt_in": consent.granted,
+        "recorded_at": consent.recorded_at
     })))
 }
 
@@ -177,7 +2382,8 @@ use crate::entities::userprofile;
 
 #[derive(Deserialize)]
 pub struct NewUserProfile {
-    pub user_id: i32,
+    #[serde(default)]
+    pub user_id: Option<i32>,
     pub profile_photo: Option<String>,
     pub about: Option<String>,
     pub location: Option<String>,
@@ -187,17 +2393,19 @@ pub struct NewUserProfile {
 
 #[post("/user_profiles")]
 async fn create_user_profile(
+    auth: AuthenticatedUser,
     new_profile: web::Json<NewUserProfile>,
     db: web::Data<DatabaseConnection>,
 ) -> Result<HttpResponse, Error> {
+    let user_id = auth.resolve_user_id(new_profile.user_id)?;
+
     let new_profile_active = userprofile::ActiveModel {
-        user_id: Set(new_profile.user_id),
+        user_id: Set(user_id),
         profile_photo: Set(new_profile.profile_photo.clone()),
         about: Set(new_profile.about.clone()),
         location: Set(new_profile.location.clone()),
         language: Set(new_profile.language.clone()),
         phone_number: Set(new_profile.phone_number.clone()),
-        ..Default::default()
     };
 
     match new_profile_active.insert(db.as_ref()).await {
@@ -213,7 +2421,7 @@ async fn create_user_profile(
     }
 }
 
-#[get("/user_profiles")]
+#[get("/user_profiles", wrap = "RequireRole::any_of(&[Role::SupportAgent])")]
 async fn get_user_profiles(db: web::Data<DatabaseConnection>) -> Result<HttpResponse, Error> {
     match userprofile::Entity::find()
         .all(db.as_ref())
@@ -229,15 +2437,32 @@ async fn get_user_profiles(db: web::Data<DatabaseConnection>) -> Result<HttpResp
     }
 }
 
-#[put("/user_profiles/{user_id}")]
-async fn update_user_profile(
-    user_id: web::Path<i32>,
-    updated_profile: web::Json<NewUserProfile>,
+#[get("/me/profile")]
+async fn get_my_profile(
+    auth: AuthenticatedUser,
     db: web::Data<DatabaseConnection>,
+) -> Result<HttpResponse, Error> {
+    let profile = userprofile::Entity::find_by_id(auth.user.id)
+        .one(db.as_ref())
+        .await
+        .map_err(database_error)?;
+
+    match profile {
+        Some(profile) => Ok(HttpResponse::Ok().json(profile)),
+        None => Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "error": "User profile not found"
+        }))),
+    }
+}
+
+async fn update_profile_for(
+    db: &DatabaseConnection,
+    user_id: i32,
+    updated_profile: &NewUserProfile,
 ) -> Result<HttpResponse, Error> {
     let existing_profile = userprofile::Entity::find()
-        .filter(userprofile::Column::UserId.eq(user_id.into_inner()))
-        .one(db.as_ref())
+        .filter(userprofile::Column::UserId.eq(user_id))
+        .one(db)
         .await
         .map_err(|e| {
             eprintln!("Database query error: {:?}", e);
@@ -253,7 +2478,7 @@ async fn update_user_profile(
         profile_active.language = Set(updated_profile.language.clone());
         profile_active.phone_number = Set(updated_profile.phone_number.clone());
 
-        match profile_active.update(db.as_ref()).await {
+        match profile_active.update(db).await {
             Ok(_) => Ok(HttpResponse::Ok().json(serde_json::json!({
                 "message": "User profile updated successfully"
             }))),
@@ -271,13 +2496,38 @@ async fn update_user_profile(
     }
 }
 
+#[put("/user_profiles/{user_id}")]
+async fn update_user_profile(
+    auth: AuthenticatedUser,
+    user_id: web::Path<i32>,
+    updated_profile: web::Json<NewUserProfile>,
+    db: web::Data<DatabaseConnection>,
+) -> Result<HttpResponse, Error> {
+    let user_id = user_id.into_inner();
+    auth.ensure_owner(user_id)?;
+    update_profile_for(db.as_ref(), user_id, &updated_profile).await
+}
+
+#[put("/me/profile")]
+async fn update_my_profile(
+    auth: AuthenticatedUser,
+    updated_profile: web::Json<NewUserProfile>,
+    db: web::Data<DatabaseConnection>,
+) -> Result<HttpResponse, Error> {
+    update_profile_for(db.as_ref(), auth.user.id, &updated_profile).await
+}
+
 #[delete("/user_profiles/{user_id}")]
 async fn delete_user_profile(
+    auth: AuthenticatedUser,
     user_id: web::Path<i32>,
     db: web::Data<DatabaseConnection>,
 ) -> Result<HttpResponse, Error> {
+    let user_id = user_id.into_inner();
+    auth.ensure_owner(user_id)?;
+
     let deleted_count = userprofile::Entity::delete_many()
-        .filter(userprofile::Column::UserId.eq(user_id.into_inner()))
+        .filter(userprofile::Column::UserId.eq(user_id))
         .exec(db.as_ref())
         .await
         .map_err(|e| {
@@ -304,93 +2554,201 @@ async fn delete_user_profile(
 
 
 
+/// Driver sign-up payload. Server-owned fields (rating, ride count, verification and
+/// availability status, location) are never taken from the client.
+#[derive(Debug, Deserialize)]
+pub struct RegisterDriver {
+    pub first_name: String,
+    pub last_name: String,
+    /// Required when creating a new account; ignored when linking a signed-in rider.
+    #[serde(default)]
+    pub email: Option<String>,
+    #[serde(default)]
+    pub password: Option<String>,
+    #[serde(default)]
+    pub city: Option<i32>,
+    pub phone: String,
+    pub photo: String,
+    pub about_me: String,
+    pub from_location: String,
+    pub languages: Vec<String>,
+    pub is_pilot: bool,
+    pub license_number: String,
+    /// Ids of the current legal documents accepted; required when creating a new account.
+    #[serde(default)]
+    pub accepted_documents: Vec<i32>,
+    #[serde(default)]
+    pub marketing_opt_in: bool,
+}
+
+/// Registers a driver. Signed-in riders get a driver profile linked to their existing
+/// account; everyone else gets a new account with the driver role.
 #[post("/drivers")]
-async fn create_driver(driver: web::Json<driverentity::Model>) -> impl Responder {
-    match establish_connection_pool().await {
-        Ok(db) => {
-            let existing_driver = driverentity::Entity::find()
-                .filter(
-                    driverentity::Column::Email.eq(driver.email.clone())
-                    .or(driverentity::Column::Phone.eq(driver.phone.clone()))
-                )
-                .one(&db)
-                .await;
-
-            match existing_driver {
-                Ok(Some(existing)) => {
-                    let message = if existing.email == driver.email {
-                        "Driver with this email already registered"
-                    } else {
-                        "Phone number already exists"
-                    };
-                    
-                    let response = json!({
-                        "message": message,
-                        "email": driver.email,
-                        "phone": driver.phone
-                    });
-                    HttpResponse::Conflict().json(response)
-                }
-                Ok(None) => {
-                    use chrono::Utc;
-
-                    let now = Utc::now().naive_utc(); 
-
-                    let new_driver = driverentity::ActiveModel {
-                        first_name: Set(driver.first_name.clone()),
-                        last_name: Set(driver.last_name.clone()),
-                        email: Set(driver.email.clone()),
-                        phone: Set(driver.phone.clone()),
-                        photo: Set(driver.photo.clone()),
-                        rating: Set(driver.rating),
-                        total_rides: Set(driver.total_rides),
-                        about_me: Set(driver.about_me.clone()),
-                        from_location: Set(driver.from_location.clone()),
-                        languages: Set(driver.languages.clone()),
-                        is_pilot: Set(driver.is_pilot),
-                        license_number: Set(driver.license_number.clone()),
-                        verification_status: Set(driver.verification_status.clone()),
-                        current_lat: Set(driver.current_lat),
-                        current_lng: Set(driver.current_lng),
-                        availability_status: Set(driver.availability_status.clone()),
-                        created_at: Set(Some(now)), 
-                        updated_at: Set(Some(now)), 
-                        ..Default::default() 
-                    };
-
-                    match driverentity::Entity::insert(new_driver).exec(&db).await {
-                        Ok(inserted) => {
-                            let response = json!({
-                                "message": "Driver registered successfully!",
-                                "driver_id": inserted.last_insert_id, 
-                                "email": driver.email,
-                                "phone": driver.phone,
-                                "created_at": now,
-                                "updated_at": now
-                            });
-                            HttpResponse::Created().json(response)
-                        }
-                        Err(e) => {
-                            eprintln!("Database insertion error: {:?}", e);
-                            HttpResponse::InternalServerError().body("Error creating driver")
-                        }
-                    }
-                }
-                Err(e) => {
-                    eprintln!("Database query failed: {:?}", e);
-                    HttpResponse::InternalServerError().body("Database query failed")
+async fn register_driver(
+    req: HttpRequest,
+    payload: web::Json<RegisterDriver>,
+    db: web::Data<DatabaseConnection>,
+    mailer: web::Data<dyn Mailer>,
+) -> Result<HttpResponse, Error> {
+    let auth = authenticate_if_present(&req).await?;
+    if validate_phone(&payload.phone).is_err() {
+        return Ok(HttpResponse::BadRequest().json(json!({"error": "Invalid phone number format"})));
+    }
+
+    let email = match &auth {
+        Some(auth) => auth.user.email.clone(),
+        None => match &payload.email {
+            Some(email) if is_valid_email(email) => email.clone(),
+            _ => return Ok(HttpResponse::BadRequest().json(json!({"error": "Incorrect email format"}))),
+        },
+    };
+
+    let existing_driver = driverentity::Entity::find()
+        .filter(
+            driverentity::Column::Email.eq(email.clone())
+                .or(driverentity::Column::Phone.eq(payload.phone.clone())),
+        )
+        .one(db.as_ref())
+        .await
+        .map_err(database_error)?;
+
+    if let Some(existing) = existing_driver {
+        let message = if existing.email == email {
+            "Driver with this email already registered"
+        } else {
+            "Phone number already exists"
+        };
+        return Ok(HttpResponse::Conflict().json(json!({"error": message})));
+    }
+
+    let txn = db.begin().await.map_err(database_error)?;
+
+    let (user, new_account) = match &auth {
+        Some(auth) => (auth.user.clone(), false),
+        None => {
+            let existing_user = UserEntity::find()
+                .filter(userentity::Column::Email.eq(email.clone()))
+                .one(&txn)
+                .await
+                .map_err(database_error)?;
+            if existing_user.is_some() {
+                return Ok(HttpResponse::Conflict().json(json!({
+                    "error": "An account with this email already exists. Sign in and register again to link it."
+                })));
+            }
+
+            let (password, city) = match (&payload.password, payload.city) {
+                (Some(password), Some(city)) => (password, city),
+                _ => {
+                    return Ok(HttpResponse::BadRequest().json(json!({
+                        "error": "password and city are required to create a driver account"
+                    })));
                 }
+            };
+
+            if let Err(message) = password::check_policy(password, &email) {
+                return Ok(HttpResponse::BadRequest().json(json!({ "error": message })));
+            }
+
+            let required = legal::current_documents(&txn).await.map_err(database_error)?;
+            if let Some(rejection) = unaccepted_documents(&required, &payload.accepted_documents) {
+                return Ok(rejection);
+            }
+
+            let password_hash = hash_new_password(password)?;
+
+            let user = userentity::ActiveModel {
+                first_name: Set(payload.first_name.clone()),
+                last_name: Set(payload.last_name.clone()),
+                email: Set(email.clone()),
+                password: Set(password_hash),
+                city: Set(city),
+                phone_number: Set(Some(payload.phone.clone())),
+                role: Set(Role::Driver.as_str().to_string()),
+                ..Default::default()
             }
+            .insert(&txn)
+            .await
+            .map_err(database_error)?;
+            let ip_address = throttle::client_ip(&req);
+            record_signup_consents(&txn, user.id, &required, payload.marketing_opt_in, &ip_address).await?;
+            (user, true)
         }
-        Err(_) => HttpResponse::InternalServerError().body("Database connection failed"),
+    };
+
+    let now = Utc::now().naive_utc();
+    let driver = driverentity::ActiveModel {
+        first_name: Set(payload.first_name.clone()),
+        last_name: Set(payload.last_name.clone()),
+        email: Set(email),
+        phone: Set(payload.phone.clone()),
+        photo: Set(payload.photo.clone()),
+        rating: Set(0.0),
+        total_rides: Set(0),
+        about_me: Set(payload.about_me.clone()),
+        from_location: Set(payload.from_location.clone()),
+        languages: Set(payload.languages.clone()),
+        is_pilot: Set(payload.is_pilot),
+        license_number: Set(payload.license_number.clone()),
+        verification_status: Set(drivers::VERIFICATION_PENDING.to_string()),
+        current_lat: Set(0.0),
+        current_lng: Set(0.0),
+        location_updated_at: Set(None),
+        // New drivers start offline; going online requires a verified phone.
+        availability_status: Set("offline".to_string()),
+        phone_verified_at: Set(None),
+        user_id: Set(Some(user.id)),
+        created_at: Set(Some(now)),
+        updated_at: Set(Some(now)),
+        ..Default::default()
     }
+    .insert(&txn)
+    .await
+    .map_err(database_error)?;
+
+    txn.commit().await.map_err(database_error)?;
+
+    if new_account {
+        send_verification_email(db.as_ref(), mailer.get_ref(), &user).await?;
+    }
+
+    Ok(HttpResponse::Created().json(json!({
+        "message": "Driver registered successfully!",
+        "driver_id": driver.id,
+        "user_id": user.id,
+        "email": driver.email,
+        "phone": driver.phone,
+        "created_at": now,
+        "updated_at": now
+    })))
 }
 
+/// Signs in with the account's credentials and issues driver-role tokens.
+#[post("/drivers/login")]
+async fn driver_login(
+    req: HttpRequest,
+    credentials: web::Json<LoginRequest>,
+    db: web::Data<DatabaseConnection>,
+) -> Result<HttpResponse, Error> {
+    let user = match authenticate_login(db.as_ref(), &req, &credentials.email, &credentials.password).await? {
+        Ok(user) => user,
+        Err(rejection) => return Ok(rejection),
+    };
+
+    let driver = driverentity::Entity::find()
+        .filter(driverentity::Column::UserId.eq(user.id))
+        .one(db.as_ref())
+        .await
+        .map_err(database_error)?;
+
+    if driver.is_none() {
+        return Ok(HttpResponse::Forbidden().json(json!({
+            "error": "No driver profile is linked to this account"
+        })));
+    }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct Claims {
-    sub: String,
-    exp: usize,
+    let client = ClientInfo::from_request(&req, credentials.device.clone());
+    complete_login(db.as_ref(), &user, Role::Driver, client).await
 }
 
 #[get("/drivers")]
@@ -427,8 +2785,456 @@ async fn get_drivers() -> impl Responder {
 
 pub fn configure(cfg: &mut web::ServiceConfig) {
     cfg.service(get_drivers);
-    cfg.service(create_driver);
+    cfg.service(register_driver);
+    cfg.service(driver_login);
+    cfg.service(send_driver_phone_otp);
+    cfg.service(verify_driver_phone);
+    cfg.service(update_driver_availability);
+    cfg.service(update_driver_location);
+}
+
+#[derive(Deserialize)]
+pub struct UpdateAvailability {
+    pub availability_status: String,
+}
+
+const AVAILABILITY_STATUSES: [&str; 3] = ["available", "busy", "offline"];
+
+#[put("/drivers/{id}/availability", wrap = "RequireRole::any_of(&[Role::Driver])")]
+async fn update_driver_availability(
+    auth: AuthenticatedUser,
+    driver_id: web::Path<i32>,
+    payload: web::Json<UpdateAvailability>,
+    db: web::Data<DatabaseConnection>,
+) -> Result<HttpResponse, Error> {
+    let driver_id = driver_id.into_inner();
+    auth.ensure_driver(driver_id)?;
+
+    let status = payload.availability_status.as_str();
+    if !AVAILABILITY_STATUSES.contains(&status) {
+        return Ok(HttpResponse::BadRequest().json(json!({
+            "error": "availability_status must be one of available, busy, offline"
+        })));
+    }
+
+    let driver = match driverentity::Entity::find_by_id(driver_id)
+        .one(db.as_ref())
+        .await
+        .map_err(database_error)?
+    {
+        Some(driver) => driver,
+        None => return Ok(HttpResponse::NotFound().json(json!({"error": "Driver not found"}))),
+    };
+
+    if status == "available" && driver.phone_verified_at.is_none() {
+        return Ok(HttpResponse::Forbidden().json(json!({
+            "error": "Verify your phone number before going online"
+        })));
+    }
+    if status == "available" && driver.location_updated_at.is_none() {
+        return Ok(HttpResponse::Conflict().json(json!({
+            "error": "Share your location before going online"
+        })));
+    }
+
+    let mut active_driver: driverentity::ActiveModel = driver.into();
+    active_driver.availability_status = Set(status.to_string());
+    active_driver.updated_at = Set(Some(Utc::now().naive_utc()));
+    let updated = active_driver.update(db.as_ref()).await.map_err(database_error)?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "message": "Availability updated",
+        "driver_id": updated.id,
+        "availability_status": updated.availability_status
+    })))
+}
+
+#[derive(Deserialize)]
+pub struct UpdateLocation {
+    pub lat: f64,
+    pub lng: f64,
+}
+
+/// Position reports from the driver app; dispatch ranks drivers by the latest one.
+#[put("/drivers/{id}/location", wrap = "RequireRole::any_of(&[Role::Driver])")]
+async fn update_driver_location(
+    auth: AuthenticatedUser,
+    driver_id: web::Path<i32>,
+    payload: web::Json<UpdateLocation>,
+    db: web::Data<DatabaseConnection>,
+) -> Result<HttpResponse, Error> {
+    let driver_id = driver_id.into_inner();
+    auth.ensure_driver(driver_id)?;
+
+    if !geo::is_valid_coordinate(payload.lat, payload.lng) {
+        return Ok(HttpResponse::BadRequest().json(json!({"error": "Invalid coordinates"})));
+    }
+
+    match drivers::update_location(db.as_ref(), driver_id, payload.lat, payload.lng)
+        .await
+        .map_err(database_error)?
+    {
+        Some(updated) => Ok(HttpResponse::Ok().json(json!({
+            "driver_id": updated.id,
+            "current_lat": updated.current_lat,
+            "current_lng": updated.current_lng,
+            "location_updated_at": updated.location_updated_at
+        }))),
+        None => Ok(HttpResponse::NotFound().json(json!({"error": "Driver not found"}))),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct DriverVerificationRequest {
+    pub verification_status: String,
+}
+
+/// Records the result of reviewing a driver's documents. Only verified drivers are
+/// offered rides.
+#[put("/admin/drivers/{id}/verification", wrap = "RequireRole::any_of(&[Role::Admin])")]
+async fn set_driver_verification(
+    auth: AuthenticatedUser,
+    db: web::Data<DatabaseConnection>,
+    driver_id: web::Path<i32>,
+    payload: web::Json<DriverVerificationRequest>,
+) -> Result<HttpResponse, Error> {
+    let driver_id = driver_id.into_inner();
+    let status = payload.verification_status.as_str();
+    if !drivers::VERIFICATION_STATUSES.contains(&status) {
+        return Ok(HttpResponse::BadRequest().json(json!({
+            "error": format!("verification_status must be one of {}", drivers::VERIFICATION_STATUSES.join(", "))
+        })));
+    }
+
+    match drivers::set_verification(db.as_ref(), driver_id, status)
+        .await
+        .map_err(database_error)?
+    {
+        Some(updated) => {
+            info!("Driver {} marked {} by admin {}", driver_id, status, auth.user.id);
+            Ok(HttpResponse::Ok().json(json!({
+                "driver_id": updated.id,
+                "verification_status": updated.verification_status
+            })))
+        }
+        None => Ok(HttpResponse::NotFound().json(json!({"error": "Driver not found"}))),
+    }
+}
+
+
+//phone verification
 
+
+const OTP_TTL_MINUTES: i64 = 5;
+const OTP_MAX_ATTEMPTS: i32 = 5;
+const OTP_RESEND_COOLDOWN_SECONDS: i64 = 60;
+
+#[derive(Deserialize)]
+pub struct VerifyPhoneRequest {
+    pub code: String,
+}
+
+fn otp_hash(subject_type: &str, subject_id: i32, code: &str) -> String {
+    hash_token(&format!("{}:{}:{}", subject_type, subject_id, code))
+}
+
+async fn latest_phone_otp(
+    db: &DatabaseConnection,
+    subject_type: &str,
+    subject_id: i32,
+) -> Result<Option<phoneverification::Model>, Error> {
+    phoneverification::Entity::find()
+        .filter(phoneverification::Column::SubjectType.eq(subject_type))
+        .filter(phoneverification::Column::SubjectId.eq(subject_id))
+        .filter(phoneverification::Column::ConsumedAt.is_null())
+        .order_by_desc(phoneverification::Column::CreatedAt)
+        .one(db)
+        .await
+        .map_err(database_error)
+}
+
+/// Generates a new code for the subject, replacing any outstanding one, and texts it to `phone`.
+async fn send_phone_otp(
+    db: &DatabaseConnection,
+    sms: &dyn SmsSender,
+    subject_type: &str,
+    subject_id: i32,
+    phone: &str,
+) -> Result<HttpResponse, Error> {
+    if validate_phone(phone).is_err() {
+        return Ok(missing_phone_number());
+    }
+    let now = Utc::now().naive_utc();
+
+    if let Some(latest) = latest_phone_otp(db, subject_type, subject_id).await? {
+        let retry_at = latest.created_at + chrono::Duration::seconds(OTP_RESEND_COOLDOWN_SECONDS);
+        if retry_at > now {
+            return Ok(HttpResponse::TooManyRequests().json(json!({
+                "error": "Please wait before requesting another code",
+                "retry_after_seconds": (retry_at - now).num_seconds().max(1)
+            })));
+        }
+    }
+
+    phoneverification::Entity::update_many()
+        .col_expr(phoneverification::Column::ConsumedAt, Expr::value(now))
+        .filter(phoneverification::Column::SubjectType.eq(subject_type))
+        .filter(phoneverification::Column::SubjectId.eq(subject_id))
+        .filter(phoneverification::Column::ConsumedAt.is_null())
+        .exec(db)
+        .await
+        .map_err(database_error)?;
+
+    let code = format!("{:06}", rand::thread_rng().gen_range(0..1_000_000));
+    let otp = phoneverification::ActiveModel {
+        subject_type: Set(subject_type.to_string()),
+        subject_id: Set(subject_id),
+        phone: Set(phone.to_string()),
+        code_hash: Set(otp_hash(subject_type, subject_id, &code)),
+        attempts: Set(0),
+        expires_at: Set(now + chrono::Duration::minutes(OTP_TTL_MINUTES)),
+        consumed_at: Set(None),
+        created_at: Set(now),
+        ..Default::default()
+    };
+    otp.insert(db).await.map_err(database_error)?;
+
+    let message = format!(
+        "Your Arrively verification code is {}. It expires in {} minutes.",
+        code, OTP_TTL_MINUTES
+    );
+    if let Err(e) = sms.send(phone, &message).await {
+        error!("Failed to send verification SMS for {} {}: {}", subject_type, subject_id, e);
+        return Ok(HttpResponse::InternalServerError().json(json!({
+            "error": "Failed to send verification code"
+        })));
+    }
+
+    Ok(HttpResponse::Ok().json(json!({
+        "message": "Verification code sent",
+        "expires_in_seconds": OTP_TTL_MINUTES * 60
+    })))
+}
+
+/// Checks `code` against the subject's outstanding OTP, counting failed attempts.
+/// Returns `Ok(Err(response))` when the code is rejected.
+async fn check_phone_otp(
+    db: &DatabaseConnection,
+    subject_type: &str,
+    subject_id: i32,
+    phone: &str,
+    code: &str,
+) -> Result<Result<(), HttpResponse>, Error> {
+    let now = Utc::now().naive_utc();
+
+    let otp = match latest_phone_otp(db, subject_type, subject_id).await? {
+        Some(otp) if otp.phone == phone => otp,
+        _ => {
+            return Ok(Err(HttpResponse::BadRequest().json(json!({
+                "error": "No active verification code. Request a new one."
+            }))));
+        }
+    };
+
+    if otp.expires_at <= now {
+        return Ok(Err(HttpResponse::BadRequest().json(json!({
+            "error": "Verification code has expired. Request a new one."
+        }))));
+    }
+
+    if otp.attempts >= OTP_MAX_ATTEMPTS {
+        return Ok(Err(HttpResponse::TooManyRequests().json(json!({
+            "error": "Too many incorrect attempts. Request a new code."
+        }))));
+    }
+
+    if otp_hash(subject_type, subject_id, code.trim()) != otp.code_hash {
+        phoneverification::Entity::update_many()
+            .col_expr(
+                phoneverification::Column::Attempts,
+                Expr::col(phoneverification::Column::Attempts).add(1),
+            )
+            .filter(phoneverification::Column::Id.eq(otp.id))
+            .exec(db)
+            .await
+            .map_err(database_error)?;
+
+        return Ok(Err(HttpResponse::BadRequest().json(json!({
+            "error": "Incorrect verification code",
+            "attempts_remaining": (OTP_MAX_ATTEMPTS - otp.attempts - 1).max(0)
+        }))));
+    }
+
+    let consumed = phoneverification::Entity::update_many()
+        .col_expr(phoneverification::Column::ConsumedAt, Expr::value(now))
+        .filter(phoneverification::Column::Id.eq(otp.id))
+        .filter(phoneverification::Column::ConsumedAt.is_null())
+        .exec(db)
+        .await
+        .map_err(database_error)?;
+    if consumed.rows_affected == 0 {
+        return Ok(Err(HttpResponse::BadRequest().json(json!({
+            "error": "No active verification code. Request a new one."
+        }))));
+    }
+
+    Ok(Ok(()))
+}
+
+fn missing_phone_number() -> HttpResponse {
+    HttpResponse::BadRequest().json(json!({
+        "error": "Add a phone number to your account first"
+    }))
+}
+
+#[derive(Deserialize)]
+pub struct UpdatePhoneRequest {
+    pub phone_number: String,
+}
+
+/// Sets the caller's phone number. A changed number has to be verified again.
+#[put("/me/phone")]
+async fn update_my_phone(
+    auth: AuthenticatedUser,
+    db: web::Data<DatabaseConnection>,
+    payload: web::Json<UpdatePhoneRequest>,
+) -> Result<HttpResponse, Error> {
+    let phone = payload.phone_number.trim();
+    if validate_phone(phone).is_err() {
+        return Ok(HttpResponse::BadRequest().json(json!({"error": "Invalid phone number format"})));
+    }
+    if auth.user.phone_number.as_deref() == Some(phone) {
+        return Ok(HttpResponse::Ok().json(json!({"message": "Phone number unchanged"})));
+    }
+
+    let taken = UserEntity::find()
+        .filter(userentity::Column::PhoneNumber.eq(phone))
+        .filter(userentity::Column::Id.ne(auth.user.id))
+        .one(db.as_ref())
+        .await
+        .map_err(database_error)?;
+    if taken.is_some() {
+        return Ok(HttpResponse::Conflict().json(json!({"error": "Phone number already exists"})));
+    }
+
+    UserEntity::update_many()
+        .col_expr(userentity::Column::PhoneNumber, Expr::value(phone))
+        .col_expr(userentity::Column::PhoneVerifiedAt, Expr::value(Option::<chrono::NaiveDateTime>::None))
+        .filter(userentity::Column::Id.eq(auth.user.id))
+        .exec(db.as_ref())
+        .await
+        .map_err(database_error)?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "message": "Phone number updated. Verify it with the code we send you.",
+        "phone_number": phone
+    })))
+}
+
+#[post("/me/phone/otp")]
+async fn send_my_phone_otp(
+    auth: AuthenticatedUser,
+    db: web::Data<DatabaseConnection>,
+    sms: web::Data<dyn SmsSender>,
+) -> Result<HttpResponse, Error> {
+    if auth.user.phone_verified_at.is_some() {
+        return Ok(HttpResponse::Conflict().json(json!({
+            "error": "Phone number is already verified"
+        })));
+    }
+
+    match auth.user.phone_number.as_deref() {
+        Some(phone) => send_phone_otp(db.as_ref(), sms.get_ref(), "user", auth.user.id, phone).await,
+        None => Ok(missing_phone_number()),
+    }
+}
+
+#[post("/me/phone/verify")]
+async fn verify_my_phone(
+    auth: AuthenticatedUser,
+    db: web::Data<DatabaseConnection>,
+    payload: web::Json<VerifyPhoneRequest>,
+) -> Result<HttpResponse, Error> {
+    let phone = match auth.user.phone_number.as_deref() {
+        Some(phone) => phone,
+        None => return Ok(missing_phone_number()),
+    };
+    if let Err(rejection) = check_phone_otp(db.as_ref(), "user", auth.user.id, phone, &payload.code).await? {
+        return Ok(rejection);
+    }
+
+    UserEntity::update_many()
+        .col_expr(userentity::Column::PhoneVerifiedAt, Expr::value(Utc::now().naive_utc()))
+        .filter(userentity::Column::Id.eq(auth.user.id))
+        .exec(db.as_ref())
+        .await
+        .map_err(database_error)?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "message": "Phone number verified successfully"
+    })))
+}
+
+#[post("/drivers/{id}/phone/otp")]
+async fn send_driver_phone_otp(
+    auth: AuthenticatedUser,
+    driver_id: web::Path<i32>,
+    db: web::Data<DatabaseConnection>,
+    sms: web::Data<dyn SmsSender>,
+) -> Result<HttpResponse, Error> {
+    let driver_id = driver_id.into_inner();
+    auth.ensure_driver(driver_id)?;
+
+    let driver = match driverentity::Entity::find_by_id(driver_id)
+        .one(db.as_ref())
+        .await
+        .map_err(database_error)?
+    {
+        Some(driver) => driver,
+        None => return Ok(HttpResponse::NotFound().json(json!({"error": "Driver not found"}))),
+    };
+
+    if driver.phone_verified_at.is_some() {
+        return Ok(HttpResponse::Conflict().json(json!({
+            "error": "Phone number is already verified"
+        })));
+    }
+
+    send_phone_otp(db.as_ref(), sms.get_ref(), "driver", driver.id, &driver.phone).await
+}
+
+#[post("/drivers/{id}/phone/verify")]
+async fn verify_driver_phone(
+    auth: AuthenticatedUser,
+    driver_id: web::Path<i32>,
+    db: web::Data<DatabaseConnection>,
+    payload: web::Json<VerifyPhoneRequest>,
+) -> Result<HttpResponse, Error> {
+    let driver_id = driver_id.into_inner();
+    auth.ensure_driver(driver_id)?;
+
+    let driver = match driverentity::Entity::find_by_id(driver_id)
+        .one(db.as_ref())
+        .await
+        .map_err(database_error)?
+    {
+        Some(driver) => driver,
+        None => return Ok(HttpResponse::NotFound().json(json!({"error": "Driver not found"}))),
+    };
+
+    if let Err(rejection) =
+        check_phone_otp(db.as_ref(), "driver", driver.id, &driver.phone, &payload.code).await?
+    {
+        return Ok(rejection);
+    }
+
+    let mut active_driver: driverentity::ActiveModel = driver.into();
+    active_driver.phone_verified_at = Set(Some(Utc::now().naive_utc()));
+    active_driver.update(db.as_ref()).await.map_err(database_error)?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "message": "Phone number verified successfully"
+    })))
 }
 
 
@@ -437,7 +3243,9 @@ pub fn configure(cfg: &mut web::ServiceConfig) {
 
 #[derive(Debug, Deserialize)]
 pub struct CreateVehicle {
-    pub driver_id: i32,
+    /// Defaults to the caller's own driver profile; only admins may name another driver.
+    #[serde(default)]
+    pub driver_id: Option<i32>,
     pub vehicle_type: String,
     pub style: String,
     pub make: String,
@@ -475,16 +3283,25 @@ pub async fn get_vehicle(db: web::Data<DatabaseConnection>, vehicle_id: web::Pat
     }
 }
 
-#[post("/vehicles")]
+#[post("/vehicles", wrap = "RequireRole::any_of(&[Role::Driver])")]
 pub async fn create_vehicle(
+    auth: AuthenticatedUser,
     db: web::Data<DatabaseConnection>,
     vehicle_data: web::Json<CreateVehicle>,
 ) -> impl Responder {
     eprintln!("Received vehicle data: {:?}", vehicle_data);
 
+    let driver_id = match vehicle_data.driver_id.or(auth.driver.as_ref().map(|driver| driver.id)) {
+        Some(driver_id) => driver_id,
+        None => return HttpResponse::BadRequest().json(json!({"error": "driver_id is required"})),
+    };
+    if let Err(e) = auth.ensure_driver(driver_id) {
+        return e.error_response();
+    }
+
     // Check if a vehicle for the same driver already exists
     let existing_vehicle = vehicleentity::Entity::find()
-        .filter(vehicleentity::Column::DriverId.eq(vehicle_data.driver_id))
+        .filter(vehicleentity::Column::DriverId.eq(driver_id))
         .one(db.get_ref())
         .await;
 
@@ -501,7 +3318,7 @@ pub async fn create_vehicle(
 
     // Create a new vehicle
     let new_vehicle = vehicleentity::ActiveModel {
-        driver_id: Set(vehicle_data.driver_id),
+        driver_id: Set(driver_id),
         vehicle_type: Set(vehicle_data.vehicle_type.clone()),
         style: Set(vehicle_data.style.clone()),
         make: Set(vehicle_data.make.clone()),
@@ -531,9 +3348,27 @@ pub async fn create_vehicle(
     }
 }
 
-#[delete("/vehicles/{id}")]
-pub async fn delete_vehicle(db: web::Data<DatabaseConnection>, vehicle_id: web::Path<i32>) -> impl Responder {
-    match vehicleentity::Entity::delete_by_id(vehicle_id.into_inner()).exec(db.get_ref()).await {
+#[delete("/vehicles/{id}", wrap = "RequireRole::any_of(&[Role::Driver])")]
+pub async fn delete_vehicle(
+    auth: AuthenticatedUser,
+    db: web::Data<DatabaseConnection>,
+    vehicle_id: web::Path<i32>,
+) -> impl Responder {
+    let vehicle_id = vehicle_id.into_inner();
+    match vehicleentity::Entity::find_by_id(vehicle_id).one(db.get_ref()).await {
+        Ok(Some(vehicle)) => {
+            if let Err(e) = auth.ensure_driver(vehicle.driver_id) {
+                return e.error_response();
+            }
+        }
+        Ok(None) => return HttpResponse::NotFound().body("Vehicle not found"),
+        Err(e) => {
+            eprintln!("Failed to fetch vehicle: {:?}", e);
+            return HttpResponse::InternalServerError().body(format!("Failed to fetch vehicle: {:?}", e));
+        }
+    }
+
+    match vehicleentity::Entity::delete_by_id(vehicle_id).exec(db.get_ref()).await {
         Ok(_) => HttpResponse::Ok().body("Vehicle deleted successfully"),
         Err(e) => {
             eprintln!("Failed to delete vehicle: {:?}", e); 
@@ -550,9 +3385,8 @@ pub async fn delete_vehicle(db: web::Data<DatabaseConnection>, vehicle_id: web::
 
 #[derive(Debug, Deserialize)]
 pub struct CreateRide {
-    pub user_id: i32,
-    pub driver_id: i32,
-    pub vehicle_id: i32,
+    #[serde(default)]
+    pub user_id: Option<i32>,
     pub ride_type: String,
     pub vehicle_type: String,
     pub pickup_location: String,
@@ -562,21 +3396,13 @@ pub struct CreateRide {
     pub dropoff_lat: f64,
     pub dropoff_lng: f64,
     pub scheduled_time: Option<ChronoDateTime<Utc>>,
-    pub start_time: Option<ChronoDateTime<Utc>>,
-    pub end_time: Option<ChronoDateTime<Utc>>,
-    pub status: String,
-    pub distance_fare: Decimal,
-    pub time_fare: Decimal,
-    pub tip_amount: Option<Decimal>,
-    pub total_amount: Decimal,
-    pub rating: Option<i16>,
-    pub review: Option<String>,
-    pub cancel_reason: Option<String>,
-    pub payment_status: String,
     pub payment_id: i32,
+    /// Token from `POST /rides/quote`. When present the quoted fare is locked in.
+    #[serde(default)]
+    pub quote_token: Option<String>,
 }
 
-#[get("/rides")]
+#[get("/rides", wrap = "RequireRole::any_of(&[Role::SupportAgent])")]
 pub async fn get_all_rides(db: web::Data<DatabaseConnection>) -> impl Responder {
     match RideEntity::find().all(db.get_ref()).await {
         Ok(ride_list) => HttpResponse::Ok().json(ride_list),
@@ -587,10 +3413,38 @@ pub async fn get_all_rides(db: web::Data<DatabaseConnection>) -> impl Responder
     }
 }
 
+#[get("/me/rides")]
+pub async fn get_my_rides(auth: AuthenticatedUser, db: web::Data<DatabaseConnection>) -> impl Responder {
+    match RideEntity::find()
+        .filter(rideentity::Column::UserId.eq(auth.user.id))
+        .all(db.get_ref())
+        .await
+    {
+        Ok(ride_list) => HttpResponse::Ok().json(ride_list),
+        Err(e) => {
+            eprintln!("Failed to fetch rides: {:?}", e); 
+            HttpResponse::InternalServerError().body(format!("Failed to fetch rides: {:?}", e))
+        }
+    }
+}
+
 #[get("/rides/{id}")]
-pub async fn get_ride(db: web::Data<DatabaseConnection>, ride_id: web::Path<i32>) -> impl Responder {
+pub async fn get_ride(auth: AuthenticatedUser, db: web::Data<DatabaseConnection>, ride_id: web::Path<i32>) -> impl Responder {
     match RideEntity::find_by_id(ride_id.into_inner()).one(db.get_ref()).await {
-        Ok(Some(ride)) => HttpResponse::Ok().json(ride),
+        Ok(Some(ride)) => {
+            // Rides of deleted accounts have no owner; only staff and the assigned
+            // driver can see them.
+            let access = match ride.user_id {
+                Some(owner_id) => auth.ensure_owner_or_role(owner_id, &[Role::SupportAgent]),
+                None if auth.has_any_role(&[Role::SupportAgent]) => Ok(()),
+                None => Err(AuthError::Forbidden),
+            }
+            .or_else(|e| ride.driver_id.map_or(Err(e), |driver_id| auth.ensure_driver(driver_id)));
+            match access {
+                Ok(()) => HttpResponse::Ok().json(ride),
+                Err(e) => e.error_response(),
+            }
+        }
         Ok(None) => HttpResponse::NotFound().body("Ride not found"),
         Err(e) => {
             eprintln!("Failed to fetch ride: {:?}", e); 
@@ -599,16 +3453,100 @@ pub async fn get_ride(db: web::Data<DatabaseConnection>, ride_id: web::Path<i32>
     }
 }
 
+fn quote_already_used() -> HttpResponse {
+    HttpResponse::Conflict().json(serde_json::json!({
+        "error": "Quote has already been used; request a new one"
+    }))
+}
+
 #[post("/rides")]
 pub async fn create_ride(
+    auth: AuthenticatedUser,
     db: web::Data<DatabaseConnection>,
     ride_data: web::Json<CreateRide>,
 ) -> impl Responder {
-    // Create a new ride
+    let user_id = match auth.resolve_user_id(ride_data.user_id) {
+        Ok(user_id) => user_id,
+        Err(e) => return e.error_response(),
+    };
+
+    // Unverified accounts can sign in, but must confirm their email before booking.
+    if auth.user.email_verified_at.is_none() {
+        return HttpResponse::Forbidden().json(serde_json::json!({
+            "error": "Please verify your email address before booking a ride"
+        }));
+    }
+
+    if !geo::is_valid_coordinate(ride_data.pickup_lat, ride_data.pickup_lng)
+        || !geo::is_valid_coordinate(ride_data.dropoff_lat, ride_data.dropoff_lng)
+    {
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "Pickup and dropoff must be valid coordinates"
+        }));
+    }
+    let pickup = Coordinates { lat: ride_data.pickup_lat, lng: ride_data.pickup_lng };
+    let dropoff = Coordinates { lat: ride_data.dropoff_lat, lng: ride_data.dropoff_lng };
+
+    let quote = match ride_data.quote_token.as_deref() {
+        Some(token) => {
+            let claims = match quotes::verify_quote(token, auth.user.id) {
+                Some(claims) => claims,
+                None => {
+                    return HttpResponse::BadRequest().json(serde_json::json!({
+                        "error": "Quote is invalid or has expired; request a new one"
+                    }))
+                }
+            };
+            let quote = &claims.quote;
+            if !quote.matches_trip(pickup, dropoff) {
+                return HttpResponse::BadRequest().json(serde_json::json!({
+                    "error": "Quote was issued for a different trip"
+                }));
+            }
+            if !quote.covers(&ride_data.vehicle_type) {
+                return HttpResponse::BadRequest().json(serde_json::json!({
+                    "error": format!("Quote does not cover vehicle type '{}'", ride_data.vehicle_type)
+                }));
+            }
+            Some(claims)
+        }
+        None => None,
+    };
+
+    let txn = match db.begin().await {
+        Ok(txn) => txn,
+        Err(e) => return database_error(e).error_response(),
+    };
+    let candidate = match dispatch::next_candidate(&txn, &ride_data.vehicle_type, pickup, &std::collections::HashSet::new()).await {
+        Ok(Some(candidate)) => candidate,
+        Ok(None) => {
+            return HttpResponse::Conflict().json(serde_json::json!({
+                "error": format!("No {} drivers are available nearby; please try again shortly", ride_data.vehicle_type)
+            }))
+        }
+        Err(e) => return database_error(e).error_response(),
+    };
+
+    let mut quoted_fare = None;
+    if let Some(claims) = &quote {
+        match rideentity::Entity::find()
+            .filter(rideentity::Column::QuoteId.eq(claims.jti.as_str()))
+            .count(&txn)
+            .await
+        {
+            Ok(0) => {}
+            Ok(_) => return quote_already_used(),
+            Err(e) => return database_error(e).error_response(),
+        }
+        // Charged exactly as signed, whichever driver ends up taking the ride.
+        quoted_fare = claims.quote.locked_fare(&ride_data.vehicle_type);
+    }
+
+    // Create a new ride; the driver is assigned when they accept the offer.
     let new_ride = rideentity::ActiveModel {
-        user_id: Set(ride_data.user_id),
-        driver_id: Set(ride_data.driver_id),
-        vehicle_id: Set(ride_data.vehicle_id),
+        user_id: Set(Some(user_id)),
+        driver_id: Set(None),
+        vehicle_id: Set(None),
         ride_type: Set(ride_data.ride_type.clone()),
         vehicle_type: Set(ride_data.vehicle_type.clone()),
         pickup_location: Set(ride_data.pickup_location.clone()),
@@ -617,35 +3555,49 @@ pub async fn create_ride(
         dropoff_location: Set(ride_data.dropoff_location.clone()),
         dropoff_lat: Set(ride_data.dropoff_lat),
         dropoff_lng: Set(ride_data.dropoff_lng),
-        scheduled_time: Set(ride_data.scheduled_time.clone()),
-        start_time: Set(ride_data.start_time.clone()),
-        end_time: Set(ride_data.end_time.clone()),
-        status: Set(ride_data.status.clone()),
-        distance_fare: Set(ride_data.distance_fare),
-        time_fare: Set(ride_data.time_fare),
-        tip_amount: Set(ride_data.tip_amount),
-        total_amount: Set(ride_data.total_amount),
-        rating: Set(ride_data.rating),
-        review: Set(ride_data.review.clone()),
-        cancel_reason: Set(ride_data.cancel_reason.clone()),
-        payment_status: Set(ride_data.payment_status.clone()),
+        scheduled_time: Set(ride_data.scheduled_time),
+        status: Set(RideStatus::Requested),
+        // Priced by the fare engine when the ride completes.
+        distance_fare: Set(Decimal::ZERO),
+        time_fare: Set(Decimal::ZERO),
+        tip_amount: Set(None),
+        total_amount: Set(Decimal::ZERO),
+        quoted_fare: Set(quoted_fare),
+        quote_id: Set(quote.map(|claims| claims.jti)),
+        // Rated, reviewed, cancelled and paid through the ride's lifecycle, never at booking.
+        rating: Set(None),
+        review: Set(None),
+        cancel_reason: Set(None),
+        payment_status: Set(rides::PAYMENT_PENDING.to_string()),
         payment_id: Set(ride_data.payment_id),
         ..Default::default() 
     };
 
-    match new_ride.insert(db.get_ref()).await {
-        Ok(ride) => HttpResponse::Created().json(serde_json::json!({
-            "message": "Ride created successfully",
-            "ride": ride
-        })),
+    let ride = match new_ride.insert(&txn).await {
+        Ok(ride) => ride,
+        // Another booking with the same quote got in first.
+        Err(e) if matches!(e.sql_err(), Some(SqlErr::UniqueConstraintViolation(_))) => return quote_already_used(),
         Err(e) => {
             eprintln!("Failed to create ride: {:?}", e); 
-            HttpResponse::InternalServerError().body(format!("Failed to create ride: {:?}", e))
+            return HttpResponse::InternalServerError().body(format!("Failed to create ride: {:?}", e));
         }
+    };
+    let offer = match offers::create_offer(&txn, &ride, &candidate).await {
+        Ok(offer) => offer,
+        Err(e) => return database_error(e).error_response(),
+    };
+    if let Err(e) = txn.commit().await {
+        return database_error(e).error_response();
     }
+
+    HttpResponse::Created().json(serde_json::json!({
+        "message": "Ride created successfully",
+        "ride": ride,
+        "offer_expires_at": offer.expires_at
+    }))
 }
 
-#[delete("/rides/{id}")]
+#[delete("/rides/{id}", wrap = "RequireRole::any_of(&[Role::Admin])")]
 pub async fn delete_ride(db: web::Data<DatabaseConnection>, ride_id: web::Path<i32>) -> impl Responder {
     match RideEntity::delete_by_id(ride_id.into_inner()).exec(db.get_ref()).await {
         Ok(_) => HttpResponse::Ok().body("Ride deleted successfully"),
@@ -656,10 +3608,435 @@ pub async fn delete_ride(db: web::Data<DatabaseConnection>, ride_id: web::Path<i
     }
 }
 
+#[derive(Deserialize)]
+pub struct QuoteRequest {
+    pub pickup_lat: f64,
+    pub pickup_lng: f64,
+    pub dropoff_lat: f64,
+    pub dropoff_lng: f64,
+    /// Limits the quote to one vehicle type; all available types otherwise.
+    pub vehicle_type: Option<String>,
+}
+
+/// Estimates the trip and quotes a fare range per available vehicle type. The signed
+/// `quote_token` can be passed to `POST /rides` to lock the fare in.
+#[post("/rides/quote")]
+async fn quote_ride(
+    auth: AuthenticatedUser,
+    db: web::Data<DatabaseConnection>,
+    router: web::Data<dyn RoutingProvider>,
+    payload: web::Json<QuoteRequest>,
+) -> Result<HttpResponse, Error> {
+    if !geo::is_valid_coordinate(payload.pickup_lat, payload.pickup_lng)
+        || !geo::is_valid_coordinate(payload.dropoff_lat, payload.dropoff_lng)
+    {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "Pickup and dropoff must be valid coordinates"
+        })));
+    }
+
+    let pickup = Coordinates { lat: payload.pickup_lat, lng: payload.pickup_lng };
+    let dropoff = Coordinates { lat: payload.dropoff_lat, lng: payload.dropoff_lng };
+    let estimate = match router.estimate(pickup, dropoff).await {
+        Ok(estimate) if estimate.distance_km <= fares::MAX_TRIP_DISTANCE_KM => estimate,
+        Ok(_) => {
+            return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+                "error": format!("Trips longer than {} km cannot be quoted", fares::MAX_TRIP_DISTANCE_KM)
+            })));
+        }
+        Err(e) => {
+            error!("Routing estimate failed: {}", e);
+            return Ok(HttpResponse::ServiceUnavailable().json(serde_json::json!({
+                "error": "Unable to estimate this trip right now"
+            })));
+        }
+    };
+
+    let vehicle_type = payload
+        .vehicle_type
+        .as_deref()
+        .map(str::trim)
+        .filter(|vehicle_type| !vehicle_type.is_empty());
+    let quote = quotes::build_quote(db.as_ref(), pickup, dropoff, estimate, vehicle_type)
+        .await
+        .map_err(database_error)?;
+    let (quote_token, expires_at) = quotes::sign_quote(quote.clone(), auth.user.id).map_err(|e| {
+        error!("Failed to sign quote: {}", e);
+        actix_web::error::ErrorInternalServerError("Failed to create quote")
+    })?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "quote_token": quote_token,
+        "expires_at": expires_at,
+        "distance_km": quote.distance_km,
+        "duration_minutes": quote.duration_minutes,
+        "fares": quote.fares
+    })))
+}
+
+#[derive(Deserialize)]
+pub struct CancelRideRequest {
+    pub reason: Option<String>,
+}
+
+/// Checks that the caller may perform `action` on `ride`. Only the assigned driver
+/// moves a ride forward; the rider, the driver and support staff may cancel it.
+fn ensure_can_transition(auth: &AuthenticatedUser, ride: &rideentity::Model, action: RideAction) -> Result<(), AuthError> {
+    match action {
+        RideAction::Cancel => {
+            let is_rider = ride.user_id == Some(auth.user.id);
+            let is_driver = auth.driver.as_ref().is_some_and(|driver| Some(driver.id) == ride.driver_id);
+            if is_rider || is_driver || auth.has_any_role(&[Role::SupportAgent]) {
+                Ok(())
+            } else {
+                Err(AuthError::Forbidden)
+            }
+        }
+        _ => match ride.driver_id {
+            Some(driver_id) => auth.ensure_driver(driver_id),
+            None => Err(AuthError::Forbidden),
+        },
+    }
+}
+
+fn ride_transition_conflict(action: RideAction, status: RideStatus) -> HttpResponse {
+    HttpResponse::Conflict().json(serde_json::json!({
+        "error": format!("Cannot {} a ride that is {}", action.as_str(), status.to_value()),
+        "status": status
+    }))
+}
+
+/// Loads the ride and checks that the caller may perform `action` and that it is legal
+/// from the ride's current status. Returns the ride and the status it moves to.
+async fn prepare_ride_transition(
+    auth: &AuthenticatedUser,
+    db: &DatabaseConnection,
+    ride_id: i32,
+    action: RideAction,
+) -> Result<Result<(rideentity::Model, RideStatus), HttpResponse>, Error> {
+    let ride = match RideEntity::find_by_id(ride_id).one(db).await.map_err(database_error)? {
+        Some(ride) => ride,
+        None => return Ok(Err(HttpResponse::NotFound().json(serde_json::json!({"error": "Ride not found"})))),
+    };
+    ensure_can_transition(auth, &ride, action)?;
+
+    match action.transition(ride.status) {
+        Some(next) => Ok(Ok((ride, next))),
+        None => Ok(Err(ride_transition_conflict(action, ride.status))),
+    }
+}
+
+/// Someone else moved the ride first; report the status it is in now.
+async fn lost_ride_transition(
+    db: &DatabaseConnection,
+    ride: &rideentity::Model,
+    action: RideAction,
+) -> Result<HttpResponse, Error> {
+    let current = RideEntity::find_by_id(ride.id)
+        .one(db)
+        .await
+        .map_err(database_error)?
+        .map_or(ride.status, |ride| ride.status);
+    Ok(ride_transition_conflict(action, current))
+}
+
+async fn transition_ride(
+    auth: &AuthenticatedUser,
+    db: &DatabaseConnection,
+    ride_id: i32,
+    action: RideAction,
+    cancel_reason: Option<String>,
+) -> Result<HttpResponse, Error> {
+    let (ride, next) = match prepare_ride_transition(auth, db, ride_id, action).await? {
+        Ok(prepared) => prepared,
+        Err(rejection) => return Ok(rejection),
+    };
+
+    // A cancelled ride's offers are withdrawn with it, or the driver would stay busy
+    // until the offer expired.
+    let txn = db.begin().await.map_err(database_error)?;
+    let updated = match rides::apply_transition(&txn, ride.id, ride.status, next, cancel_reason)
+        .await
+        .map_err(database_error)?
+    {
+        Some(updated) => updated,
+        None => {
+            drop(txn);
+            return lost_ride_transition(db, &ride, action).await;
+        }
+    };
+    if next == RideStatus::Cancelled {
+        offers::withdraw_pending(&txn, ride.id).await.map_err(database_error)?;
+    }
+    txn.commit().await.map_err(database_error)?;
+
+    info!("Ride {} moved from {} to {} by user {}", ride.id, ride.status.to_value(), next.to_value(), auth.user.id);
+    Ok(HttpResponse::Ok().json(updated))
+}
+
+/// Rides currently offered to the calling driver, awaiting an answer.
+#[get("/me/ride-offers", wrap = "RequireRole::any_of(&[Role::Driver])")]
+async fn get_my_ride_offers(auth: AuthenticatedUser, db: web::Data<DatabaseConnection>) -> Result<HttpResponse, Error> {
+    let Some(driver) = auth.driver.as_ref() else {
+        return Ok(HttpResponse::Ok().json(Vec::<serde_json::Value>::new()));
+    };
+
+    let pending = rideoffer::Entity::find()
+        .filter(rideoffer::Column::DriverId.eq(driver.id))
+        .filter(offers::outstanding())
+        .find_also_related(RideEntity)
+        .all(db.as_ref())
+        .await
+        .map_err(database_error)?;
+
+    let pending: Vec<_> = pending
+        .into_iter()
+        .filter_map(|(offer, ride)| {
+            let ride = ride?;
+            Some(serde_json::json!({
+                "offer_id": offer.id,
+                "ride_id": ride.id,
+                "expires_at": offer.expires_at,
+                "pickup_distance_km": offer.pickup_distance_km,
+                "vehicle_type": ride.vehicle_type,
+                "pickup_location": ride.pickup_location,
+                "pickup_lat": ride.pickup_lat,
+                "pickup_lng": ride.pickup_lng,
+                "dropoff_location": ride.dropoff_location,
+                "dropoff_lat": ride.dropoff_lat,
+                "dropoff_lng": ride.dropoff_lng,
+                "quoted_fare": ride.quoted_fare
+            }))
+        })
+        .collect();
+    Ok(HttpResponse::Ok().json(pending))
+}
+
+/// The calling driver's unanswered offer for `ride_id`.
+async fn find_pending_offer(
+    auth: &AuthenticatedUser,
+    db: &DatabaseConnection,
+    ride_id: i32,
+) -> Result<Result<rideoffer::Model, HttpResponse>, Error> {
+    let no_offer = || HttpResponse::Conflict().json(serde_json::json!({
+        "error": "You have no open offer for this ride"
+    }));
+    let Some(driver) = auth.driver.as_ref() else {
+        return Ok(Err(no_offer()));
+    };
+
+    let offer = rideoffer::Entity::find()
+        .filter(rideoffer::Column::RideId.eq(ride_id))
+        .filter(rideoffer::Column::DriverId.eq(driver.id))
+        .filter(offers::outstanding())
+        .one(db)
+        .await
+        .map_err(database_error)?;
+    Ok(offer.ok_or_else(no_offer))
+}
+
+/// Accepts the ride offered to the calling driver, who becomes the ride's driver.
+#[post("/rides/{id}/accept", wrap = "RequireRole::any_of(&[Role::Driver])")]
+async fn accept_ride(
+    auth: AuthenticatedUser,
+    db: web::Data<DatabaseConnection>,
+    ride_id: web::Path<i32>,
+) -> Result<HttpResponse, Error> {
+    let ride_id = ride_id.into_inner();
+    let ride = match RideEntity::find_by_id(ride_id).one(db.as_ref()).await.map_err(database_error)? {
+        Some(ride) => ride,
+        None => return Ok(HttpResponse::NotFound().json(serde_json::json!({"error": "Ride not found"}))),
+    };
+    if RideAction::Accept.transition(ride.status).is_none() {
+        return Ok(ride_transition_conflict(RideAction::Accept, ride.status));
+    }
+    let offer = match find_pending_offer(&auth, db.as_ref(), ride_id).await? {
+        Ok(offer) => offer,
+        Err(rejection) => return Ok(rejection),
+    };
+
+    let txn = db.begin().await.map_err(database_error)?;
+    match offers::accept(&txn, &offer).await.map_err(database_error)? {
+        Some(accepted) => {
+            txn.commit().await.map_err(database_error)?;
+            info!("Ride {} accepted by driver {}", ride_id, offer.driver_id);
+            Ok(HttpResponse::Ok().json(accepted))
+        }
+        None => {
+            drop(txn);
+            Ok(HttpResponse::Conflict().json(serde_json::json!({
+                "error": "This offer has expired or the ride is no longer available"
+            })))
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct DeclineRideRequest {
+    pub reason: String,
+}
+
+/// Declines the ride offered to the calling driver; it is offered to the next driver.
+#[post("/rides/{id}/decline", wrap = "RequireRole::any_of(&[Role::Driver])")]
+async fn decline_ride(
+    auth: AuthenticatedUser,
+    db: web::Data<DatabaseConnection>,
+    mailer: web::Data<dyn Mailer>,
+    ride_id: web::Path<i32>,
+    payload: web::Json<DeclineRideRequest>,
+) -> Result<HttpResponse, Error> {
+    let reason = payload.reason.trim();
+    if reason.is_empty() {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "A reason is required to decline a ride"
+        })));
+    }
+
+    let offer = match find_pending_offer(&auth, db.as_ref(), ride_id.into_inner()).await? {
+        Ok(offer) => offer,
+        Err(rejection) => return Ok(rejection),
+    };
+
+    let txn = db.begin().await.map_err(database_error)?;
+    if !offers::decline(&txn, &offer, reason).await.map_err(database_error)? {
+        drop(txn);
+        return Ok(HttpResponse::Conflict().json(serde_json::json!({
+            "error": "This offer has already expired"
+        })));
+    }
+    let abandoned = offers::offer_next(&txn, offer.ride_id).await.map_err(database_error)?;
+    txn.commit().await.map_err(database_error)?;
+
+    info!("Ride {} declined by driver {}: {}", offer.ride_id, offer.driver_id, reason);
+    if let Some(ride) = abandoned {
+        offers::notify_no_drivers(db.as_ref(), mailer.as_ref(), &ride).await;
+    }
+    Ok(HttpResponse::Ok().json(serde_json::json!({"message": "Ride declined"})))
+}
+
+#[post("/rides/{id}/arrive", wrap = "RequireRole::any_of(&[Role::Driver])")]
+async fn arrive_for_ride(
+    auth: AuthenticatedUser,
+    db: web::Data<DatabaseConnection>,
+    ride_id: web::Path<i32>,
+) -> Result<HttpResponse, Error> {
+    transition_ride(&auth, db.as_ref(), ride_id.into_inner(), RideAction::Arrive, None).await
+}
+
+#[post("/rides/{id}/start", wrap = "RequireRole::any_of(&[Role::Driver])")]
+async fn start_ride(
+    auth: AuthenticatedUser,
+    db: web::Data<DatabaseConnection>,
+    ride_id: web::Path<i32>,
+) -> Result<HttpResponse, Error> {
+    transition_ride(&auth, db.as_ref(), ride_id.into_inner(), RideAction::Start, None).await
+}
+
+#[derive(Deserialize)]
+pub struct CompleteRideRequest {
+    /// Distance driven as measured by the driver app, capped by `fares::billable_distance_km`.
+    /// Falls back to the straight-line distance between pickup and dropoff when absent.
+    pub distance_km: Option<f64>,
+}
+
+/// Completes the ride and prices it from the vehicle's rate card, the measured distance
+/// and the time between start and completion.
+#[post("/rides/{id}/complete", wrap = "RequireRole::any_of(&[Role::Driver])")]
+async fn complete_ride(
+    auth: AuthenticatedUser,
+    db: web::Data<DatabaseConnection>,
+    ride_id: web::Path<i32>,
+    payload: Option<web::Json<CompleteRideRequest>>,
+) -> Result<HttpResponse, Error> {
+    let action = RideAction::Complete;
+    let (ride, next) = match prepare_ride_transition(&auth, db.as_ref(), ride_id.into_inner(), action).await? {
+        Ok(prepared) => prepared,
+        Err(rejection) => return Ok(rejection),
+    };
+
+    let reported_km = payload.and_then(|payload| payload.distance_km);
+    if reported_km.is_some_and(|distance| !distance.is_finite() || !(0.0..=fares::MAX_TRIP_DISTANCE_KM).contains(&distance)) {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": format!("distance_km must be between 0 and {}", fares::MAX_TRIP_DISTANCE_KM)
+        })));
+    }
+    let straight_line_km = geo::great_circle_km(ride.pickup_lat, ride.pickup_lng, ride.dropoff_lat, ride.dropoff_lng);
+    let distance_km = fares::billable_distance_km(reported_km, straight_line_km);
+    if reported_km.is_some_and(|reported| reported > distance_km) {
+        warn!("Ride {}: reported distance {:?} km capped to {:.2} km", ride.id, reported_km, distance_km);
+    }
+
+    let vehicle = match ride.vehicle_id {
+        Some(vehicle_id) => vehicleentity::Entity::find_by_id(vehicle_id)
+            .one(db.as_ref())
+            .await
+            .map_err(database_error)?,
+        None => None,
+    };
+    let rates = match vehicle.as_ref().and_then(fares::RateCard::from_vehicle) {
+        Some(rates) => rates,
+        None => {
+            error!("Ride {} cannot be priced: vehicle {:?} has no rate card", ride.id, ride.vehicle_id);
+            return Ok(HttpResponse::Conflict().json(serde_json::json!({
+                "error": "The ride's vehicle has no rate card; contact support"
+            })));
+        }
+    };
+
+    let txn = db.begin().await.map_err(database_error)?;
+    let completed = match rides::apply_transition(&txn, ride.id, ride.status, next, None)
+        .await
+        .map_err(database_error)?
+    {
+        Some(completed) => completed,
+        None => {
+            drop(txn);
+            return lost_ride_transition(db.as_ref(), &ride, action).await;
+        }
+    };
+
+    let duration = match (completed.start_time, completed.end_time) {
+        (Some(start), Some(end)) => end - start,
+        _ => chrono::Duration::zero(),
+    };
+    let fare = fares::calculate(rates, distance_km, duration);
+    let (completed, breakdown) = fares::record_fare(&txn, &completed, &fare).await.map_err(database_error)?;
+    txn.commit().await.map_err(database_error)?;
+
+    info!("Ride {} completed by user {}, fare {}", ride.id, auth.user.id, fare.total);
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "ride": completed,
+        "fare": breakdown
+    })))
+}
+
+#[post("/rides/{id}/cancel")]
+async fn cancel_ride(
+    auth: AuthenticatedUser,
+    db: web::Data<DatabaseConnection>,
+    ride_id: web::Path<i32>,
+    payload: Option<web::Json<CancelRideRequest>>,
+) -> Result<HttpResponse, Error> {
+    let reason = payload
+        .and_then(|payload| payload.into_inner().reason)
+        .map(|reason| reason.trim().to_string())
+        .filter(|reason| !reason.is_empty());
+    transition_ride(&auth, db.as_ref(), ride_id.into_inner(), RideAction::Cancel, reason).await
+}
+
 pub fn config(cfg: &mut web::ServiceConfig) {
     cfg.service(get_all_rides)
+        .service(get_my_rides)
         .service(get_ride)
+        .service(quote_ride)
         .service(create_ride)
+        .service(get_my_ride_offers)
+        .service(accept_ride)
+        .service(decline_ride)
+        .service(arrive_for_ride)
+        .service(start_ride)
+        .service(complete_ride)
+        .service(cancel_ride)
         .service(delete_ride);
 }
 
@@ -674,7 +4051,7 @@ struct NewCity {
     pub name: String,
 }
 
-#[post("/cities")]
+#[post("/cities", wrap = "RequireRole::any_of(&[Role::Admin])")]
 async fn add_cities(
     cities: web::Json<Vec<NewCity>>, 
     db: web::Data<DatabaseConnection>
@@ -707,7 +4084,8 @@ async fn get_cities(db: web::Data<DatabaseConnection>) -> impl Responder {
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct CreateSettings {
-    pub user_id: i32,
+    #[serde(default)]
+    pub user_id: Option<i32>,
     pub language: String,
     pub notifications_enabled: bool,
     pub dark_mode: bool,
@@ -726,13 +4104,19 @@ pub struct UpdateSettings {
 
 #[post("/settings")]
 async fn create_settings(
+    auth: AuthenticatedUser,
     db: web::Data<DatabaseConnection>,
     payload: web::Json<CreateSettings>,
 ) -> impl Responder {
     info!("Received request to create settings: {:?}", payload);
 
+    let user_id = match auth.resolve_user_id(payload.user_id) {
+        Ok(user_id) => user_id,
+        Err(e) => return e.error_response(),
+    };
+
     let new_setting = settings::ActiveModel {
-        user_id: Set(payload.user_id),
+        user_id: Set(user_id),
         language: Set(payload.language.clone()),
         notifications_enabled: Set(payload.notifications_enabled),
         dark_mode: Set(payload.dark_mode),
@@ -772,6 +4156,7 @@ async fn create_settings(
 /// Get a settings entry by ID
 #[get("/settings/{id}")]
 async fn get_settings(
+    auth: AuthenticatedUser,
     db: web::Data<DatabaseConnection>,
     id: web::Path<i32>,
 ) -> impl Responder {
@@ -779,7 +4164,10 @@ async fn get_settings(
     info!("Fetching settings for ID: {}", id);
 
     match settings::Entity::find_by_id(id).one(db.get_ref()).await {
-        Ok(Some(setting)) => HttpResponse::Ok().json(setting),
+        Ok(Some(setting)) => match auth.ensure_owner(setting.user_id) {
+            Ok(()) => HttpResponse::Ok().json(setting),
+            Err(e) => e.error_response(),
+        },
         Ok(None) => {
             info!("Settings not found for ID: {}", id);
             HttpResponse::NotFound().json(format!("No settings found for ID: {}", id))
@@ -791,9 +4179,63 @@ async fn get_settings(
     }
 }
 
+/// Get the caller's own settings
+#[get("/me/settings")]
+async fn get_my_settings(
+    auth: AuthenticatedUser,
+    db: web::Data<DatabaseConnection>,
+) -> impl Responder {
+    match settings::Entity::find()
+        .filter(settings::Column::UserId.eq(auth.user.id))
+        .one(db.get_ref())
+        .await
+    {
+        Ok(Some(setting)) => HttpResponse::Ok().json(setting),
+        Ok(None) => HttpResponse::NotFound().json("No settings found for current user"),
+        Err(e) => {
+            error!("Database error fetching settings: {:?}", e);
+            HttpResponse::InternalServerError().json("Database error retrieving settings.")
+        }
+    }
+}
+
+async fn apply_settings_update(
+    db: &DatabaseConnection,
+    setting: settings::Model,
+    payload: &UpdateSettings,
+) -> HttpResponse {
+    let id = setting.id;
+    let mut active_model: settings::ActiveModel = setting.into();
+
+    if let Some(language) = &payload.language {
+        active_model.language = Set(language.clone());
+    }
+    if let Some(notifications_enabled) = payload.notifications_enabled {
+        active_model.notifications_enabled = Set(notifications_enabled);
+    }
+    if let Some(dark_mode) = payload.dark_mode {
+        active_model.dark_mode = Set(dark_mode);
+    }
+    if let Some(currency) = &payload.currency {
+        active_model.currency = Set(currency.clone());
+    }
+
+    match active_model.update(db).await {
+        Ok(updated) => {
+            info!("Successfully updated settings for ID: {}", id);
+            HttpResponse::Ok().json(updated)
+        }
+        Err(e) => {
+            error!("Database error updating settings: {:?}", e);
+            HttpResponse::InternalServerError().json("Database error updating settings.")
+        }
+    }
+}
+
 /// Update a settings entry
 #[put("/settings/{id}")]
 async fn update_settings(
+    auth: AuthenticatedUser,
     db: web::Data<DatabaseConnection>,
     id: web::Path<i32>,
     payload: web::Json<UpdateSettings>,
@@ -803,31 +4245,10 @@ async fn update_settings(
 
     match settings::Entity::find_by_id(id).one(db.get_ref()).await {
         Ok(Some(setting)) => {
-            let mut active_model: settings::ActiveModel = setting.into();
-
-            if let Some(language) = &payload.language {
-                active_model.language = Set(language.clone());
-            }
-            if let Some(notifications_enabled) = payload.notifications_enabled {
-                active_model.notifications_enabled = Set(notifications_enabled);
-            }
-            if let Some(dark_mode) = payload.dark_mode {
-                active_model.dark_mode = Set(dark_mode);
-            }
-            if let Some(currency) = &payload.currency {
-                active_model.currency = Set(currency.clone());
-            }
-
-            match active_model.update(db.get_ref()).await {
-                Ok(updated) => {
-                    info!("Successfully updated settings for ID: {}", id);
-                    HttpResponse::Ok().json(updated)
-                }
-                Err(e) => {
-                    error!("Database error updating settings: {:?}", e);
-                    HttpResponse::InternalServerError().json("Database error updating settings.")
-                }
+            if let Err(e) = auth.ensure_owner(setting.user_id) {
+                return e.error_response();
             }
+            apply_settings_update(db.get_ref(), setting, &payload).await
         }
         Ok(None) => {
             info!("Settings not found for ID: {}", id);
@@ -840,15 +4261,53 @@ async fn update_settings(
     }
 }
 
+/// Update the caller's own settings
+#[put("/me/settings")]
+async fn update_my_settings(
+    auth: AuthenticatedUser,
+    db: web::Data<DatabaseConnection>,
+    payload: web::Json<UpdateSettings>,
+) -> impl Responder {
+    match settings::Entity::find()
+        .filter(settings::Column::UserId.eq(auth.user.id))
+        .one(db.get_ref())
+        .await
+    {
+        Ok(Some(setting)) => apply_settings_update(db.get_ref(), setting, &payload).await,
+        Ok(None) => HttpResponse::NotFound().json("No settings found for current user"),
+        Err(e) => {
+            error!("Database error fetching settings for update: {:?}", e);
+            HttpResponse::InternalServerError().json("Database error retrieving settings for update.")
+        }
+    }
+}
+
 /// Delete a settings entry
 #[delete("/settings/{id}")]
 async fn delete_settings(
+    auth: AuthenticatedUser,
     db: web::Data<DatabaseConnection>,
     id: web::Path<i32>,
 ) -> impl Responder {
     let id = id.into_inner();
     info!("Deleting settings for ID: {}", id);
 
+    match settings::Entity::find_by_id(id).one(db.get_ref()).await {
+        Ok(Some(setting)) => {
+            if let Err(e) = auth.ensure_owner(setting.user_id) {
+                return e.error_response();
+            }
+        }
+        Ok(None) => {
+            info!("No settings found for ID: {}", id);
+            return HttpResponse::NotFound().json(format!("No settings found for ID: {}", id));
+        }
+        Err(e) => {
+            error!("Database error fetching settings: {:?}", e);
+            return HttpResponse::InternalServerError().json("Database error retrieving settings.");
+        }
+    }
+
     match settings::Entity::delete_by_id(id).exec(db.get_ref()).await {
         Ok(delete_result) if delete_result.rows_affected > 0 => {
             info!("Successfully deleted settings for ID: {}", id);
@@ -876,11 +4335,17 @@ use crate::entities::helpsupport::{Entity as HelpSupportEntity, ActiveModel};
 /// Create a new support ticket
 #[post("/tickets")]
 async fn create_ticket(
+    auth: AuthenticatedUser,
     db: web::Data<DatabaseConnection>,
     payload: web::Json<NewTicketRequest>,
 ) -> impl Responder {
+    let user_id = match auth.resolve_user_id(payload.user_id) {
+        Ok(user_id) => user_id,
+        Err(e) => return e.error_response(),
+    };
+
     let new_ticket = entities::helpsupport::ActiveModel {
-        user_id: Set(payload.user_id),
+        user_id: Set(user_id),
         subject: Set(payload.subject.clone()),
         description: Set(payload.description.clone()),
         status: Set(payload.status.clone()),
@@ -911,7 +4376,7 @@ async fn create_ticket(
 
 
 /// Get all support tickets
-#[get("/tickets")]
+#[get("/tickets", wrap = "RequireRole::any_of(&[Role::SupportAgent])")]
 async fn get_tickets(db: web::Data<DatabaseConnection>) -> impl Responder {
     match HelpSupportEntity::find().all(db.get_ref()).await {
         Ok(tickets) => HttpResponse::Ok().json(tickets),
@@ -922,11 +4387,30 @@ async fn get_tickets(db: web::Data<DatabaseConnection>) -> impl Responder {
     }
 }
 
+/// Get the caller's own support tickets
+#[get("/me/tickets")]
+async fn get_my_tickets(auth: AuthenticatedUser, db: web::Data<DatabaseConnection>) -> impl Responder {
+    match HelpSupportEntity::find()
+        .filter(entities::helpsupport::Column::UserId.eq(auth.user.id))
+        .all(db.get_ref())
+        .await
+    {
+        Ok(tickets) => HttpResponse::Ok().json(tickets),
+        Err(e) => {
+            error!("Failed to fetch support tickets: {}", e);
+            HttpResponse::InternalServerError().body("Failed to fetch support tickets")
+        }
+    }
+}
+
 /// Get a support ticket by ID
 #[get("/tickets/{id}")]
-async fn get_ticket(db: web::Data<DatabaseConnection>, id: web::Path<i32>) -> impl Responder {
+async fn get_ticket(auth: AuthenticatedUser, db: web::Data<DatabaseConnection>, id: web::Path<i32>) -> impl Responder {
     match HelpSupportEntity::find_by_id(id.into_inner()).one(db.get_ref()).await {
-        Ok(Some(ticket)) => HttpResponse::Ok().json(ticket),
+        Ok(Some(ticket)) => match auth.ensure_owner_or_role(ticket.user_id, &[Role::SupportAgent]) {
+            Ok(()) => HttpResponse::Ok().json(ticket),
+            Err(e) => e.error_response(),
+        },
         Ok(None) => HttpResponse::NotFound().body("Support ticket not found"),
         Err(e) => {
             error!("Failed to fetch support ticket: {}", e);
@@ -936,7 +4420,7 @@ async fn get_ticket(db: web::Data<DatabaseConnection>, id: web::Path<i32>) -> im
 }
 
 /// Update a support ticket by ID
-#[put("/tickets/{id}")]
+#[put("/tickets/{id}", wrap = "RequireRole::any_of(&[Role::SupportAgent])")]
 async fn update_ticket(
     db: web::Data<DatabaseConnection>, 
     id: web::Path<i32>, 
@@ -946,8 +4430,8 @@ async fn update_ticket(
 
     match entities::helpsupport::Entity::find_by_id(ticket_id).one(db.get_ref()).await {
         Ok(Some(existing_ticket)) => {
+            // The ticket stays with the user who opened it; any `user_id` in the body is ignored.
             let mut active_ticket: entities::helpsupport::ActiveModel = existing_ticket.into();
-            active_ticket.user_id = Set(ticket.user_id);
             active_ticket.subject = Set(ticket.subject.clone());
             active_ticket.description = Set(ticket.description.clone());
             active_ticket.status = Set(ticket.status.clone());
@@ -983,7 +4467,7 @@ async fn update_ticket(
 }
 
 /// Delete a support ticket by ID
-#[delete("/tickets/{id}")]
+#[delete("/tickets/{id}", wrap = "RequireRole::any_of(&[Role::SupportAgent])")]
 async fn delete_ticket(db: web::Data<DatabaseConnection>, id: web::Path<i32>) -> impl Responder {
     let ticket_id = id.into_inner();
 
@@ -1016,7 +4500,8 @@ use crate::entities::recentlocation;
 
 #[derive(Deserialize)]
 pub struct NewRecentLocationRequest {
-    pub user_id: i32,
+    #[serde(default)]
+    pub user_id: Option<i32>,
     pub location_name: String,
     pub address: String,
     pub lat: f64,
@@ -1026,13 +4511,18 @@ pub struct NewRecentLocationRequest {
 
 #[post("/recent-locations")]
 async fn add_recent_location(
+    auth: AuthenticatedUser,
     db: web::Data<DatabaseConnection>,
     payload: web::Json<NewRecentLocationRequest>,  
 ) -> impl Responder {
+    let user_id = match auth.resolve_user_id(payload.user_id) {
+        Ok(user_id) => user_id,
+        Err(e) => return e.error_response(),
+    };
     let now = Utc::now().naive_utc(); 
 
     let new_location = recentlocation::ActiveModel {
-        user_id: Set(payload.user_id),
+        user_id: Set(user_id),
         location_name: Set(payload.location_name.clone()),
         address: Set(payload.address.clone()),
         lat: Set(payload.lat),
@@ -1065,11 +4555,23 @@ async fn add_recent_location(
 
 
 #[get("/recent-locations/{user_id}")]
-async fn get_recent_locations(db: web::Data<DatabaseConnection>, user_id: web::Path<i32>) -> impl Responder {
+async fn get_recent_locations(auth: AuthenticatedUser, db: web::Data<DatabaseConnection>, user_id: web::Path<i32>) -> impl Responder {
     let user_id = user_id.into_inner();
+    if let Err(e) = auth.ensure_owner(user_id) {
+        return e.error_response();
+    }
+    recent_locations_for(db.get_ref(), user_id).await
+}
+
+#[get("/me/recent-locations")]
+async fn get_my_recent_locations(auth: AuthenticatedUser, db: web::Data<DatabaseConnection>) -> impl Responder {
+    recent_locations_for(db.get_ref(), auth.user.id).await
+}
+
+async fn recent_locations_for(db: &DatabaseConnection, user_id: i32) -> HttpResponse {
     match recentlocation::Entity::find()
         .filter(recentlocation::Column::UserId.eq(user_id))
-        .all(db.get_ref())
+        .all(db)
         .await
     {
         Ok(locations) if !locations.is_empty() => HttpResponse::Ok().json(locations),
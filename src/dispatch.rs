@@ -0,0 +1,355 @@
+//! Driver matching. Riders book a trip and a vehicle type; dispatch finds the nearest
+//! driver who can take it, and `crate::offers` offers them the ride.
+
+use sea_orm::{ColumnTrait, ConnectionTrait, DbErr, EntityTrait, QueryFilter, QuerySelect};
+use std::collections::{HashMap, HashSet};
+use crate::entities::{accountsuspension, driverentity, rideentity, rideoffer, vehicleentity};
+use crate::fares::RateCard;
+use crate::drivers;
+use crate::geo;
+use crate::offers;
+use crate::rides;
+use crate::routing::Coordinates;
+use crate::suspension;
+
+pub const DRIVER_AVAILABLE: &str = "available";
+pub const VEHICLE_ACTIVE: &str = "active";
+
+/// Drivers further than this from the pickup are not considered.
+pub const MAX_PICKUP_DISTANCE_KM: f64 = 25.0;
+
+#[derive(Debug, Clone)]
+pub struct Candidate {
+    pub driver: driverentity::Model,
+    pub vehicle: vehicleentity::Model,
+    pub pickup_distance_km: f64,
+}
+
+/// Whether the driver, going by their own row, may be offered rides: online, verified,
+/// and with a position the app has actually reported.
+pub fn is_dispatchable(driver: &driverentity::Model) -> bool {
+    driver.availability_status == DRIVER_AVAILABLE
+        && driver.verification_status == drivers::VERIFICATION_VERIFIED
+        && driver.location_updated_at.is_some()
+}
+
+/// Drivers who could take a trip from `pickup` in a `vehicle_type`, nearest first,
+/// leaving out those in `exclude`.
+///
+/// A candidate is available, verified, not suspended, not on a ride and not holding
+/// another offer, and has an active vehicle of that type with a rate card.
+pub async fn find_candidates<C: ConnectionTrait>(
+    conn: &C,
+    vehicle_type: &str,
+    pickup: Coordinates,
+    exclude: &HashSet<i32>,
+) -> Result<Vec<Candidate>, DbErr> {
+    let drivers = driverentity::Entity::find()
+        .filter(driverentity::Column::AvailabilityStatus.eq(DRIVER_AVAILABLE))
+        .filter(driverentity::Column::VerificationStatus.eq(drivers::VERIFICATION_VERIFIED))
+        .filter(driverentity::Column::LocationUpdatedAt.is_not_null())
+        .all(conn)
+        .await?;
+    if drivers.is_empty() {
+        return Ok(Vec::new());
+    }
+    let driver_ids: Vec<i32> = drivers.iter().map(|driver| driver.id).collect();
+
+    let suspended: HashSet<i32> = accountsuspension::Entity::find()
+        .filter(accountsuspension::Column::UserId.is_in(drivers.iter().filter_map(|driver| driver.user_id)))
+        .filter(suspension::in_effect())
+        .all(conn)
+        .await?
+        .into_iter()
+        .map(|suspension| suspension.user_id)
+        .collect();
+
+    let mut busy: HashSet<i32> = rideentity::Entity::find()
+        .filter(rideentity::Column::DriverId.is_in(driver_ids.clone()))
+        .filter(rideentity::Column::Status.is_in(rides::ACTIVE_STATUSES))
+        .all(conn)
+        .await?
+        .into_iter()
+        .filter_map(|ride| ride.driver_id)
+        .collect();
+    busy.extend(
+        rideoffer::Entity::find()
+            .filter(rideoffer::Column::DriverId.is_in(driver_ids.clone()))
+            .filter(offers::outstanding())
+            .all(conn)
+            .await?
+            .into_iter()
+            .map(|offer| offer.driver_id),
+    );
+
+    // Vehicles without a full rate card cannot be priced, so are never dispatched.
+    let mut vehicles: HashMap<i32, vehicleentity::Model> = HashMap::new();
+    for vehicle in vehicleentity::Entity::find()
+        .filter(vehicleentity::Column::DriverId.is_in(driver_ids))
+        .filter(vehicleentity::Column::VehicleType.eq(vehicle_type))
+        .filter(vehicleentity::Column::Status.eq(VEHICLE_ACTIVE))
+        .all(conn)
+        .await?
+    {
+        if RateCard::from_vehicle(&vehicle).is_some() {
+            vehicles.entry(vehicle.driver_id).or_insert(vehicle);
+        }
+    }
+
+    let mut candidates: Vec<Candidate> = drivers
+        .into_iter()
+        .filter(is_dispatchable)
+        .filter(|driver| !driver.user_id.is_some_and(|user_id| suspended.contains(&user_id)))
+        .filter(|driver| !busy.contains(&driver.id) && !exclude.contains(&driver.id))
+        .filter_map(|driver| {
+            let vehicle = vehicles.remove(&driver.id)?;
+            let pickup_distance_km =
+                geo::great_circle_km(driver.current_lat, driver.current_lng, pickup.lat, pickup.lng);
+            Some(Candidate { driver, vehicle, pickup_distance_km })
+        })
+        .filter(|candidate| candidate.pickup_distance_km <= MAX_PICKUP_DISTANCE_KM)
+        .collect();
+    candidates.sort_by(|a, b| a.pickup_distance_km.total_cmp(&b.pickup_distance_km));
+    Ok(candidates)
+}
+
+/// Locks the candidate's driver row and checks they are still free to take a ride.
+///
+/// Run inside the transaction that creates the offer: concurrent bookings then queue on
+/// the lock, and the loser sees the winner's offer and moves on to the next candidate.
+pub async fn claim_driver<C: ConnectionTrait>(conn: &C, candidate: &Candidate) -> Result<bool, DbErr> {
+    let driver = driverentity::Entity::find_by_id(candidate.driver.id)
+        .filter(driverentity::Column::AvailabilityStatus.eq(DRIVER_AVAILABLE))
+        .lock_exclusive()
+        .one(conn)
+        .await?;
+    match driver {
+        Some(driver) if is_dispatchable(&driver) && !suspension::is_driver_suspended(conn, &driver).await? => {}
+        _ => return Ok(false),
+    }
+
+    let on_ride = rideentity::Entity::find()
+        .filter(rideentity::Column::DriverId.eq(candidate.driver.id))
+        .filter(rideentity::Column::Status.is_in(rides::ACTIVE_STATUSES))
+        .one(conn)
+        .await?;
+    let offered = rideoffer::Entity::find()
+        .filter(rideoffer::Column::DriverId.eq(candidate.driver.id))
+        .filter(offers::outstanding())
+        .one(conn)
+        .await?;
+    Ok(on_ride.is_none() && offered.is_none())
+}
+
+/// Claims the nearest candidate for the trip who is not in `exclude`, or `None` if no
+/// driver can take it.
+pub async fn next_candidate<C: ConnectionTrait>(
+    conn: &C,
+    vehicle_type: &str,
+    pickup: Coordinates,
+    exclude: &HashSet<i32>,
+) -> Result<Option<Candidate>, DbErr> {
+    for candidate in find_candidates(conn, vehicle_type, pickup, exclude).await? {
+        if claim_driver(conn, &candidate).await? {
+            return Ok(Some(candidate));
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use sea_orm::{DatabaseBackend, MockDatabase, MockExecResult, Transaction, Value};
+
+    const PICKUP: Coordinates = Coordinates { lat: 52.5200, lng: 13.4050 };
+
+    /// A driver as `register_driver` creates them.
+    fn registered_driver() -> driverentity::Model {
+        driverentity::Model {
+            id: 7,
+            first_name: "Dana".to_string(),
+            last_name: "Driver".to_string(),
+            email: "dana@example.com".to_string(),
+            phone: "+4915112345678".to_string(),
+            photo: String::new(),
+            rating: 0.0,
+            total_rides: 0,
+            about_me: String::new(),
+            from_location: "Berlin".to_string(),
+            languages: vec!["de".to_string()],
+            is_pilot: false,
+            license_number: "B-123".to_string(),
+            verification_status: drivers::VERIFICATION_PENDING.to_string(),
+            current_lat: 0.0,
+            current_lng: 0.0,
+            location_updated_at: None,
+            availability_status: "offline".to_string(),
+            created_at: None,
+            updated_at: None,
+            phone_verified_at: Some(Utc::now().naive_utc()),
+            user_id: Some(11),
+        }
+    }
+
+    fn sedan(driver_id: i32) -> vehicleentity::Model {
+        let now = Utc::now().naive_utc();
+        vehicleentity::Model {
+            id: 3,
+            driver_id,
+            vehicle_type: "sedan".to_string(),
+            style: "saloon".to_string(),
+            make: "Skoda".to_string(),
+            model: "Octavia".to_string(),
+            year: 2022,
+            license_plate: "B-AR-1".to_string(),
+            passenger_capacity: 4,
+            photo: String::new(),
+            base_fare: Some(3.5),
+            per_minute_rate: Some(0.3),
+            per_kilometer_rate: Some(1.2),
+            status: VEHICLE_ACTIVE.to_string(),
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    fn updated() -> MockExecResult {
+        MockExecResult { last_insert_id: 0, rows_affected: 1 }
+    }
+
+    /// Query results for one `find_candidates` pass that sees only `driver`.
+    fn candidate_queries(db: MockDatabase, driver: &driverentity::Model) -> MockDatabase {
+        db.append_query_results([vec![driver.clone()]])
+            .append_query_results([Vec::<accountsuspension::Model>::new()])
+            .append_query_results([Vec::<rideentity::Model>::new()])
+            .append_query_results([Vec::<rideoffer::Model>::new()])
+            .append_query_results([vec![sedan(driver.id)]])
+    }
+
+    #[tokio::test]
+    async fn registered_driver_is_not_dispatched() {
+        let registered = registered_driver();
+        assert!(!is_dispatchable(&registered));
+
+        let db = candidate_queries(MockDatabase::new(DatabaseBackend::Postgres), &registered).into_connection();
+        let candidates = find_candidates(&db, "sedan", PICKUP, &HashSet::new()).await.unwrap();
+        assert!(candidates.is_empty());
+    }
+
+    /// Whether any statement in the log sets `column` to `value`.
+    fn sets(log: &[Transaction], column: &str, value: Value) -> bool {
+        log.iter().flat_map(|txn| txn.statements()).any(|statement| {
+            statement.sql.starts_with("UPDATE \"drivers\"")
+                && statement.sql.contains(&format!("\"{}\" = ", column))
+                && statement.values.as_ref().is_some_and(|values| values.0.contains(&value))
+        })
+    }
+
+    #[tokio::test]
+    async fn review_and_location_report_update_the_dispatch_fields() {
+        let db = MockDatabase::new(DatabaseBackend::Postgres)
+            .append_exec_results([updated()])
+            .append_query_results([Vec::<driverentity::Model>::new()])
+            .append_exec_results([updated()])
+            .append_query_results([Vec::<driverentity::Model>::new()])
+            .into_connection();
+
+        drivers::set_verification(&db, 7, drivers::VERIFICATION_VERIFIED).await.unwrap();
+        drivers::update_location(&db, 7, 52.5163, 13.3777).await.unwrap();
+
+        let log = db.into_transaction_log();
+        assert!(sets(&log, "verification_status", drivers::VERIFICATION_VERIFIED.into()));
+        assert!(sets(&log, "current_lat", 52.5163.into()));
+        assert!(sets(&log, "current_lng", 13.3777.into()));
+        assert!(log.iter().flat_map(|txn| txn.statements()).any(|statement| {
+            statement.sql.starts_with("UPDATE \"drivers\"") && statement.sql.contains("\"location_updated_at\" = ")
+        }));
+    }
+
+    #[test]
+    fn dispatch_requires_verification_location_and_availability() {
+        let registered = registered_driver();
+        let verified = driverentity::Model {
+            verification_status: drivers::VERIFICATION_VERIFIED.to_string(),
+            ..registered.clone()
+        };
+        let located = driverentity::Model {
+            location_updated_at: Some(Utc::now().naive_utc()),
+            ..verified.clone()
+        };
+        let online = driverentity::Model {
+            availability_status: DRIVER_AVAILABLE.to_string(),
+            ..located.clone()
+        };
+        let online_unverified = driverentity::Model {
+            verification_status: drivers::VERIFICATION_PENDING.to_string(),
+            ..online.clone()
+        };
+
+        assert!(!is_dispatchable(&registered));
+        assert!(!is_dispatchable(&verified));
+        assert!(!is_dispatchable(&located));
+        assert!(!is_dispatchable(&online_unverified));
+        assert!(is_dispatchable(&online));
+    }
+
+    #[tokio::test]
+    async fn nearby_online_driver_is_dispatched() {
+        let online = driverentity::Model {
+            verification_status: drivers::VERIFICATION_VERIFIED.to_string(),
+            current_lat: 52.5163,
+            current_lng: 13.3777,
+            location_updated_at: Some(Utc::now().naive_utc()),
+            availability_status: DRIVER_AVAILABLE.to_string(),
+            ..registered_driver()
+        };
+        let db = candidate_queries(MockDatabase::new(DatabaseBackend::Postgres), &online)
+            // claim_driver: the locked driver row, their suspensions, rides and offers.
+            .append_query_results([vec![online.clone()]])
+            .append_query_results([Vec::<accountsuspension::Model>::new()])
+            .append_query_results([Vec::<rideentity::Model>::new()])
+            .append_query_results([Vec::<rideoffer::Model>::new()])
+            .into_connection();
+
+        let candidate = next_candidate(&db, "sedan", PICKUP, &HashSet::new())
+            .await
+            .unwrap()
+            .expect("driver should be dispatched");
+        assert_eq!(candidate.driver.id, online.id);
+        assert_eq!(candidate.vehicle.id, 3);
+        assert!(candidate.pickup_distance_km < 3.0);
+    }
+
+    #[tokio::test]
+    async fn driver_beyond_pickup_radius_is_skipped() {
+        let far_away = driverentity::Model {
+            verification_status: drivers::VERIFICATION_VERIFIED.to_string(),
+            current_lat: 48.8566,
+            current_lng: 2.3522,
+            location_updated_at: Some(Utc::now().naive_utc()),
+            availability_status: DRIVER_AVAILABLE.to_string(),
+            ..registered_driver()
+        };
+        let db = candidate_queries(MockDatabase::new(DatabaseBackend::Postgres), &far_away).into_connection();
+
+        assert!(find_candidates(&db, "sedan", PICKUP, &HashSet::new()).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn excluded_driver_is_skipped() {
+        let online = driverentity::Model {
+            verification_status: drivers::VERIFICATION_VERIFIED.to_string(),
+            location_updated_at: Some(Utc::now().naive_utc()),
+            availability_status: DRIVER_AVAILABLE.to_string(),
+            current_lat: PICKUP.lat,
+            current_lng: PICKUP.lng,
+            ..registered_driver()
+        };
+        let db = candidate_queries(MockDatabase::new(DatabaseBackend::Postgres), &online).into_connection();
+
+        let exclude = HashSet::from([online.id]);
+        assert!(find_candidates(&db, "sedan", PICKUP, &exclude).await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,55 @@
+//! Driver verification and position reports, the driver state dispatch matches on.
+
+use chrono::Utc;
+use sea_orm::sea_query::Expr;
+use sea_orm::{ColumnTrait, ConnectionTrait, DbErr, EntityTrait, QueryFilter};
+use crate::entities::driverentity;
+
+pub const VERIFICATION_PENDING: &str = "pending";
+pub const VERIFICATION_VERIFIED: &str = "verified";
+pub const VERIFICATION_REJECTED: &str = "rejected";
+
+pub const VERIFICATION_STATUSES: [&str; 3] = [VERIFICATION_PENDING, VERIFICATION_VERIFIED, VERIFICATION_REJECTED];
+
+/// Records the outcome of an admin's document review. Returns `None` if there is no
+/// such driver.
+pub async fn set_verification<C: ConnectionTrait>(
+    conn: &C,
+    driver_id: i32,
+    status: &str,
+) -> Result<Option<driverentity::Model>, DbErr> {
+    let result = driverentity::Entity::update_many()
+        .col_expr(driverentity::Column::VerificationStatus, Expr::value(status))
+        .col_expr(driverentity::Column::UpdatedAt, Expr::value(Utc::now().naive_utc()))
+        .filter(driverentity::Column::Id.eq(driver_id))
+        .exec(conn)
+        .await?;
+    if result.rows_affected == 0 {
+        return Ok(None);
+    }
+
+    driverentity::Entity::find_by_id(driver_id).one(conn).await
+}
+
+/// Stores the position the driver app reported. Returns `None` if there is no such driver.
+pub async fn update_location<C: ConnectionTrait>(
+    conn: &C,
+    driver_id: i32,
+    lat: f64,
+    lng: f64,
+) -> Result<Option<driverentity::Model>, DbErr> {
+    let now = Utc::now().naive_utc();
+    let result = driverentity::Entity::update_many()
+        .col_expr(driverentity::Column::CurrentLat, Expr::value(lat))
+        .col_expr(driverentity::Column::CurrentLng, Expr::value(lng))
+        .col_expr(driverentity::Column::LocationUpdatedAt, Expr::value(now))
+        .col_expr(driverentity::Column::UpdatedAt, Expr::value(now))
+        .filter(driverentity::Column::Id.eq(driver_id))
+        .exec(conn)
+        .await?;
+    if result.rows_affected == 0 {
+        return Ok(None);
+    }
+
+    driverentity::Entity::find_by_id(driver_id).one(conn).await
+}
@@ -0,0 +1,38 @@
+use sea_orm::entity::prelude::*;
+use serde::{Serialize, Deserialize};
+
+/// A suspension of a rider or driver account. It is in effect while `status` is
+/// `active` and `expires_at` has not passed.
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "account_suspensions")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i32,
+    pub user_id: i32,
+    /// `active` or `lifted`.
+    pub status: String,
+    pub reason: String,
+    pub suspended_by: Option<i32>,
+    pub created_at: DateTime,
+    pub expires_at: Option<DateTime>,
+    pub lifted_by: Option<i32>,
+    pub lifted_at: Option<DateTime>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "crate::entities::userentity::Entity",
+        from = "Column::UserId",
+        to = "crate::entities::userentity::Column::Id"
+    )]
+    User,
+}
+
+impl Related<crate::entities::userentity::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::User.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
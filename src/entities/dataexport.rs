@@ -0,0 +1,41 @@
+use sea_orm::entity::prelude::*;
+use serde::{Serialize, Deserialize};
+
+/// A personal data export. The archive is built by the export worker and can be
+/// downloaded with the token until `expires_at`.
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "data_exports")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i32,
+    pub user_id: i32,
+    /// `pending`, `processing`, `ready`, `failed` or `expired`.
+    pub status: String,
+    #[serde(skip_serializing)]
+    pub archive: Option<Vec<u8>>,
+    #[sea_orm(unique)]
+    #[serde(skip_serializing)]
+    pub download_token_hash: Option<String>,
+    pub error: Option<String>,
+    pub requested_at: DateTime,
+    pub completed_at: Option<DateTime>,
+    pub expires_at: Option<DateTime>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "crate::entities::userentity::Entity",
+        from = "Column::UserId",
+        to = "crate::entities::userentity::Column::Id"
+    )]
+    User,
+}
+
+impl Related<crate::entities::userentity::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::User.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
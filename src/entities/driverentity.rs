@@ -21,15 +21,32 @@ pub struct Model {
     pub verification_status: String,
     pub current_lat: f64,
     pub current_lng: f64,
+    /// When the driver app last reported `current_lat`/`current_lng`; `None` until it has.
+    pub location_updated_at: Option<chrono::NaiveDateTime>,
     pub availability_status: String,
     #[sea_orm(default_value = "now()")]
     pub created_at: Option<chrono::NaiveDateTime>,  
     #[sea_orm(default_value = "now()", on_update = "now()")]
     pub updated_at: Option<chrono::NaiveDateTime>,  
-
+    pub phone_verified_at: Option<chrono::NaiveDateTime>,
+    #[sea_orm(unique)]
+    pub user_id: Option<i32>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
-pub enum Relation {}
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "crate::entities::userentity::Entity",
+        from = "Column::UserId",
+        to = "crate::entities::userentity::Column::Id"
+    )]
+    User,
+}
+
+impl Related<crate::entities::userentity::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::User.def()
+    }
+}
 
 impl ActiveModelBehavior for ActiveModel {}
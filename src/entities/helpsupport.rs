@@ -19,7 +19,8 @@ pub struct Model {
 
 #[derive(Serialize, Deserialize)]
 pub struct NewTicketRequest {
-    pub user_id: i32,
+    #[serde(default)]
+    pub user_id: Option<i32>,
     pub subject: String,
     pub description: String,
     pub status: String,
@@ -0,0 +1,33 @@
+use sea_orm::entity::prelude::*;
+use serde::{Serialize, Deserialize};
+
+/// One request made with an impersonation token. Rejected writes are logged too.
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "impersonation_audit_logs")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i32,
+    pub session_id: i32,
+    pub method: String,
+    pub path: String,
+    pub allowed: bool,
+    pub created_at: DateTime,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "crate::entities::impersonationsession::Entity",
+        from = "Column::SessionId",
+        to = "crate::entities::impersonationsession::Column::Id"
+    )]
+    Session,
+}
+
+impl Related<crate::entities::impersonationsession::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Session.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,32 @@
+use sea_orm::entity::prelude::*;
+use serde::{Serialize, Deserialize};
+
+/// An impersonation token minted by an admin. `token_id` is the token's `jti`.
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "impersonation_sessions")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i32,
+    #[sea_orm(unique)]
+    #[serde(skip_serializing)]
+    pub token_id: String,
+    pub actor_user_id: Option<i32>,
+    pub target_user_id: Option<i32>,
+    pub reason: String,
+    pub created_at: DateTime,
+    pub expires_at: DateTime,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(has_many = "crate::entities::impersonationauditlog::Entity")]
+    AuditLog,
+}
+
+impl Related<crate::entities::impersonationauditlog::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::AuditLog.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,44 @@
+use sea_orm::entity::prelude::*;
+use serde::{Serialize, Deserialize};
+
+/// A user's acceptance of one legal document version.
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "legal_acceptances")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i32,
+    pub user_id: i32,
+    pub document_id: i32,
+    pub ip_address: Option<String>,
+    pub accepted_at: DateTime,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "crate::entities::userentity::Entity",
+        from = "Column::UserId",
+        to = "crate::entities::userentity::Column::Id"
+    )]
+    User,
+    #[sea_orm(
+        belongs_to = "crate::entities::legaldocument::Entity",
+        from = "Column::DocumentId",
+        to = "crate::entities::legaldocument::Column::Id"
+    )]
+    Document,
+}
+
+impl Related<crate::entities::userentity::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::User.def()
+    }
+}
+
+impl Related<crate::entities::legaldocument::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Document.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
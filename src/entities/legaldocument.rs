@@ -0,0 +1,31 @@
+use sea_orm::entity::prelude::*;
+use serde::{Serialize, Deserialize};
+
+/// One version of a legal document. The current version of each kind is the latest
+/// one whose `published_at` has passed.
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "legal_documents")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i32,
+    /// `terms` or `privacy`.
+    pub kind: String,
+    pub version: String,
+    pub content: String,
+    pub published_at: DateTime,
+    pub created_at: DateTime,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(has_many = "crate::entities::legalacceptance::Entity")]
+    Acceptance,
+}
+
+impl Related<crate::entities::legalacceptance::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Acceptance.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,24 @@
+use sea_orm::entity::prelude::*;
+use serde::{Serialize, Deserialize};
+
+/// A one-time code sent to a user's or driver's phone. `subject_type` is
+/// `"user"` or `"driver"` and `subject_id` the matching row id.
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "phone_verifications")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i32,
+    pub subject_type: String,
+    pub subject_id: i32,
+    pub phone: String,
+    pub code_hash: String,
+    pub attempts: i32,
+    pub expires_at: DateTime,
+    pub consumed_at: Option<DateTime>,
+    pub created_at: DateTime,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
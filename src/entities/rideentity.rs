@@ -11,9 +11,11 @@ use serde::{Deserialize, Serialize}; // Make sure to import Serialize
 pub struct Model {
     #[sea_orm(primary_key)]
     pub id: i32,
-    pub user_id: i32,
-    pub driver_id: i32,
-    pub vehicle_id: i32,
+    /// `None` once the rider's account has been deleted.
+    pub user_id: Option<i32>,
+    /// `None` until a driver accepts the ride's offer.
+    pub driver_id: Option<i32>,
+    pub vehicle_id: Option<i32>,
     pub ride_type: String,
     pub vehicle_type: String,
     pub pickup_location: String,
@@ -25,11 +27,16 @@ pub struct Model {
     pub scheduled_time: Option<ChronoDateTime<Utc>>,
     pub start_time: Option<ChronoDateTime<Utc>>,
     pub end_time: Option<ChronoDateTime<Utc>>,
-    pub status: String,
+    pub status: RideStatus,
     pub distance_fare: Decimal,
     pub time_fare: Decimal,
     pub tip_amount: Option<Decimal>,
     pub total_amount: Decimal,
+    /// Fare locked in from a quote at booking. When set, the rider is charged this
+    /// instead of the metered fare.
+    pub quoted_fare: Option<Decimal>,
+    /// ID of the quote the ride was booked with. Unique, so a quote books one ride.
+    pub quote_id: Option<String>,
     pub rating: Option<i16>,
     pub review: Option<String>,
     pub cancel_reason: Option<String>,
@@ -39,6 +46,28 @@ pub struct Model {
     pub updated_at: ChronoDateTime<Utc>,
 }
 
+/// Where a ride is in its lifecycle. Transitions are defined in `crate::rides`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, EnumIter, DeriveActiveEnum, Serialize, Deserialize)]
+#[sea_orm(rs_type = "String", db_type = "String(StringLen::None)")]
+#[serde(rename_all = "snake_case")]
+pub enum RideStatus {
+    #[sea_orm(string_value = "requested")]
+    Requested,
+    #[sea_orm(string_value = "accepted")]
+    Accepted,
+    #[sea_orm(string_value = "driver_arrived")]
+    DriverArrived,
+    #[sea_orm(string_value = "in_progress")]
+    InProgress,
+    #[sea_orm(string_value = "completed")]
+    Completed,
+    #[sea_orm(string_value = "cancelled")]
+    Cancelled,
+    /// Every driver offered the ride declined or let the offer expire.
+    #[sea_orm(string_value = "no_drivers")]
+    NoDrivers,
+}
+
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
 pub enum Relation {
     #[sea_orm(belongs_to = "PaymentEntity", from = "Column::PaymentId", to = "super::payment::Column::Id")]
@@ -0,0 +1,42 @@
+use sea_orm::entity::prelude::*;
+use rust_decimal::Decimal;
+use serde::{Serialize, Deserialize};
+
+/// How a completed ride's fare was calculated: the vehicle's rates at completion and
+/// the measured distance and duration.
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "ride_fares")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i32,
+    #[sea_orm(unique)]
+    pub ride_id: i32,
+    pub vehicle_id: i32,
+    pub base_fare: Decimal,
+    pub per_kilometer_rate: Decimal,
+    pub per_minute_rate: Decimal,
+    pub distance_km: Decimal,
+    pub duration_minutes: Decimal,
+    pub distance_fare: Decimal,
+    pub time_fare: Decimal,
+    pub total: Decimal,
+    pub calculated_at: DateTime,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "crate::entities::rideentity::Entity",
+        from = "Column::RideId",
+        to = "crate::entities::rideentity::Column::Id"
+    )]
+    Ride,
+}
+
+impl Related<crate::entities::rideentity::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Ride.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,51 @@
+use sea_orm::entity::prelude::*;
+use serde::{Serialize, Deserialize};
+
+/// A ride offered to one driver. The driver has until `expires_at` to accept or
+/// decline; otherwise the offer expires and the ride goes to the next candidate.
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "ride_offers")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i32,
+    pub ride_id: i32,
+    pub driver_id: i32,
+    pub vehicle_id: i32,
+    /// `pending`, `accepted`, `declined`, `expired` or `withdrawn`.
+    pub status: String,
+    pub pickup_distance_km: f64,
+    pub decline_reason: Option<String>,
+    pub offered_at: DateTime,
+    pub expires_at: DateTime,
+    pub responded_at: Option<DateTime>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "crate::entities::rideentity::Entity",
+        from = "Column::RideId",
+        to = "crate::entities::rideentity::Column::Id"
+    )]
+    Ride,
+    #[sea_orm(
+        belongs_to = "crate::entities::driverentity::Entity",
+        from = "Column::DriverId",
+        to = "crate::entities::driverentity::Column::Id"
+    )]
+    Driver,
+}
+
+impl Related<crate::entities::rideentity::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Ride.def()
+    }
+}
+
+impl Related<crate::entities::driverentity::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Driver.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -11,7 +11,13 @@ pub struct Model {
     pub email: String,
     pub password: String,
     pub city: i32,  
-    pub phone_number: String,
+    /// `None` for social sign-ups until the user adds a number.
+    pub phone_number: Option<String>,
+    pub role: String,
+    pub email_verified_at: Option<DateTime>,
+    pub phone_verified_at: Option<DateTime>,
+    /// Set while a deletion request is in its grace period.
+    pub deletion_scheduled_for: Option<DateTime>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -4,8 +4,6 @@ use sea_orm::entity::prelude::*;
 use serde::{Deserialize, Serialize};
 #[derive(Clone, Debug, PartialEq, DeriveEntityModel,Serialize, Deserialize )]
 #[sea_orm(table_name = "vehicles")]
- 
-
 pub struct Model {
     #[sea_orm(primary_key)]
     pub id: i32,
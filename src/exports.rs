@@ -0,0 +1,233 @@
+//! Personal data exports. `GET /me/export` queues a request; `run_export_worker`
+//! builds the zip archive in the background and mails the user a download link.
+
+use chrono::{Duration, Utc};
+use log::{error, info};
+use sea_orm::sea_query::Expr;
+use sea_orm::{ActiveModelTrait, ColumnTrait, ConnectionTrait, DatabaseConnection, DbErr, EntityTrait, QueryFilter, QueryOrder, Set};
+use serde::Serialize;
+use std::io::{Cursor, Write};
+use std::sync::Arc;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+use crate::auth::{generate_secret_token, hash_token};
+use crate::entities::helpsupport;
+use crate::entities::{dataexport, payment, recentlocation, rideentity, settings, userentity, userprofile};
+use crate::mailer::{app_base_url, Email, Mailer};
+
+pub const STATUS_PENDING: &str = "pending";
+pub const STATUS_PROCESSING: &str = "processing";
+pub const STATUS_READY: &str = "ready";
+pub const STATUS_FAILED: &str = "failed";
+pub const STATUS_EXPIRED: &str = "expired";
+
+/// How long a finished export can be downloaded.
+pub const EXPORT_LINK_HOURS: i64 = 48;
+const WORKER_INTERVAL_SECONDS: u64 = 30;
+
+/// The link a download token is redeemed at.
+pub fn download_url(token: &str) -> String {
+    format!("{}/v1/exports/download?token={}", app_base_url(), token)
+}
+
+/// Replaces the export's download token and returns the new one. Links handed out
+/// earlier stop working; the expiry is unchanged.
+pub async fn issue_download_token<C: ConnectionTrait>(conn: &C, export_id: i32) -> Result<String, DbErr> {
+    let token = generate_secret_token();
+    dataexport::Entity::update_many()
+        .col_expr(dataexport::Column::DownloadTokenHash, Expr::value(hash_token(&token)))
+        .filter(dataexport::Column::Id.eq(export_id))
+        .exec(conn)
+        .await?;
+    Ok(token)
+}
+
+/// Keeps the last four digits of a card number, e.g. `************4242`.
+fn mask_card_number(number: &str) -> String {
+    let digits: Vec<char> = number.chars().filter(|c| c.is_ascii_digit()).collect();
+    let visible = digits.len().saturating_sub(4);
+    digits
+        .iter()
+        .enumerate()
+        .map(|(i, digit)| if i < visible { '*' } else { *digit })
+        .collect()
+}
+
+fn add_json<T: Serialize>(zip: &mut ZipWriter<Cursor<Vec<u8>>>, name: &str, value: &T) -> Result<(), String> {
+    let json = serde_json::to_vec_pretty(value).map_err(|e| format!("Failed to serialize {}: {}", name, e))?;
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+    zip.start_file(name, options)
+        .map_err(|e| format!("Failed to add {}: {}", name, e))?;
+    zip.write_all(&json).map_err(|e| format!("Failed to write {}: {}", name, e))
+}
+
+/// Collects everything held about `user_id` into a zip of JSON files.
+pub async fn build_archive(db: &DatabaseConnection, user_id: i32) -> Result<Vec<u8>, String> {
+    let db_error = |e: DbErr| format!("Database error: {}", e);
+
+    let user = userentity::Entity::find_by_id(user_id)
+        .one(db)
+        .await
+        .map_err(db_error)?
+        .ok_or_else(|| format!("User {} not found", user_id))?;
+    let mut user = serde_json::to_value(&user).map_err(|e| format!("Failed to serialize user: {}", e))?;
+    if let Some(fields) = user.as_object_mut() {
+        fields.remove("password");
+    }
+
+    let profile = userprofile::Entity::find()
+        .filter(userprofile::Column::UserId.eq(user_id))
+        .one(db)
+        .await
+        .map_err(db_error)?;
+    let settings = settings::Entity::find()
+        .filter(settings::Column::UserId.eq(user_id))
+        .all(db)
+        .await
+        .map_err(db_error)?;
+    let recent_locations = recentlocation::Entity::find()
+        .filter(recentlocation::Column::UserId.eq(user_id))
+        .order_by_desc(recentlocation::Column::LastUsed)
+        .all(db)
+        .await
+        .map_err(db_error)?;
+    let rides = rideentity::Entity::find()
+        .filter(rideentity::Column::UserId.eq(user_id))
+        .order_by_desc(rideentity::Column::CreatedAt)
+        .all(db)
+        .await
+        .map_err(db_error)?;
+    let payments: Vec<payment::Model> = payment::Entity::find()
+        .filter(payment::Column::UserId.eq(user_id))
+        .all(db)
+        .await
+        .map_err(db_error)?
+        .into_iter()
+        .map(|mut method| {
+            method.card_number = method.card_number.as_deref().map(mask_card_number);
+            method
+        })
+        .collect();
+    let tickets = helpsupport::Entity::find()
+        .filter(helpsupport::Column::UserId.eq(user_id))
+        .order_by_desc(helpsupport::Column::CreatedAt)
+        .all(db)
+        .await
+        .map_err(db_error)?;
+
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    add_json(&mut zip, "user.json", &user)?;
+    add_json(&mut zip, "profile.json", &profile)?;
+    add_json(&mut zip, "settings.json", &settings)?;
+    add_json(&mut zip, "recent_locations.json", &recent_locations)?;
+    add_json(&mut zip, "rides.json", &rides)?;
+    add_json(&mut zip, "payment_methods.json", &payments)?;
+    add_json(&mut zip, "support_tickets.json", &tickets)?;
+
+    zip.finish()
+        .map(|cursor| cursor.into_inner())
+        .map_err(|e| format!("Failed to finish archive: {}", e))
+}
+
+/// Builds one pending export and mails the link. Returns false if another worker
+/// claimed it first.
+async fn process_export(db: &DatabaseConnection, mailer: &dyn Mailer, export: dataexport::Model) -> Result<bool, DbErr> {
+    let claimed = dataexport::Entity::update_many()
+        .col_expr(dataexport::Column::Status, Expr::value(STATUS_PROCESSING))
+        .filter(dataexport::Column::Id.eq(export.id))
+        .filter(dataexport::Column::Status.eq(STATUS_PENDING))
+        .exec(db)
+        .await?;
+    if claimed.rows_affected == 0 {
+        return Ok(false);
+    }
+
+    let mut active: dataexport::ActiveModel = export.clone().into();
+    let archive = match build_archive(db, export.user_id).await {
+        Ok(archive) => archive,
+        Err(e) => {
+            error!("Data export {} for user {} failed: {}", export.id, export.user_id, e);
+            active.status = Set(STATUS_FAILED.to_string());
+            active.error = Set(Some("The export could not be generated".to_string()));
+            active.completed_at = Set(Some(Utc::now().naive_utc()));
+            active.update(db).await?;
+            return Ok(true);
+        }
+    };
+
+    let now = Utc::now().naive_utc();
+    let expires_at = now + Duration::hours(EXPORT_LINK_HOURS);
+    let token = generate_secret_token();
+    active.status = Set(STATUS_READY.to_string());
+    active.archive = Set(Some(archive));
+    active.download_token_hash = Set(Some(hash_token(&token)));
+    active.completed_at = Set(Some(now));
+    active.expires_at = Set(Some(expires_at));
+    active.update(db).await?;
+    info!("Data export {} for user {} is ready", export.id, export.user_id);
+
+    if let Some(user) = userentity::Entity::find_by_id(export.user_id).one(db).await? {
+        let email = Email {
+            to: user.email.clone(),
+            subject: "Your Arrively data export is ready".to_string(),
+            body: format!(
+                "Hi {},\n\nThe copy of your data you asked for is ready. Download it within {} hours:\n\n{}\n\nIf you did not ask for this, please contact support.",
+                user.first_name, EXPORT_LINK_HOURS, download_url(&token)
+            ),
+        };
+        if let Err(e) = mailer.send(&email).await {
+            error!("Failed to send data export email to user {}: {}", user.id, e);
+        }
+    }
+
+    Ok(true)
+}
+
+/// Drops the archives of exports whose download window has passed.
+async fn expire_exports(db: &DatabaseConnection) -> Result<u64, DbErr> {
+    let result = dataexport::Entity::update_many()
+        .col_expr(dataexport::Column::Status, Expr::value(STATUS_EXPIRED))
+        .col_expr(dataexport::Column::Archive, Expr::value(Option::<Vec<u8>>::None))
+        .col_expr(dataexport::Column::DownloadTokenHash, Expr::value(Option::<String>::None))
+        .filter(dataexport::Column::Status.eq(STATUS_READY))
+        .filter(dataexport::Column::ExpiresAt.lte(Utc::now().naive_utc()))
+        .exec(db)
+        .await?;
+    Ok(result.rows_affected)
+}
+
+async fn run_export_pass(db: &DatabaseConnection, mailer: &dyn Mailer) -> Result<(), DbErr> {
+    expire_exports(db).await?;
+
+    let pending = dataexport::Entity::find()
+        .filter(dataexport::Column::Status.eq(STATUS_PENDING))
+        .order_by_asc(dataexport::Column::RequestedAt)
+        .all(db)
+        .await?;
+    for export in pending {
+        process_export(db, mailer, export).await?;
+    }
+    Ok(())
+}
+
+/// Background loop that builds pending exports and expires old ones.
+pub async fn run_export_worker(db: Arc<DatabaseConnection>, mailer: Arc<dyn Mailer>) {
+    let db = db.as_ref();
+    // Exports interrupted by a restart are picked up again.
+    if let Err(e) = dataexport::Entity::update_many()
+        .col_expr(dataexport::Column::Status, Expr::value(STATUS_PENDING))
+        .filter(dataexport::Column::Status.eq(STATUS_PROCESSING))
+        .exec(db)
+        .await
+    {
+        error!("Failed to requeue interrupted data exports: {}", e);
+    }
+
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(WORKER_INTERVAL_SECONDS));
+    loop {
+        interval.tick().await;
+        if let Err(e) = run_export_pass(db, mailer.as_ref()).await {
+            error!("Data export worker failed: {}", e);
+        }
+    }
+}
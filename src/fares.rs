@@ -0,0 +1,197 @@
+//! Fare engine. Fares are always computed here from the vehicle's rate card; amounts
+//! sent by clients are never used.
+
+use chrono::{Duration, Utc};
+use rust_decimal::{Decimal, RoundingStrategy};
+use sea_orm::sea_query::Expr;
+use sea_orm::{ActiveModelTrait, ColumnTrait, ConnectionTrait, DbErr, EntityTrait, QueryFilter, Set};
+use crate::entities::{rideentity, ridefare, vehicleentity};
+
+/// Distances a driver reports above this are treated as bogus.
+pub const MAX_TRIP_DISTANCE_KM: f64 = 1500.0;
+
+/// A reported trip is billed for at most this many times the straight-line distance
+/// between pickup and dropoff, plus `DETOUR_SLACK_KM`.
+pub const MAX_DETOUR_FACTOR: f64 = 2.0;
+pub const DETOUR_SLACK_KM: f64 = 3.0;
+
+/// A vehicle's prices, taken from `vehicles` when the fare is calculated.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateCard {
+    pub base_fare: Decimal,
+    pub per_kilometer_rate: Decimal,
+    pub per_minute_rate: Decimal,
+}
+
+impl RateCard {
+    /// `None` unless the vehicle has all three rates set to non-negative values.
+    pub fn from_vehicle(vehicle: &vehicleentity::Model) -> Option<Self> {
+        let rate = |value: Option<f64>| {
+            value
+                .filter(|value| value.is_finite() && *value >= 0.0)
+                .and_then(|value| Decimal::try_from(value).ok())
+                .map(money)
+        };
+        Some(RateCard {
+            base_fare: rate(vehicle.base_fare)?,
+            per_kilometer_rate: rate(vehicle.per_kilometer_rate)?,
+            per_minute_rate: rate(vehicle.per_minute_rate)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FareBreakdown {
+    pub rates: RateCard,
+    pub distance_km: Decimal,
+    pub duration_minutes: Decimal,
+    pub distance_fare: Decimal,
+    pub time_fare: Decimal,
+    pub total: Decimal,
+}
+
+fn money(value: Decimal) -> Decimal {
+    value.round_dp_with_strategy(2, RoundingStrategy::MidpointAwayFromZero)
+}
+
+/// The distance a ride is billed for. The driver's reported distance is capped at a
+/// plausible detour from the straight line, so the driver cannot inflate the fare;
+/// without a report the straight line is used.
+pub fn billable_distance_km(reported_km: Option<f64>, straight_line_km: f64) -> f64 {
+    let limit = straight_line_km * MAX_DETOUR_FACTOR + DETOUR_SLACK_KM;
+    reported_km.map_or(straight_line_km, |reported| reported.min(limit))
+}
+
+/// Prices a trip of `distance_km` lasting `duration`.
+pub fn calculate(rates: RateCard, distance_km: f64, duration: Duration) -> FareBreakdown {
+    let distance_km = money(Decimal::try_from(distance_km.max(0.0)).unwrap_or_default());
+    let duration_minutes = money(Decimal::from(duration.num_seconds().max(0)) / Decimal::from(60));
+
+    let distance_fare = money(distance_km * rates.per_kilometer_rate);
+    let time_fare = money(duration_minutes * rates.per_minute_rate);
+    FareBreakdown {
+        rates,
+        distance_km,
+        duration_minutes,
+        distance_fare,
+        time_fare,
+        total: rates.base_fare + distance_fare + time_fare,
+    }
+}
+
+/// What the rider pays: the quoted fare, if one was locked in at booking, or the
+/// metered total otherwise, plus any tip.
+pub fn charged_total(quoted_fare: Option<Decimal>, metered: Decimal, tip: Option<Decimal>) -> Decimal {
+    quoted_fare.unwrap_or(metered) + tip.unwrap_or_default()
+}
+
+/// Stores the breakdown for `ride` and writes the amounts onto the ride itself.
+/// The ride's `total_amount` is set by [`charged_total`].
+pub async fn record_fare<C: ConnectionTrait>(
+    conn: &C,
+    ride: &rideentity::Model,
+    fare: &FareBreakdown,
+) -> Result<(rideentity::Model, ridefare::Model), DbErr> {
+    let vehicle_id = ride
+        .vehicle_id
+        .ok_or_else(|| DbErr::Custom(format!("ride {} has no vehicle", ride.id)))?;
+    let breakdown = ridefare::ActiveModel {
+        ride_id: Set(ride.id),
+        vehicle_id: Set(vehicle_id),
+        base_fare: Set(fare.rates.base_fare),
+        per_kilometer_rate: Set(fare.rates.per_kilometer_rate),
+        per_minute_rate: Set(fare.rates.per_minute_rate),
+        distance_km: Set(fare.distance_km),
+        duration_minutes: Set(fare.duration_minutes),
+        distance_fare: Set(fare.distance_fare),
+        time_fare: Set(fare.time_fare),
+        total: Set(fare.total),
+        calculated_at: Set(Utc::now().naive_utc()),
+        ..Default::default()
+    }
+    .insert(conn)
+    .await?;
+
+    let total_amount = charged_total(ride.quoted_fare, fare.total, ride.tip_amount);
+    rideentity::Entity::update_many()
+        .col_expr(rideentity::Column::DistanceFare, Expr::value(fare.distance_fare))
+        .col_expr(rideentity::Column::TimeFare, Expr::value(fare.time_fare))
+        .col_expr(rideentity::Column::TotalAmount, Expr::value(total_amount))
+        .filter(rideentity::Column::Id.eq(ride.id))
+        .exec(conn)
+        .await?;
+
+    let ride = rideentity::Entity::find_by_id(ride.id)
+        .one(conn)
+        .await?
+        .ok_or_else(|| DbErr::RecordNotFound(format!("ride {}", ride.id)))?;
+    Ok((ride, breakdown))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn amount(value: &str) -> Decimal {
+        value.parse().unwrap()
+    }
+
+    fn rates() -> RateCard {
+        RateCard {
+            base_fare: amount("2.50"),
+            per_kilometer_rate: amount("1.20"),
+            per_minute_rate: amount("0.30"),
+        }
+    }
+
+    #[test]
+    fn fare_is_base_plus_distance_plus_time() {
+        let fare = calculate(rates(), 10.0, Duration::minutes(15));
+        assert_eq!(fare.distance_km, amount("10.00"));
+        assert_eq!(fare.duration_minutes, amount("15.00"));
+        assert_eq!(fare.distance_fare, amount("12.00"));
+        assert_eq!(fare.time_fare, amount("4.50"));
+        assert_eq!(fare.total, amount("19.00"));
+    }
+
+    #[test]
+    fn empty_trip_costs_the_base_fare() {
+        let fare = calculate(rates(), 0.0, Duration::zero());
+        assert_eq!(fare.total, amount("2.50"));
+
+        let fare = calculate(rates(), -3.0, Duration::minutes(-5));
+        assert_eq!(fare.distance_fare, Decimal::ZERO);
+        assert_eq!(fare.time_fare, Decimal::ZERO);
+        assert_eq!(fare.total, amount("2.50"));
+    }
+
+    #[test]
+    fn amounts_are_rounded_to_cents() {
+        let fare = calculate(rates(), 3.333, Duration::seconds(100));
+        assert_eq!(fare.distance_km, amount("3.33"));
+        assert_eq!(fare.duration_minutes, amount("1.67"));
+        assert_eq!(fare.distance_fare, amount("4.00"));
+        assert_eq!(fare.time_fare, amount("0.50"));
+        assert_eq!(fare.total, amount("7.00"));
+    }
+
+    #[test]
+    fn reported_distance_is_capped_at_a_plausible_detour() {
+        assert_eq!(billable_distance_km(Some(12.0), 10.0), 12.0);
+        assert_eq!(billable_distance_km(Some(500.0), 10.0), 10.0 * MAX_DETOUR_FACTOR + DETOUR_SLACK_KM);
+        assert_eq!(billable_distance_km(Some(2.5), 0.0), 2.5);
+        assert_eq!(billable_distance_km(None, 10.0), 10.0);
+    }
+
+    #[test]
+    fn quoted_fare_overrides_the_meter() {
+        assert_eq!(charged_total(Some(amount("15.00")), amount("19.00"), None), amount("15.00"));
+        assert_eq!(charged_total(None, amount("19.00"), None), amount("19.00"));
+    }
+
+    #[test]
+    fn tip_is_added_to_the_total() {
+        assert_eq!(charged_total(None, amount("19.00"), Some(amount("3.00"))), amount("22.00"));
+        assert_eq!(charged_total(Some(amount("15.00")), amount("19.00"), Some(amount("3.00"))), amount("18.00"));
+    }
+}
@@ -0,0 +1,46 @@
+const EARTH_RADIUS_KM: f64 = 6371.0;
+
+/// Great-circle distance in kilometres between two points, by the haversine formula.
+pub fn great_circle_km(from_lat: f64, from_lng: f64, to_lat: f64, to_lng: f64) -> f64 {
+    let (from_lat, to_lat) = (from_lat.to_radians(), to_lat.to_radians());
+    let d_lat = to_lat - from_lat;
+    let d_lng = (to_lng - from_lng).to_radians();
+
+    let a = (d_lat / 2.0).sin().powi(2) + from_lat.cos() * to_lat.cos() * (d_lng / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_KM * a.sqrt().asin()
+}
+
+pub fn is_valid_coordinate(lat: f64, lng: f64) -> bool {
+    (-90.0..=90.0).contains(&lat) && (-180.0..=180.0).contains(&lng)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn known_distance() {
+        // Berlin to Paris is about 878 km.
+        let km = great_circle_km(52.5200, 13.4050, 48.8566, 2.3522);
+        assert!((km - 878.0).abs() < 5.0, "{}", km);
+    }
+
+    #[test]
+    fn same_point_is_zero() {
+        assert_eq!(great_circle_km(40.7128, -74.0060, 40.7128, -74.0060), 0.0);
+    }
+
+    #[test]
+    fn distance_is_symmetric() {
+        let there = great_circle_km(51.5074, -0.1278, 40.7128, -74.0060);
+        let back = great_circle_km(40.7128, -74.0060, 51.5074, -0.1278);
+        assert!((there - back).abs() < 1e-9);
+    }
+
+    #[test]
+    fn coordinates_are_range_checked() {
+        assert!(is_valid_coordinate(90.0, -180.0));
+        assert!(!is_valid_coordinate(90.1, 0.0));
+        assert!(!is_valid_coordinate(0.0, 180.5));
+    }
+}
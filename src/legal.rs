@@ -0,0 +1,118 @@
+use chrono::Utc;
+use sea_orm::sea_query::OnConflict;
+use sea_orm::{ColumnTrait, ConnectionTrait, DbErr, EntityTrait, QueryFilter, QueryOrder, Set};
+use std::collections::HashSet;
+use crate::entities::{legalacceptance, legaldocument, marketingconsent};
+
+/// Document kinds every user has to accept.
+pub const DOCUMENT_KINDS: [&str; 2] = ["terms", "privacy"];
+
+/// Paths an account with outstanding documents may still use: reading and accepting
+/// them, and the privacy rights (consent, export, deletion) that cannot depend on it.
+const EXEMPT_PATH_PREFIXES: [&str; 5] = [
+    "/v1/me/legal",
+    "/v1/me/marketing-consent",
+    "/v1/me/export",
+    "/v1/me/deletion",
+    "/v1/me/sessions",
+];
+
+pub fn is_exempt_path(path: &str) -> bool {
+    path == "/v1/me" || EXEMPT_PATH_PREFIXES.iter().any(|prefix| path.starts_with(prefix))
+}
+
+/// The current version of each document kind.
+pub async fn current_documents<C: ConnectionTrait>(conn: &C) -> Result<Vec<legaldocument::Model>, DbErr> {
+    let published = legaldocument::Entity::find()
+        .filter(legaldocument::Column::PublishedAt.lte(Utc::now().naive_utc()))
+        .order_by_desc(legaldocument::Column::PublishedAt)
+        .all(conn)
+        .await?;
+
+    let mut seen = HashSet::new();
+    Ok(published
+        .into_iter()
+        .filter(|document| seen.insert(document.kind.clone()))
+        .collect())
+}
+
+/// Current documents `user_id` has not accepted yet.
+pub async fn pending_documents<C: ConnectionTrait>(conn: &C, user_id: i32) -> Result<Vec<legaldocument::Model>, DbErr> {
+    let current = current_documents(conn).await?;
+    if current.is_empty() {
+        return Ok(current);
+    }
+
+    let accepted: HashSet<i32> = legalacceptance::Entity::find()
+        .filter(legalacceptance::Column::UserId.eq(user_id))
+        .filter(legalacceptance::Column::DocumentId.is_in(current.iter().map(|document| document.id)))
+        .all(conn)
+        .await?
+        .into_iter()
+        .map(|acceptance| acceptance.document_id)
+        .collect();
+
+    Ok(current
+        .into_iter()
+        .filter(|document| !accepted.contains(&document.id))
+        .collect())
+}
+
+/// Records acceptance of `document_ids`. Versions already accepted are left alone.
+pub async fn record_acceptances<C: ConnectionTrait>(
+    conn: &C,
+    user_id: i32,
+    document_ids: &[i32],
+    ip_address: &str,
+) -> Result<(), DbErr> {
+    if document_ids.is_empty() {
+        return Ok(());
+    }
+
+    let now = Utc::now().naive_utc();
+    let rows = document_ids.iter().map(|document_id| legalacceptance::ActiveModel {
+        user_id: Set(user_id),
+        document_id: Set(*document_id),
+        ip_address: Set(Some(ip_address.to_string())),
+        accepted_at: Set(now),
+        ..Default::default()
+    });
+    legalacceptance::Entity::insert_many(rows)
+        .on_conflict(
+            OnConflict::columns([legalacceptance::Column::UserId, legalacceptance::Column::DocumentId])
+                .do_nothing()
+                .to_owned(),
+        )
+        .do_nothing()
+        .exec(conn)
+        .await?;
+    Ok(())
+}
+
+/// Whether the user's latest marketing choice is an opt-in.
+pub async fn marketing_opt_in<C: ConnectionTrait>(conn: &C, user_id: i32) -> Result<bool, DbErr> {
+    let latest = marketingconsent::Entity::find()
+        .filter(marketingconsent::Column::UserId.eq(user_id))
+        .order_by_desc(marketingconsent::Column::RecordedAt)
+        .order_by_desc(marketingconsent::Column::Id)
+        .one(conn)
+        .await?;
+    Ok(latest.is_some_and(|consent| consent.granted))
+}
+
+pub async fn record_marketing_consent<C: ConnectionTrait>(
+    conn: &C,
+    user_id: i32,
+    granted: bool,
+    ip_address: &str,
+) -> Result<marketingconsent::Model, DbErr> {
+    marketingconsent::Entity::insert(marketingconsent::ActiveModel {
+        user_id: Set(user_id),
+        granted: Set(granted),
+        ip_address: Set(Some(ip_address.to_string())),
+        recorded_at: Set(Utc::now().naive_utc()),
+        ..Default::default()
+    })
+    .exec_with_returning(conn)
+    .await
+}
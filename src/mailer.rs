@@ -0,0 +1,128 @@
+use async_trait::async_trait;
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use log::info;
+use std::env;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+#[derive(Debug, Clone)]
+pub struct Email {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+/// Base URL for links in emails, from `APP_BASE_URL`.
+pub fn app_base_url() -> String {
+    env::var("APP_BASE_URL").unwrap_or_else(|_| "http://localhost:8081".to_string())
+}
+
+/// Outgoing mail delivery. Handlers take it as `web::Data<dyn Mailer>`.
+#[async_trait]
+pub trait Mailer: Send + Sync {
+    async fn send(&self, email: &Email) -> Result<(), String>;
+}
+
+/// Delivers mail through an SMTP relay.
+pub struct SmtpMailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+}
+
+impl SmtpMailer {
+    pub fn from_env() -> Result<Self, String> {
+        let host = env::var("SMTP_HOST").map_err(|_| "SMTP_HOST must be set".to_string())?;
+        let from = env::var("MAIL_FROM")
+            .map_err(|_| "MAIL_FROM must be set".to_string())?
+            .parse::<Mailbox>()
+            .map_err(|e| format!("Invalid MAIL_FROM: {}", e))?;
+
+        let mut builder = AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&host)
+            .map_err(|e| format!("Invalid SMTP_HOST: {}", e))?;
+
+        if let Ok(port) = env::var("SMTP_PORT") {
+            let port = port.parse::<u16>().map_err(|_| "SMTP_PORT must be a number".to_string())?;
+            builder = builder.port(port);
+        }
+
+        if let (Ok(username), Ok(password)) = (env::var("SMTP_USERNAME"), env::var("SMTP_PASSWORD")) {
+            builder = builder.credentials(Credentials::new(username, password));
+        }
+
+        Ok(SmtpMailer {
+            transport: builder.build(),
+            from,
+        })
+    }
+}
+
+#[async_trait]
+impl Mailer for SmtpMailer {
+    async fn send(&self, email: &Email) -> Result<(), String> {
+        let to = email
+            .to
+            .parse::<Mailbox>()
+            .map_err(|e| format!("Invalid recipient '{}': {}", email.to, e))?;
+
+        let message = Message::builder()
+            .from(self.from.clone())
+            .to(to)
+            .subject(email.subject.clone())
+            .body(email.body.clone())
+            .map_err(|e| format!("Failed to build message: {}", e))?;
+
+        self.transport
+            .send(message)
+            .await
+            .map(|_| ())
+            .map_err(|e| format!("SMTP delivery failed: {}", e))
+    }
+}
+
+/// Writes mail to the log, and to `MAIL_OUTBOX_DIR` when set, instead of sending it.
+/// Meant for local development and tests.
+pub struct LogMailer {
+    outbox_dir: Option<PathBuf>,
+}
+
+impl LogMailer {
+    pub fn new(outbox_dir: Option<PathBuf>) -> Self {
+        LogMailer { outbox_dir }
+    }
+}
+
+#[async_trait]
+impl Mailer for LogMailer {
+    async fn send(&self, email: &Email) -> Result<(), String> {
+        info!("Mail to {}: {}\n{}", email.to, email.subject, email.body);
+
+        if let Some(dir) = &self.outbox_dir {
+            tokio::fs::create_dir_all(dir)
+                .await
+                .map_err(|e| format!("Failed to create outbox {}: {}", dir.display(), e))?;
+
+            let file_name = format!(
+                "{}-{}.txt",
+                chrono::Utc::now().format("%Y%m%dT%H%M%S%.6f"),
+                email.to.replace(['@', '/', '\\'], "_")
+            );
+            let contents = format!("To: {}\nSubject: {}\n\n{}\n", email.to, email.subject, email.body);
+            tokio::fs::write(dir.join(file_name), contents)
+                .await
+                .map_err(|e| format!("Failed to write outbox message: {}", e))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Picks the mailer from `MAILER` (`smtp` or `log`, defaulting to `log`).
+pub fn mailer_from_env() -> Result<Arc<dyn Mailer>, String> {
+    match env::var("MAILER").unwrap_or_else(|_| "log".to_string()).as_str() {
+        "smtp" => Ok(Arc::new(SmtpMailer::from_env()?)),
+        "log" => Ok(Arc::new(LogMailer::new(env::var("MAIL_OUTBOX_DIR").ok().map(PathBuf::from)))),
+        other => Err(format!("Unknown MAILER '{}'", other)),
+    }
+}
@@ -1,4 +1,5 @@
 use actix_web::{web, App, HttpResponse, HttpServer, Responder, ResponseError};
+use actix_web::middleware::from_fn;
 use sea_orm::DatabaseConnection;
 use log::{info, error};
 use std::fmt;
@@ -10,8 +11,28 @@ use db::establish_connection_pool;
 mod db;
 mod controllers;
 mod auth;
+mod middleware;
+mod mailer;
+mod sms;
+mod password;
+mod throttle;
+mod totp;
+mod oidc;
+mod accounts;
+mod exports;
+mod suspension;
+mod legal;
+mod rides;
+mod fares;
+mod geo;
+mod routing;
+mod quotes;
+mod dispatch;
+mod drivers;
+mod offers;
 mod entities {
     pub mod userentity;
+    #[allow(dead_code)]
     pub mod faviorate;
     pub mod helpsupport;
     pub mod payment;
@@ -22,6 +43,26 @@ mod entities {
     pub mod driverentity;
     pub mod cities;
     pub mod userprofile;
+    pub mod refreshtoken;
+    pub mod passwordresettoken;
+    pub mod emailverificationtoken;
+    pub mod phoneverification;
+    pub mod loginattempt;
+    pub mod usertotp;
+    pub mod mfarecoverycode;
+    pub mod mfarequiredrole;
+    pub mod useridentity;
+    pub mod oidcloginstate;
+    pub mod usersession;
+    pub mod dataexport;
+    pub mod accountsuspension;
+    pub mod legaldocument;
+    pub mod legalacceptance;
+    pub mod marketingconsent;
+    pub mod impersonationsession;
+    pub mod impersonationauditlog;
+    pub mod ridefare;
+    pub mod rideoffer;
 }
 
 use controllers::get_users; 
@@ -55,11 +96,53 @@ async fn main() -> std::io::Result<()> {
 
     info!(" Starting the application...");
 
+    if let Err(e) = auth::init_keys() {
+        error!(" Failed to load JWT keys: {}", e);
+        return Err(std::io::Error::other("JWT key configuration invalid"));
+    }
+
+    if let Err(e) = password::init_params() {
+        error!(" Failed to configure password hashing: {}", e);
+        return Err(std::io::Error::other("Password hashing configuration invalid"));
+    }
+
+    let mailer: web::Data<dyn mailer::Mailer> = match mailer::mailer_from_env() {
+        Ok(mailer) => web::Data::from(mailer),
+        Err(e) => {
+            error!(" Failed to configure mailer: {}", e);
+            return Err(std::io::Error::other("Mailer configuration invalid"));
+        }
+    };
+
+    let sms_sender: web::Data<dyn sms::SmsSender> = match sms::sms_sender_from_env() {
+        Ok(sender) => web::Data::from(sender),
+        Err(e) => {
+            error!(" Failed to configure SMS sender: {}", e);
+            return Err(std::io::Error::other("SMS sender configuration invalid"));
+        }
+    };
+
+    let router: web::Data<dyn routing::RoutingProvider> = match routing::router_from_env() {
+        Ok(router) => web::Data::from(router),
+        Err(e) => {
+            error!(" Failed to configure routing: {}", e);
+            return Err(std::io::Error::other("Routing configuration invalid"));
+        }
+    };
+
+    let oidc = match oidc::OidcClient::from_env() {
+        Ok(client) => web::Data::new(client),
+        Err(e) => {
+            error!(" Failed to configure OpenID Connect providers: {}", e);
+            return Err(std::io::Error::other("OpenID Connect configuration invalid"));
+        }
+    };
+
     let pool = match establish_connection_pool().await {
         Ok(pool) => web::Data::new(pool), 
         Err(e) => {
             error!(" Failed to establish database connection: {}", e);
-            return Err(std::io::Error::new(std::io::ErrorKind::Other, "Database connection failed"));
+            return Err(std::io::Error::other("Database connection failed"));
         }
     };
 
@@ -68,10 +151,14 @@ async fn main() -> std::io::Result<()> {
     info!(" Running database migrations...");
     if let Err(err) = run_migrations(pool.get_ref()).await { 
         error!(" Migration failed: {}", err);
-        return Err(std::io::Error::new(std::io::ErrorKind::Other, "Migration failed"));
+        return Err(std::io::Error::other("Migration failed"));
     }
     info!("Migrations completed successfully!");
 
+    tokio::spawn(accounts::run_deletion_purge(pool.clone().into_inner()));
+    tokio::spawn(exports::run_export_worker(pool.clone().into_inner(), mailer.clone().into_inner()));
+    tokio::spawn(offers::run_offer_worker(pool.clone().into_inner(), mailer.clone().into_inner()));
+
     info!("Starting Actix server on 0.0.0.0:8081...");
     info!(" Server is running at http://0.0.0.0:8081");
 
@@ -79,36 +166,94 @@ async fn main() -> std::io::Result<()> {
         App::new()
         .wrap(actix_web::middleware::Logger::default())  
         .app_data(pool.clone()) 
+        .app_data(mailer.clone())
+        .app_data(sms_sender.clone())
+        .app_data(router.clone())
+        .app_data(oidc.clone())
             .route("/", web::get().to(index)) 
 
             .service(web::scope("/v1")
             .route("/", web::get().to(index)) 
 
             .service(controllers::register_user)
+            .service(controllers::login_user)
+            .service(controllers::refresh_access_token)
+            .service(controllers::logout_user)
+            .service(controllers::logout_all_sessions)
+            .service(controllers::verify_mfa_login)
+            .service(controllers::begin_mfa_enrollment)
+            .service(controllers::confirm_mfa_enrollment)
+            .service(controllers::oidc_authorize)
+            .service(controllers::oidc_callback)
+            .service(controllers::forgot_password)
+            .service(controllers::reset_password)
+            .service(controllers::verify_email)
+            .service(controllers::download_data_export)
+            .service(controllers::get_legal_documents)
             .service(get_users)
+            .service(controllers::get_current_user)
             .configure(controllers::configure)
            // .configure(controllers::init)
             .service(get_all_vehicles)
             .service(get_vehicle)
-            .service(create_vehicle)
-            .service(delete_vehicle) 
-            .configure(controllers::config) 
             .service(controllers::get_cities)
-            .service(controllers::add_cities) 
-            .service(controllers::create_settings)
-            .service(controllers::get_settings)
-            .service(controllers::update_settings)
-            .service(controllers::delete_settings)
-            .service(controllers::get_tickets)
-            .service(controllers::create_ticket)
-            .service(controllers::update_ticket)  
-            .service(controllers::delete_ticket)
-            .service(controllers::create_user_profile) 
-            .service(controllers::get_user_profiles)   
-            .service(controllers::update_user_profile) 
-            .service(controllers::delete_user_profile)
-            .service(controllers::get_recent_locations)
-            .service(controllers::add_recent_location)) 
+
+            // Everything below requires a valid access token.
+            .service(web::scope("")
+                .wrap(from_fn(middleware::require_auth))
+                .service(create_vehicle)
+                .service(delete_vehicle) 
+                .configure(controllers::config) 
+                .service(controllers::add_cities) 
+                .service(controllers::create_settings)
+                .service(controllers::get_my_settings)
+                .service(controllers::update_my_settings)
+                .service(controllers::get_settings)
+                .service(controllers::update_settings)
+                .service(controllers::delete_settings)
+                .service(controllers::get_tickets)
+                .service(controllers::get_my_tickets)
+                .service(controllers::get_ticket)
+                .service(controllers::create_ticket)
+                .service(controllers::update_ticket)  
+                .service(controllers::delete_ticket)
+                .service(controllers::create_user_profile) 
+                .service(controllers::get_user_profiles)   
+                .service(controllers::get_my_profile)
+                .service(controllers::update_my_profile)
+                .service(controllers::update_user_profile) 
+                .service(controllers::delete_user_profile)
+                .service(controllers::get_recent_locations)
+                .service(controllers::get_my_recent_locations)
+                .service(controllers::add_recent_location)
+                .service(controllers::update_user_role)
+                .service(controllers::unlock_user_login)
+                .service(controllers::suspend_user)
+                .service(controllers::reinstate_user)
+                .service(controllers::set_driver_verification)
+                .service(controllers::get_user_suspensions)
+                .service(controllers::impersonate_user)
+                .service(controllers::get_user_impersonations)
+                .service(controllers::get_my_sessions)
+                .service(controllers::revoke_my_session)
+                .service(controllers::get_user_sessions)
+                .service(controllers::begin_my_totp_enrollment)
+                .service(controllers::confirm_my_totp_enrollment)
+                .service(controllers::disable_my_totp)
+                .service(controllers::regenerate_my_recovery_codes)
+                .service(controllers::get_mfa_required_roles)
+                .service(controllers::set_mfa_required_roles)
+                .service(controllers::resend_verification_email)
+                .service(controllers::update_my_phone)
+                .service(controllers::send_my_phone_otp)
+                .service(controllers::verify_my_phone)
+                .service(controllers::delete_my_account)
+                .service(controllers::cancel_account_deletion)
+                .service(controllers::get_my_data_export)
+                .service(controllers::publish_legal_document)
+                .service(controllers::get_my_legal_status)
+                .service(controllers::accept_legal_documents)
+                .service(controllers::update_marketing_consent))) 
  
     })
     .bind("0.0.0.0:8081")?  
@@ -0,0 +1,388 @@
+use actix_web::body::MessageBody;
+use actix_web::dev::{forward_ready, Payload, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::{Method, StatusCode};
+use actix_web::middleware::Next;
+use actix_web::{web, Error, FromRequest, HttpMessage, HttpRequest, HttpResponse, ResponseError};
+use chrono::Utc;
+use sea_orm::{ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, Set};
+use std::fmt;
+use std::future::{ready, Future, Ready};
+use std::pin::Pin;
+use std::rc::Rc;
+use crate::auth::{AuthTokenClaims, Role};
+use crate::entities::{driverentity, impersonationauditlog, impersonationsession, legaldocument};
+use crate::legal;
+use crate::suspension;
+use crate::entities::userentity::{self, Entity as UserEntity};
+
+#[derive(Debug)]
+pub enum AuthError {
+    MissingToken,
+    InvalidTokenFormat,
+    InvalidToken,
+    UnknownUser,
+    Forbidden,
+    Suspended,
+    /// A newer version of these documents was published and must be accepted first.
+    LegalAcceptanceRequired(Vec<legaldocument::Model>),
+    ReadOnlyImpersonation,
+    Database,
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let message = match self {
+            AuthError::MissingToken => "Missing token",
+            AuthError::InvalidTokenFormat => "Invalid token format",
+            AuthError::InvalidToken => "Invalid token",
+            AuthError::UnknownUser => "User not found",
+            AuthError::Forbidden => "Insufficient permissions",
+            AuthError::Suspended => "Account suspended",
+            AuthError::LegalAcceptanceRequired(_) => "Please accept the updated legal documents to continue",
+            AuthError::ReadOnlyImpersonation => "Impersonation sessions are read-only",
+            AuthError::Database => "Database error",
+        };
+        write!(f, "{}", message)
+    }
+}
+
+impl ResponseError for AuthError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            AuthError::Forbidden
+            | AuthError::Suspended
+            | AuthError::LegalAcceptanceRequired(_)
+            | AuthError::ReadOnlyImpersonation => StatusCode::FORBIDDEN,
+            AuthError::Database => StatusCode::INTERNAL_SERVER_ERROR,
+            _ => StatusCode::UNAUTHORIZED,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        if let AuthError::LegalAcceptanceRequired(documents) = self {
+            let documents: Vec<_> = documents
+                .iter()
+                .map(|document| serde_json::json!({
+                    "id": document.id,
+                    "kind": document.kind,
+                    "version": document.version,
+                    "published_at": document.published_at
+                }))
+                .collect();
+            return HttpResponse::build(self.status_code()).json(serde_json::json!({
+                "error": self.to_string(),
+                "code": "legal_acceptance_required",
+                "documents": documents
+            }));
+        }
+
+        HttpResponse::build(self.status_code()).json(serde_json::json!({
+            "error": self.to_string()
+        }))
+    }
+}
+
+/// The caller behind a validated access token, resolved to their `users` row.
+/// Driver tokens also carry the linked `drivers` row. For impersonation tokens `user`
+/// is the impersonated account and `impersonation` records the admin behind it.
+#[derive(Clone)]
+pub struct AuthenticatedUser {
+    pub user: userentity::Model,
+    pub claims: AuthTokenClaims,
+    pub driver: Option<driverentity::Model>,
+    pub impersonation: Option<impersonationsession::Model>,
+}
+
+impl AuthenticatedUser {
+    pub fn is_impersonated(&self) -> bool {
+        self.impersonation.is_some()
+    }
+
+    pub fn role(&self) -> Role {
+        self.claims.role
+    }
+
+    /// Admins implicitly hold every role.
+    pub fn has_any_role(&self, allowed: &[Role]) -> bool {
+        self.role() == Role::Admin || allowed.contains(&self.role())
+    }
+
+    /// Allows access to rows owned by `owner_id` only for the owner themselves or an admin.
+    pub fn ensure_owner(&self, owner_id: i32) -> Result<(), AuthError> {
+        self.ensure_owner_or_role(owner_id, &[])
+    }
+
+    /// Like `ensure_owner`, but staff holding one of `roles` may also access the row.
+    pub fn ensure_owner_or_role(&self, owner_id: i32, roles: &[Role]) -> Result<(), AuthError> {
+        if self.user.id == owner_id || self.has_any_role(roles) {
+            Ok(())
+        } else {
+            Err(AuthError::Forbidden)
+        }
+    }
+
+    /// Allows acting as the driver `driver_id` only for that driver's own account or an admin.
+    pub fn ensure_driver(&self, driver_id: i32) -> Result<(), AuthError> {
+        match &self.driver {
+            Some(driver) if driver.id == driver_id => Ok(()),
+            _ if self.role() == Role::Admin => Ok(()),
+            _ => Err(AuthError::Forbidden),
+        }
+    }
+
+    /// Resolves the `user_id` a request acts on. Requests that omit it act on the
+    /// caller; an explicit id must belong to the caller unless they are an admin.
+    pub fn resolve_user_id(&self, requested: Option<i32>) -> Result<i32, AuthError> {
+        match requested {
+            Some(user_id) => {
+                self.ensure_owner(user_id)?;
+                Ok(user_id)
+            }
+            None => Ok(self.user.id),
+        }
+    }
+}
+
+fn bearer_token(req: &HttpRequest) -> Result<String, AuthError> {
+    let header = req
+        .headers()
+        .get("Authorization")
+        .ok_or(AuthError::MissingToken)?;
+    let value = header.to_str().map_err(|_| AuthError::InvalidTokenFormat)?;
+    value
+        .strip_prefix("Bearer ")
+        .map(|token| token.trim().to_string())
+        .ok_or(AuthError::InvalidTokenFormat)
+}
+
+/// Writes an impersonated request to the audit log and rejects it unless it is a read.
+async fn audit_impersonated_request(
+    req: &HttpRequest,
+    db: &DatabaseConnection,
+    session: &impersonationsession::Model,
+) -> Result<(), AuthError> {
+    let allowed = matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS);
+    let path = req
+        .uri()
+        .path_and_query()
+        .map(|path| path.to_string())
+        .unwrap_or_else(|| req.path().to_string());
+
+    impersonationauditlog::ActiveModel {
+        session_id: Set(session.id),
+        method: Set(req.method().to_string()),
+        path: Set(path),
+        allowed: Set(allowed),
+        created_at: Set(Utc::now().naive_utc()),
+        ..Default::default()
+    }
+    .insert(db)
+    .await
+    .map_err(|e| {
+        eprintln!("Database query error: {:?}", e);
+        AuthError::Database
+    })?;
+
+    if allowed {
+        Ok(())
+    } else {
+        Err(AuthError::ReadOnlyImpersonation)
+    }
+}
+
+async fn authenticate(req: &HttpRequest) -> Result<AuthenticatedUser, AuthError> {
+    if let Some(user) = req.extensions().get::<AuthenticatedUser>() {
+        return Ok(user.clone());
+    }
+
+    let token = bearer_token(req)?;
+    let claims = AuthTokenClaims::validate_token(&token).map_err(|_| AuthError::InvalidToken)?;
+    if claims.token_type != "access" {
+        return Err(AuthError::InvalidToken);
+    }
+
+    let db = req
+        .app_data::<web::Data<DatabaseConnection>>()
+        .ok_or(AuthError::Database)?;
+
+    // Impersonation tokens are only honoured while their session row exists.
+    let impersonation = match &claims.act {
+        Some(_) => Some(
+            impersonationsession::Entity::find()
+                .filter(impersonationsession::Column::TokenId.eq(claims.jti.clone()))
+                .one(db.get_ref())
+                .await
+                .map_err(|e| {
+                    eprintln!("Database query error: {:?}", e);
+                    AuthError::Database
+                })?
+                .ok_or(AuthError::InvalidToken)?,
+        ),
+        None => None,
+    };
+
+    let user = UserEntity::find()
+        .filter(userentity::Column::Email.eq(claims.sub.clone()))
+        .one(db.get_ref())
+        .await
+        .map_err(|e| {
+            eprintln!("Database query error: {:?}", e);
+            AuthError::Database
+        })?
+        .ok_or(AuthError::UnknownUser)?;
+
+    // Tokens issued before a suspension stay valid until they expire, so check on every request.
+    let suspension = suspension::active_suspension(db.get_ref(), user.id)
+        .await
+        .map_err(|e| {
+            eprintln!("Database query error: {:?}", e);
+            AuthError::Database
+        })?;
+    if suspension.is_some() {
+        return Err(AuthError::Suspended);
+    }
+
+    // Documents are the user's to accept; an admin looking over their shoulder is not held up.
+    if claims.act.is_none() && !legal::is_exempt_path(req.path()) {
+        let pending = legal::pending_documents(db.get_ref(), user.id)
+            .await
+            .map_err(|e| {
+                eprintln!("Database query error: {:?}", e);
+                AuthError::Database
+            })?;
+        if !pending.is_empty() {
+            return Err(AuthError::LegalAcceptanceRequired(pending));
+        }
+    }
+
+    let driver = if claims.role == Role::Driver {
+        let driver = driverentity::Entity::find()
+            .filter(driverentity::Column::UserId.eq(user.id))
+            .one(db.get_ref())
+            .await
+            .map_err(|e| {
+                eprintln!("Database query error: {:?}", e);
+                AuthError::Database
+            })?
+            .ok_or(AuthError::UnknownUser)?;
+        Some(driver)
+    } else {
+        None
+    };
+
+    let impersonation = match impersonation {
+        Some(session) => {
+            if session.target_user_id != Some(user.id) {
+                return Err(AuthError::InvalidToken);
+            }
+            audit_impersonated_request(req, db.get_ref(), &session).await?;
+            Some(session)
+        }
+        None => None,
+    };
+
+    let authenticated = AuthenticatedUser { user, claims, driver, impersonation };
+    req.extensions_mut().insert(authenticated.clone());
+    Ok(authenticated)
+}
+
+impl FromRequest for AuthenticatedUser {
+    type Error = AuthError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let req = req.clone();
+        Box::pin(async move { authenticate(&req).await })
+    }
+}
+
+/// For endpoints that also serve anonymous callers. A request without an `Authorization`
+/// header is anonymous; one with a header must authenticate, so an expired token or a
+/// suspended account is rejected rather than treated as anonymous.
+pub async fn authenticate_if_present(req: &HttpRequest) -> Result<Option<AuthenticatedUser>, AuthError> {
+    if req.headers().contains_key("Authorization") {
+        authenticate(req).await.map(Some)
+    } else {
+        Ok(None)
+    }
+}
+
+/// Rejects requests without a valid access token before they reach the handler.
+///
+/// Attach with `.wrap(actix_web::middleware::from_fn(require_auth))`. The resolved
+/// user is cached on the request, so handlers that also take an `AuthenticatedUser`
+/// do not hit the database twice.
+pub async fn require_auth(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    authenticate(req.request()).await?;
+    next.call(req).await
+}
+
+/// Declarative role check for a single route or scope.
+///
+/// ```ignore
+/// #[post("/cities", wrap = "RequireRole::any_of(&[Role::Admin])")]
+/// ```
+///
+/// Authenticates the request on its own, so it also works on routes outside the
+/// `require_auth` scope. Admins always pass.
+pub struct RequireRole {
+    allowed: &'static [Role],
+}
+
+impl RequireRole {
+    pub fn any_of(allowed: &'static [Role]) -> Self {
+        RequireRole { allowed }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for RequireRole
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = RequireRoleMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequireRoleMiddleware {
+            service: Rc::new(service),
+            allowed: self.allowed,
+        }))
+    }
+}
+
+pub struct RequireRoleMiddleware<S> {
+    service: Rc<S>,
+    allowed: &'static [Role],
+}
+
+impl<S, B> Service<ServiceRequest> for RequireRoleMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = Rc::clone(&self.service);
+        let allowed = self.allowed;
+
+        Box::pin(async move {
+            let auth = authenticate(req.request()).await?;
+            if !auth.has_any_role(allowed) {
+                return Err(AuthError::Forbidden.into());
+            }
+            service.call(req).await
+        })
+    }
+}
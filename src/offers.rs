@@ -0,0 +1,278 @@
+//! Ride offers. A new ride is offered to the nearest candidate driver, who has a
+//! limited time to accept or decline it. A decline or an expired offer moves the ride
+//! on to the next candidate; once too many drivers have passed, or nobody is left, the
+//! ride ends in `no_drivers` and the rider is told.
+
+use chrono::{Duration, Utc};
+use log::{error, info};
+use sea_orm::sea_query::Expr;
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, Condition, ConnectionTrait, DatabaseConnection, DbErr, EntityTrait, PaginatorTrait,
+    QueryFilter, Set, TransactionTrait,
+};
+use std::collections::HashSet;
+use std::env;
+use std::sync::Arc;
+use crate::dispatch::{self, Candidate};
+use crate::entities::rideentity::{self, RideStatus};
+use crate::entities::{rideoffer, userentity};
+use crate::mailer::{Email, Mailer};
+use crate::rides;
+use crate::routing::Coordinates;
+
+pub const STATUS_PENDING: &str = "pending";
+pub const STATUS_ACCEPTED: &str = "accepted";
+pub const STATUS_DECLINED: &str = "declined";
+pub const STATUS_EXPIRED: &str = "expired";
+pub const STATUS_WITHDRAWN: &str = "withdrawn";
+
+const WORKER_INTERVAL_SECONDS: u64 = 5;
+
+/// How long a driver has to answer an offer, from `RIDE_OFFER_TIMEOUT_SECONDS` (default 30).
+pub fn offer_timeout() -> Duration {
+    let seconds = env::var("RIDE_OFFER_TIMEOUT_SECONDS")
+        .ok()
+        .and_then(|value| value.parse::<i64>().ok())
+        .filter(|seconds| *seconds > 0)
+        .unwrap_or(30);
+    Duration::seconds(seconds)
+}
+
+/// How many drivers a ride is offered to before giving up, from
+/// `RIDE_OFFER_MAX_ATTEMPTS` (default 5).
+pub fn max_offers() -> u64 {
+    env::var("RIDE_OFFER_MAX_ATTEMPTS")
+        .ok()
+        .and_then(|value| value.parse::<u64>().ok())
+        .filter(|attempts| *attempts > 0)
+        .unwrap_or(5)
+}
+
+/// Matches offers still waiting for an answer.
+pub fn outstanding() -> Condition {
+    Condition::all()
+        .add(rideoffer::Column::Status.eq(STATUS_PENDING))
+        .add(rideoffer::Column::ExpiresAt.gt(Utc::now().naive_utc()))
+}
+
+/// Offers `ride` to `candidate`, who must have been claimed in the same transaction.
+pub async fn create_offer<C: ConnectionTrait>(
+    conn: &C,
+    ride: &rideentity::Model,
+    candidate: &Candidate,
+) -> Result<rideoffer::Model, DbErr> {
+    let now = Utc::now().naive_utc();
+    let offer = rideoffer::ActiveModel {
+        ride_id: Set(ride.id),
+        driver_id: Set(candidate.driver.id),
+        vehicle_id: Set(candidate.vehicle.id),
+        status: Set(STATUS_PENDING.to_string()),
+        pickup_distance_km: Set(candidate.pickup_distance_km),
+        offered_at: Set(now),
+        expires_at: Set(now + offer_timeout()),
+        ..Default::default()
+    }
+    .insert(conn)
+    .await?;
+
+    info!(
+        "Ride {} offered to driver {} ({:.1} km from pickup)",
+        ride.id, candidate.driver.id, candidate.pickup_distance_km
+    );
+    Ok(offer)
+}
+
+/// Offers the ride to the nearest driver who has not been offered it yet. Gives up
+/// and moves the ride to `no_drivers` after `max_offers` attempts or when no candidate
+/// is left. Returns the ride if it was given up on, so the rider can be told.
+pub async fn offer_next<C: ConnectionTrait>(conn: &C, ride_id: i32) -> Result<Option<rideentity::Model>, DbErr> {
+    let ride = match rideentity::Entity::find_by_id(ride_id).one(conn).await? {
+        Some(ride) if ride.status == RideStatus::Requested && ride.driver_id.is_none() => ride,
+        _ => return Ok(None),
+    };
+
+    let previous = rideoffer::Entity::find()
+        .filter(rideoffer::Column::RideId.eq(ride.id))
+        .all(conn)
+        .await?;
+    if previous.iter().any(|offer| offer.status == STATUS_PENDING && offer.expires_at > Utc::now().naive_utc()) {
+        return Ok(None);
+    }
+
+    if (previous.len() as u64) < max_offers() {
+        let offered: HashSet<i32> = previous.iter().map(|offer| offer.driver_id).collect();
+        let pickup = Coordinates { lat: ride.pickup_lat, lng: ride.pickup_lng };
+        if let Some(candidate) = dispatch::next_candidate(conn, &ride.vehicle_type, pickup, &offered).await? {
+            create_offer(conn, &ride, &candidate).await?;
+            return Ok(None);
+        }
+    }
+
+    info!("Ride {} found no driver after {} offers", ride.id, previous.len());
+    rides::apply_transition(conn, ride.id, RideStatus::Requested, RideStatus::NoDrivers, None).await
+}
+
+/// Accepts `offer` and assigns its driver and vehicle to the ride. Returns `None` if
+/// the offer was already answered or has expired, or the ride has moved on; the caller
+/// should then roll back.
+pub async fn accept<C: ConnectionTrait>(
+    conn: &C,
+    offer: &rideoffer::Model,
+) -> Result<Option<rideentity::Model>, DbErr> {
+    let now = Utc::now();
+    let answered = rideoffer::Entity::update_many()
+        .col_expr(rideoffer::Column::Status, Expr::value(STATUS_ACCEPTED))
+        .col_expr(rideoffer::Column::RespondedAt, Expr::value(now.naive_utc()))
+        .filter(rideoffer::Column::Id.eq(offer.id))
+        .filter(outstanding())
+        .exec(conn)
+        .await?;
+    if answered.rows_affected == 0 {
+        return Ok(None);
+    }
+
+    let assigned = rideentity::Entity::update_many()
+        .col_expr(rideentity::Column::Status, Expr::value(RideStatus::Accepted))
+        .col_expr(rideentity::Column::DriverId, Expr::value(offer.driver_id))
+        .col_expr(rideentity::Column::VehicleId, Expr::value(offer.vehicle_id))
+        .col_expr(rideentity::Column::UpdatedAt, Expr::value(now))
+        .filter(rideentity::Column::Id.eq(offer.ride_id))
+        .filter(rideentity::Column::Status.eq(RideStatus::Requested))
+        .filter(rideentity::Column::DriverId.is_null())
+        .exec(conn)
+        .await?;
+    if assigned.rows_affected == 0 {
+        return Ok(None);
+    }
+
+    rideentity::Entity::find_by_id(offer.ride_id).one(conn).await
+}
+
+/// Records the driver's refusal. Returns `false` if the offer was no longer pending.
+pub async fn decline<C: ConnectionTrait>(conn: &C, offer: &rideoffer::Model, reason: &str) -> Result<bool, DbErr> {
+    let result = rideoffer::Entity::update_many()
+        .col_expr(rideoffer::Column::Status, Expr::value(STATUS_DECLINED))
+        .col_expr(rideoffer::Column::DeclineReason, Expr::value(reason))
+        .col_expr(rideoffer::Column::RespondedAt, Expr::value(Utc::now().naive_utc()))
+        .filter(rideoffer::Column::Id.eq(offer.id))
+        .filter(outstanding())
+        .exec(conn)
+        .await?;
+    Ok(result.rows_affected > 0)
+}
+
+/// Withdraws any unanswered offer for a ride that was cancelled.
+pub async fn withdraw_pending<C: ConnectionTrait>(conn: &C, ride_id: i32) -> Result<(), DbErr> {
+    rideoffer::Entity::update_many()
+        .col_expr(rideoffer::Column::Status, Expr::value(STATUS_WITHDRAWN))
+        .filter(rideoffer::Column::RideId.eq(ride_id))
+        .filter(rideoffer::Column::Status.eq(STATUS_PENDING))
+        .exec(conn)
+        .await?;
+    Ok(())
+}
+
+/// Tells the rider that no driver could be found for their ride.
+pub async fn notify_no_drivers(db: &DatabaseConnection, mailer: &dyn Mailer, ride: &rideentity::Model) {
+    let Some(user_id) = ride.user_id else { return };
+    let user = match userentity::Entity::find_by_id(user_id).one(db).await {
+        Ok(Some(user)) => user,
+        Ok(None) => return,
+        Err(e) => {
+            error!("Failed to load rider {} of ride {}: {}", user_id, ride.id, e);
+            return;
+        }
+    };
+
+    let email = Email {
+        to: user.email.clone(),
+        subject: "We couldn't find a driver for your ride".to_string(),
+        body: format!(
+            "Hi {},\n\nSorry, no driver was able to take your ride from {} to {}. You have not been charged. Please try booking again in a few minutes.",
+            user.first_name, ride.pickup_location, ride.dropoff_location
+        ),
+    };
+    if let Err(e) = mailer.send(&email).await {
+        error!("Failed to send no-drivers email for ride {}: {}", ride.id, e);
+    }
+}
+
+/// Expires one overdue offer and passes its ride on. Returns the ride if it was given up on.
+async fn expire_offer(db: &DatabaseConnection, offer: &rideoffer::Model) -> Result<Option<rideentity::Model>, DbErr> {
+    let txn = db.begin().await?;
+    let expired = rideoffer::Entity::update_many()
+        .col_expr(rideoffer::Column::Status, Expr::value(STATUS_EXPIRED))
+        .filter(rideoffer::Column::Id.eq(offer.id))
+        .filter(rideoffer::Column::Status.eq(STATUS_PENDING))
+        .exec(&txn)
+        .await?;
+    if expired.rows_affected == 0 {
+        return Ok(None);
+    }
+
+    info!("Offer {} of ride {} to driver {} expired", offer.id, offer.ride_id, offer.driver_id);
+    let abandoned = offer_next(&txn, offer.ride_id).await?;
+    txn.commit().await?;
+    Ok(abandoned)
+}
+
+async fn expire_due_offers(db: &DatabaseConnection, mailer: &dyn Mailer) -> Result<(), DbErr> {
+    let due = rideoffer::Entity::find()
+        .filter(rideoffer::Column::Status.eq(STATUS_PENDING))
+        .filter(rideoffer::Column::ExpiresAt.lte(Utc::now().naive_utc()))
+        .all(db)
+        .await?;
+
+    for offer in due {
+        match expire_offer(db, &offer).await {
+            Ok(Some(ride)) => notify_no_drivers(db, mailer, &ride).await,
+            Ok(None) => {}
+            Err(e) => error!("Failed to expire ride offer {}: {}", offer.id, e),
+        }
+    }
+    Ok(())
+}
+
+/// Rides that were left without an offer, e.g. by a restart between a decline and the
+/// next offer, are passed on again.
+async fn resume_stalled_rides(db: &DatabaseConnection, mailer: &dyn Mailer) -> Result<(), DbErr> {
+    let waiting = rideentity::Entity::find()
+        .filter(rideentity::Column::Status.eq(RideStatus::Requested))
+        .filter(rideentity::Column::DriverId.is_null())
+        .all(db)
+        .await?;
+
+    for ride in waiting {
+        let pending = rideoffer::Entity::find()
+            .filter(rideoffer::Column::RideId.eq(ride.id))
+            .filter(rideoffer::Column::Status.eq(STATUS_PENDING))
+            .count(db)
+            .await?;
+        if pending > 0 {
+            continue;
+        }
+
+        let txn = db.begin().await?;
+        let abandoned = offer_next(&txn, ride.id).await?;
+        txn.commit().await?;
+        if let Some(ride) = abandoned {
+            notify_no_drivers(db, mailer, &ride).await;
+        }
+    }
+    Ok(())
+}
+
+pub async fn run_offer_worker(db: Arc<DatabaseConnection>, mailer: Arc<dyn Mailer>) {
+    let db = db.as_ref();
+    if let Err(e) = resume_stalled_rides(db, mailer.as_ref()).await {
+        error!("Failed to resume rides waiting for a driver: {}", e);
+    }
+
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(WORKER_INTERVAL_SECONDS));
+    loop {
+        interval.tick().await;
+        if let Err(e) = expire_due_offers(db, mailer.as_ref()).await {
+            error!("Ride offer worker failed: {}", e);
+        }
+    }
+}
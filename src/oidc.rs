@@ -0,0 +1,291 @@
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::env;
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+
+/// How long a fetched JWKS is trusted before it is fetched again.
+const JWKS_CACHE_TTL: Duration = Duration::from_secs(60 * 60);
+/// Signature algorithms accepted on ID tokens. Symmetric algorithms are never accepted.
+const ID_TOKEN_ALGORITHMS: [Algorithm; 2] = [Algorithm::RS256, Algorithm::ES256];
+
+/// One OpenID Connect identity provider.
+///
+/// Configured from `OIDC_<NAME>_*` variables. `google` and `apple` come with their
+/// public endpoints; any of them can be overridden, e.g. to point at a mock provider.
+/// Apple's client secret is the ES256 JWT generated from the team's key.
+#[derive(Debug, Clone)]
+pub struct OidcProvider {
+    pub name: String,
+    pub client_id: String,
+    client_secret: String,
+    pub issuer: String,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub jwks_uri: String,
+    pub redirect_uri: String,
+    pub scopes: String,
+}
+
+fn provider_defaults(name: &str) -> (&'static str, &'static str, &'static str, &'static str) {
+    match name {
+        "google" => (
+            "https://accounts.google.com",
+            "https://accounts.google.com/o/oauth2/v2/auth",
+            "https://oauth2.googleapis.com/token",
+            "https://www.googleapis.com/oauth2/v3/certs",
+        ),
+        "apple" => (
+            "https://appleid.apple.com",
+            "https://appleid.apple.com/auth/authorize",
+            "https://appleid.apple.com/auth/token",
+            "https://appleid.apple.com/auth/keys",
+        ),
+        _ => ("", "", "", ""),
+    }
+}
+
+impl OidcProvider {
+    fn from_env(name: &str) -> Result<Self, String> {
+        let prefix = format!("OIDC_{}_", name.to_uppercase());
+        let (issuer, authorization_endpoint, token_endpoint, jwks_uri) = provider_defaults(name);
+        let var = |suffix: &str, default: &str| -> Result<String, String> {
+            match env::var(format!("{}{}", prefix, suffix)) {
+                Ok(value) => Ok(value),
+                Err(_) if !default.is_empty() => Ok(default.to_string()),
+                Err(_) => Err(format!("{}{} must be set", prefix, suffix)),
+            }
+        };
+
+        Ok(OidcProvider {
+            name: name.to_string(),
+            client_id: var("CLIENT_ID", "")?,
+            client_secret: var("CLIENT_SECRET", "")?,
+            issuer: var("ISSUER", issuer)?,
+            authorization_endpoint: var("AUTHORIZATION_ENDPOINT", authorization_endpoint)?,
+            token_endpoint: var("TOKEN_ENDPOINT", token_endpoint)?,
+            jwks_uri: var("JWKS_URI", jwks_uri)?,
+            redirect_uri: var("REDIRECT_URI", "")?,
+            scopes: var("SCOPES", "openid email profile")?,
+        })
+    }
+}
+
+/// `email_verified` is a boolean for most providers but a string for Apple.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum EmailVerified {
+    Bool(bool),
+    String(String),
+}
+
+/// The ID token claims we rely on. Signature, issuer, audience and expiry are
+/// checked by `OidcClient::validate_id_token`.
+#[derive(Debug, Deserialize)]
+pub struct IdTokenClaims {
+    pub sub: String,
+    pub email: Option<String>,
+    email_verified: Option<EmailVerified>,
+    nonce: Option<String>,
+    pub given_name: Option<String>,
+    pub family_name: Option<String>,
+}
+
+impl IdTokenClaims {
+    /// The email address, only if the provider says it has verified it.
+    pub fn verified_email(&self) -> Option<&str> {
+        let verified = match &self.email_verified {
+            Some(EmailVerified::Bool(verified)) => *verified,
+            Some(EmailVerified::String(verified)) => verified == "true",
+            None => false,
+        };
+        self.email.as_deref().filter(|_| verified)
+    }
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+struct CachedJwks {
+    keys: JwkSet,
+    fetched_at: Instant,
+}
+
+/// Runs the authorization code flow against the configured providers.
+/// Handlers take it as `web::Data<OidcClient>`.
+pub struct OidcClient {
+    providers: HashMap<String, OidcProvider>,
+    http: reqwest::Client,
+    jwks: RwLock<HashMap<String, CachedJwks>>,
+}
+
+impl OidcClient {
+    /// Loads the providers listed in `OIDC_PROVIDERS` (comma separated, may be empty).
+    pub fn from_env() -> Result<Self, String> {
+        let mut providers = HashMap::new();
+        let names = env::var("OIDC_PROVIDERS").unwrap_or_default();
+        for name in names.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+            providers.insert(name.to_lowercase(), OidcProvider::from_env(&name.to_lowercase())?);
+        }
+
+        let http = reqwest::Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build()
+            .map_err(|e| format!("Failed to build HTTP client: {}", e))?;
+
+        Ok(OidcClient {
+            providers,
+            http,
+            jwks: RwLock::new(HashMap::new()),
+        })
+    }
+
+    pub fn provider(&self, name: &str) -> Option<&OidcProvider> {
+        self.providers.get(name)
+    }
+
+    /// The provider URL to send the user to, using PKCE with `code_verifier`.
+    pub fn authorization_url(
+        &self,
+        provider: &OidcProvider,
+        state: &str,
+        nonce: &str,
+        code_verifier: &str,
+    ) -> Result<String, String> {
+        let challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(code_verifier.as_bytes()));
+        let url = reqwest::Url::parse_with_params(
+            &provider.authorization_endpoint,
+            &[
+                ("response_type", "code"),
+                ("client_id", provider.client_id.as_str()),
+                ("redirect_uri", provider.redirect_uri.as_str()),
+                ("scope", provider.scopes.as_str()),
+                ("state", state),
+                ("nonce", nonce),
+                ("code_challenge", challenge.as_str()),
+                ("code_challenge_method", "S256"),
+            ],
+        )
+        .map_err(|e| format!("Invalid authorization endpoint for {}: {}", provider.name, e))?;
+        Ok(url.to_string())
+    }
+
+    /// Exchanges an authorization code for the provider's ID token.
+    pub async fn exchange_code(
+        &self,
+        provider: &OidcProvider,
+        code: &str,
+        code_verifier: &str,
+    ) -> Result<String, String> {
+        let response = self
+            .http
+            .post(&provider.token_endpoint)
+            .form(&[
+                ("grant_type", "authorization_code"),
+                ("code", code),
+                ("redirect_uri", provider.redirect_uri.as_str()),
+                ("client_id", provider.client_id.as_str()),
+                ("client_secret", provider.client_secret.as_str()),
+                ("code_verifier", code_verifier),
+            ])
+            .send()
+            .await
+            .map_err(|e| format!("Token request to {} failed: {}", provider.name, e))?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let body = response.text().await.unwrap_or_default();
+            return Err(format!("{} token endpoint returned {}: {}", provider.name, status, body));
+        }
+
+        response
+            .json::<TokenResponse>()
+            .await
+            .map(|tokens| tokens.id_token)
+            .map_err(|e| format!("Invalid token response from {}: {}", provider.name, e))
+    }
+
+    async fn fetch_jwks(&self, provider: &OidcProvider) -> Result<JwkSet, String> {
+        let keys = self
+            .http
+            .get(&provider.jwks_uri)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| format!("JWKS request to {} failed: {}", provider.name, e))?
+            .json::<JwkSet>()
+            .await
+            .map_err(|e| format!("Invalid JWKS from {}: {}", provider.name, e))?;
+
+        if let Ok(mut cache) = self.jwks.write() {
+            cache.insert(
+                provider.name.clone(),
+                CachedJwks {
+                    keys: keys.clone(),
+                    fetched_at: Instant::now(),
+                },
+            );
+        }
+        Ok(keys)
+    }
+
+    /// Finds the signing key `kid`, refetching the JWKS when it is stale or the key is
+    /// unknown (providers rotate keys without notice).
+    async fn decoding_key(&self, provider: &OidcProvider, kid: &str) -> Result<DecodingKey, String> {
+        let cached = self.jwks.read().ok().and_then(|cache| {
+            cache
+                .get(&provider.name)
+                .filter(|cached| cached.fetched_at.elapsed() < JWKS_CACHE_TTL)
+                .and_then(|cached| cached.keys.find(kid).cloned())
+        });
+
+        let jwk = match cached {
+            Some(jwk) => jwk,
+            None => self
+                .fetch_jwks(provider)
+                .await?
+                .find(kid)
+                .cloned()
+                .ok_or_else(|| format!("Unknown signing key '{}' for {}", kid, provider.name))?,
+        };
+
+        DecodingKey::from_jwk(&jwk).map_err(|e| format!("Unusable signing key '{}': {}", kid, e))
+    }
+
+    /// Verifies the ID token's signature against the provider's JWKS, its issuer,
+    /// audience and expiry, and that it carries the `nonce` we sent.
+    pub async fn validate_id_token(
+        &self,
+        provider: &OidcProvider,
+        id_token: &str,
+        nonce: &str,
+    ) -> Result<IdTokenClaims, String> {
+        let header = decode_header(id_token).map_err(|e| format!("Malformed ID token: {}", e))?;
+        if !ID_TOKEN_ALGORITHMS.contains(&header.alg) {
+            return Err(format!("ID token algorithm {:?} is not allowed", header.alg));
+        }
+        let kid = header.kid.ok_or_else(|| "ID token has no kid".to_string())?;
+        let key = self.decoding_key(provider, &kid).await?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_issuer(&[&provider.issuer]);
+        validation.set_audience(&[&provider.client_id]);
+        validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+
+        let claims = decode::<IdTokenClaims>(id_token, &key, &validation)
+            .map_err(|e| format!("Invalid ID token: {}", e))?
+            .claims;
+
+        if claims.nonce.as_deref() != Some(nonce) {
+            return Err("ID token nonce does not match".to_string());
+        }
+        Ok(claims)
+    }
+}
@@ -0,0 +1,182 @@
+//! Password hashing and policy.
+//!
+//! New hashes are Argon2id. Hashes created before the switch are bcrypt; they still
+//! verify, and `needs_rehash` flags them so login can upgrade them in place.
+//!
+//! Only depends on `argon2` and `bcrypt` so the `password_hasher` tool can share it.
+
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::{Algorithm, Argon2, Params, Version};
+use std::env;
+use std::sync::OnceLock;
+
+pub const MIN_PASSWORD_LENGTH: usize = 10;
+pub const MAX_PASSWORD_LENGTH: usize = 128;
+
+/// Rejected outright regardless of length. Compared case-insensitively.
+const COMMON_PASSWORDS: &[&str] = &[
+    "123456789012", "1234567890", "12345678910", "0123456789", "1q2w3e4r5t",
+    "qwertyuiop", "qwerty12345", "qwerty123456", "1qaz2wsx3edc", "asdfghjkl1",
+    "password1234", "password123", "password12", "passw0rd123", "p@ssw0rd123",
+    "iloveyou123", "welcome123", "welcome1234", "letmein123", "sunshine123",
+    "princess123", "football123", "baseball123", "superman123", "dragon1234",
+    "monkey12345", "trustno1234", "abc1234567", "abcdefghij", "aaaaaaaaaa",
+    "administrator", "changeme123", "qazwsxedcrfv", "zaq12wsxcde", "1111111111",
+];
+
+/// Argon2id cost parameters, read from `PASSWORD_ARGON2_MEMORY_KIB`,
+/// `PASSWORD_ARGON2_ITERATIONS` and `PASSWORD_ARGON2_PARALLELISM`.
+/// Defaults follow the OWASP baseline (19 MiB, 2 iterations, 1 lane).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HashParams {
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+impl Default for HashParams {
+    fn default() -> Self {
+        HashParams {
+            memory_kib: 19 * 1024,
+            iterations: 2,
+            parallelism: 1,
+        }
+    }
+}
+
+impl HashParams {
+    pub fn from_env() -> Result<Self, String> {
+        let defaults = HashParams::default();
+        let params = HashParams {
+            memory_kib: env_u32("PASSWORD_ARGON2_MEMORY_KIB", defaults.memory_kib)?,
+            iterations: env_u32("PASSWORD_ARGON2_ITERATIONS", defaults.iterations)?,
+            parallelism: env_u32("PASSWORD_ARGON2_PARALLELISM", defaults.parallelism)?,
+        };
+        params.argon2_params()?;
+        Ok(params)
+    }
+
+    fn argon2_params(&self) -> Result<Params, String> {
+        Params::new(self.memory_kib, self.iterations, self.parallelism, None)
+            .map_err(|e| format!("Invalid Argon2 parameters: {}", e))
+    }
+}
+
+fn env_u32(name: &str, default: u32) -> Result<u32, String> {
+    match env::var(name) {
+        Ok(value) => value.parse().map_err(|_| format!("{} must be a positive integer", name)),
+        Err(_) => Ok(default),
+    }
+}
+
+static PARAMS: OnceLock<HashParams> = OnceLock::new();
+
+/// Loads the hashing parameters from the environment. Call once at startup.
+pub fn init_params() -> Result<(), String> {
+    let params = HashParams::from_env()?;
+    PARAMS
+        .set(params)
+        .map_err(|_| "Password hashing parameters already initialized".to_string())
+}
+
+fn params() -> HashParams {
+    *PARAMS.get_or_init(HashParams::default)
+}
+
+fn hasher(params: HashParams) -> Result<Argon2<'static>, String> {
+    Ok(Argon2::new(Algorithm::Argon2id, Version::V0x13, params.argon2_params()?))
+}
+
+fn is_bcrypt(hash: &str) -> bool {
+    hash.starts_with("$2a$") || hash.starts_with("$2b$") || hash.starts_with("$2y$")
+}
+
+/// Hashes `password` with Argon2id and a random salt, in PHC string format.
+pub fn hash_password(password: &str) -> Result<String, String> {
+    let salt = SaltString::generate(&mut OsRng);
+    hasher(params())?
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| format!("Failed to hash password: {}", e))
+}
+
+/// Checks `password` against a stored Argon2 or legacy bcrypt hash.
+pub fn verify_password(password: &str, stored_hash: &str) -> Result<bool, String> {
+    if is_bcrypt(stored_hash) {
+        return bcrypt::verify(password, stored_hash).map_err(|e| format!("Invalid bcrypt hash: {}", e));
+    }
+
+    let parsed = PasswordHash::new(stored_hash).map_err(|e| format!("Invalid password hash: {}", e))?;
+    Ok(Argon2::default().verify_password(password.as_bytes(), &parsed).is_ok())
+}
+
+static DUMMY_HASH: OnceLock<String> = OnceLock::new();
+
+/// Does the work of `verify_password` against a throwaway hash, for logins to accounts
+/// that do not exist, so response times do not reveal which emails are registered.
+pub fn verify_dummy_password(password: &str) {
+    let hash = DUMMY_HASH.get_or_init(|| {
+        hash_password("unknown-account-placeholder").unwrap_or_else(|e| {
+            eprintln!("Failed to create dummy password hash: {}", e);
+            String::new()
+        })
+    });
+    let _ = verify_password(password, hash);
+}
+
+/// Whether a stored hash should be replaced: anything that is not Argon2id with the
+/// current parameters.
+pub fn needs_rehash(stored_hash: &str) -> bool {
+    let parsed = match PasswordHash::new(stored_hash) {
+        Ok(parsed) => parsed,
+        Err(_) => return true,
+    };
+    if parsed.algorithm != Algorithm::Argon2id.ident() {
+        return true;
+    }
+
+    let current = params();
+    match Params::try_from(&parsed) {
+        Ok(stored) => {
+            stored.m_cost() != current.memory_kib
+                || stored.t_cost() != current.iterations
+                || stored.p_cost() != current.parallelism
+        }
+        Err(_) => true,
+    }
+}
+
+/// Enforces the password policy for a new password belonging to `email`.
+pub fn check_policy(password: &str, email: &str) -> Result<(), String> {
+    let length = password.chars().count();
+    if length < MIN_PASSWORD_LENGTH {
+        return Err(format!("Password must be at least {} characters", MIN_PASSWORD_LENGTH));
+    }
+    if length > MAX_PASSWORD_LENGTH {
+        return Err(format!("Password must be at most {} characters", MAX_PASSWORD_LENGTH));
+    }
+
+    let lowered = password.to_lowercase();
+    if COMMON_PASSWORDS.contains(&lowered.as_str()) {
+        return Err("Password is too common".to_string());
+    }
+
+    // The mailbox name and each domain label except the TLD, e.g. "jane.doe",
+    // "jane", "doe" and "example" for jane.doe@example.com.
+    let email = email.trim().to_lowercase();
+    let (local, domain) = email.split_once('@').unwrap_or((email.as_str(), ""));
+    let mut labels: Vec<&str> = domain.split('.').collect();
+    labels.pop();
+
+    let fragments = std::iter::once(local)
+        .chain(local.split(|c: char| !c.is_alphanumeric()))
+        .chain(labels);
+    for fragment in fragments {
+        if fragment.chars().count() >= 3 && lowered.contains(fragment) {
+            return Err("Password must not contain parts of your email address".to_string());
+        }
+    }
+
+    Ok(())
+}
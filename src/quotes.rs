@@ -0,0 +1,193 @@
+//! Fare quotes. A quote estimates the trip and gives a fare range and a fixed fare for
+//! every vehicle type with a driver dispatch could send. It comes back as a short-lived
+//! signed token; booking with it locks the signed fare in, so the rider pays what they
+//! were shown rather than the metered fare. Each quote books at most one ride.
+
+use chrono::{Duration, Utc};
+use jsonwebtoken::errors::Error;
+use rust_decimal::Decimal;
+use sea_orm::{ColumnTrait, ConnectionTrait, DbErr, EntityTrait, QueryFilter, QuerySelect};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use crate::auth::{generate_token_id, sign_claims, token_issuer, verify_claims};
+use crate::dispatch;
+use crate::entities::vehicleentity;
+use crate::fares::{self, RateCard};
+use crate::routing::{Coordinates, RouteEstimate};
+
+pub const QUOTE_TTL_MINUTES: i64 = 10;
+
+/// Kept apart from the auth token audience so a quote can never be used to sign in.
+const QUOTE_AUDIENCE: &str = "fare-quote";
+
+/// Headroom on the estimate for the top of the range: longer routes and slower traffic.
+const DISTANCE_HEADROOM: f64 = 1.15;
+const DURATION_HEADROOM: f64 = 1.25;
+
+/// Quoted and booked coordinates may differ by this much after a JSON round trip.
+const COORDINATE_TOLERANCE: f64 = 1e-6;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FareRange {
+    pub vehicle_type: String,
+    pub low: Decimal,
+    pub high: Decimal,
+    /// What a booking with the quote is charged: the median of the type's prices at
+    /// the unpadded estimate, so no single driver's rate card sets it.
+    pub fare: Decimal,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Quote {
+    pub pickup_lat: f64,
+    pub pickup_lng: f64,
+    pub dropoff_lat: f64,
+    pub dropoff_lng: f64,
+    pub distance_km: f64,
+    pub duration_minutes: f64,
+    pub fares: Vec<FareRange>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct QuoteClaims {
+    /// The user the quote was issued to.
+    pub sub: String,
+    pub iss: String,
+    pub aud: String,
+    pub iat: usize,
+    pub exp: usize,
+    pub jti: String,
+    #[serde(flatten)]
+    pub quote: Quote,
+}
+
+impl Quote {
+    pub fn matches_trip(&self, pickup: Coordinates, dropoff: Coordinates) -> bool {
+        let close = |a: f64, b: f64| (a - b).abs() <= COORDINATE_TOLERANCE;
+        close(self.pickup_lat, pickup.lat)
+            && close(self.pickup_lng, pickup.lng)
+            && close(self.dropoff_lat, dropoff.lat)
+            && close(self.dropoff_lng, dropoff.lng)
+    }
+
+    pub fn covers(&self, vehicle_type: &str) -> bool {
+        self.fares.iter().any(|range| range.vehicle_type == vehicle_type)
+    }
+
+    /// The fare signed into the quote for `vehicle_type`, or `None` if that type was
+    /// not quoted.
+    pub fn locked_fare(&self, vehicle_type: &str) -> Option<Decimal> {
+        self.fares
+            .iter()
+            .find(|range| range.vehicle_type == vehicle_type)
+            .map(|range| range.fare)
+    }
+}
+
+fn minutes(value: f64) -> Duration {
+    Duration::seconds((value * 60.0).round() as i64)
+}
+
+fn round_2dp(value: f64) -> f64 {
+    (value * 100.0).round() / 100.0
+}
+
+/// Rate cards of the vehicles dispatch could send to `pickup` right now, by vehicle
+/// type. Uses `dispatch::find_candidates`, so a type is only quoted if booking it could
+/// find a driver.
+async fn available_rate_cards<C: ConnectionTrait>(
+    conn: &C,
+    pickup: Coordinates,
+    vehicle_type: Option<&str>,
+) -> Result<BTreeMap<String, Vec<RateCard>>, DbErr> {
+    let vehicle_types: Vec<String> = match vehicle_type {
+        Some(vehicle_type) => vec![vehicle_type.to_string()],
+        None => vehicleentity::Entity::find()
+            .select_only()
+            .column(vehicleentity::Column::VehicleType)
+            .distinct()
+            .filter(vehicleentity::Column::Status.eq(dispatch::VEHICLE_ACTIVE))
+            .into_tuple()
+            .all(conn)
+            .await?,
+    };
+
+    let mut cards: BTreeMap<String, Vec<RateCard>> = BTreeMap::new();
+    for vehicle_type in vehicle_types {
+        let candidates = dispatch::find_candidates(conn, &vehicle_type, pickup, &HashSet::new()).await?;
+        let rates: Vec<RateCard> = candidates
+            .iter()
+            .filter_map(|candidate| RateCard::from_vehicle(&candidate.vehicle))
+            .collect();
+        if !rates.is_empty() {
+            cards.insert(vehicle_type, rates);
+        }
+    }
+    Ok(cards)
+}
+
+/// Prices `estimate` for each available vehicle type, or just `vehicle_type` if given.
+pub async fn build_quote<C: ConnectionTrait>(
+    conn: &C,
+    pickup: Coordinates,
+    dropoff: Coordinates,
+    estimate: RouteEstimate,
+    vehicle_type: Option<&str>,
+) -> Result<Quote, DbErr> {
+    let distance_km = round_2dp(estimate.distance_km);
+    let duration_minutes = round_2dp(estimate.duration_minutes);
+    let padded_distance = distance_km * DISTANCE_HEADROOM;
+    let padded_duration = minutes(duration_minutes * DURATION_HEADROOM);
+
+    let fares = available_rate_cards(conn, pickup, vehicle_type)
+        .await?
+        .into_iter()
+        .filter_map(|(vehicle_type, cards)| {
+            let mut estimated: Vec<Decimal> = cards
+                .iter()
+                .map(|rates| fares::calculate(*rates, distance_km, minutes(duration_minutes)).total)
+                .collect();
+            estimated.sort();
+            let low = *estimated.first()?;
+            let fare = estimated[(estimated.len() - 1) / 2];
+            let high = cards
+                .iter()
+                .map(|rates| fares::calculate(*rates, padded_distance, padded_duration).total)
+                .max()?;
+            Some(FareRange { vehicle_type, low, high, fare })
+        })
+        .collect();
+
+    Ok(Quote {
+        pickup_lat: pickup.lat,
+        pickup_lng: pickup.lng,
+        dropoff_lat: dropoff.lat,
+        dropoff_lng: dropoff.lng,
+        distance_km,
+        duration_minutes,
+        fares,
+    })
+}
+
+/// Signs `quote` for `user_id`. Returns the token and when it expires.
+pub fn sign_quote(quote: Quote, user_id: i32) -> Result<(String, chrono::DateTime<Utc>), Error> {
+    let now = Utc::now();
+    let expires_at = now + Duration::minutes(QUOTE_TTL_MINUTES);
+    let claims = QuoteClaims {
+        sub: user_id.to_string(),
+        iss: token_issuer(),
+        aud: QUOTE_AUDIENCE.to_string(),
+        iat: now.timestamp() as usize,
+        exp: expires_at.timestamp() as usize,
+        jti: generate_token_id(),
+        quote,
+    };
+    Ok((sign_claims(&claims)?, expires_at))
+}
+
+/// The claims in `token`, if the signature holds, it has not expired and it was issued
+/// to `user_id`. The `jti` identifies the quote when it is booked.
+pub fn verify_quote(token: &str, user_id: i32) -> Option<QuoteClaims> {
+    let claims: QuoteClaims = verify_claims(token, QUOTE_AUDIENCE).ok()?;
+    (claims.sub == user_id.to_string()).then_some(claims)
+}
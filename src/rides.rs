@@ -0,0 +1,164 @@
+//! The ride lifecycle:
+//!
+//! ```text
+//! requested -> accepted -> driver_arrived -> in_progress -> completed
+//! requested | accepted | driver_arrived -> cancelled
+//! requested -> no_drivers
+//! ```
+//!
+//! Clients never set the status directly; they ask for an action and the server moves
+//! the ride if that action is allowed from its current status. A ride is accepted by
+//! answering its offer (see `crate::offers`), and only dispatch gives up on it.
+
+use chrono::Utc;
+use sea_orm::sea_query::Expr;
+use sea_orm::{ColumnTrait, ConnectionTrait, DbErr, EntityTrait, QueryFilter};
+use crate::entities::rideentity::{self, RideStatus};
+
+/// Statuses in which a ride still occupies its driver.
+pub const ACTIVE_STATUSES: [RideStatus; 4] = [
+    RideStatus::Requested,
+    RideStatus::Accepted,
+    RideStatus::DriverArrived,
+    RideStatus::InProgress,
+];
+
+/// Payment status of a newly booked ride.
+pub const PAYMENT_PENDING: &str = "pending";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RideAction {
+    Accept,
+    Arrive,
+    Start,
+    Complete,
+    Cancel,
+}
+
+impl RideAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RideAction::Accept => "accept",
+            RideAction::Arrive => "arrive",
+            RideAction::Start => "start",
+            RideAction::Complete => "complete",
+            RideAction::Cancel => "cancel",
+        }
+    }
+
+    /// The status a ride in `from` moves to, or `None` if the action is not allowed there.
+    pub fn transition(self, from: RideStatus) -> Option<RideStatus> {
+        match (self, from) {
+            (RideAction::Accept, RideStatus::Requested) => Some(RideStatus::Accepted),
+            (RideAction::Arrive, RideStatus::Accepted) => Some(RideStatus::DriverArrived),
+            (RideAction::Start, RideStatus::DriverArrived) => Some(RideStatus::InProgress),
+            (RideAction::Complete, RideStatus::InProgress) => Some(RideStatus::Completed),
+            (
+                RideAction::Cancel,
+                RideStatus::Requested | RideStatus::Accepted | RideStatus::DriverArrived,
+            ) => Some(RideStatus::Cancelled),
+            _ => None,
+        }
+    }
+}
+
+/// Moves `ride` from `from` to `to`, stamping `start_time` when the trip starts and
+/// `end_time` when it completes.
+///
+/// The update only applies while the ride is still in `from`, so of two concurrent
+/// transitions only one wins. Returns the updated ride, or `None` if it had already moved.
+pub async fn apply_transition<C: ConnectionTrait>(
+    conn: &C,
+    ride_id: i32,
+    from: RideStatus,
+    to: RideStatus,
+    cancel_reason: Option<String>,
+) -> Result<Option<rideentity::Model>, DbErr> {
+    let now = Utc::now();
+    let mut update = rideentity::Entity::update_many()
+        .col_expr(rideentity::Column::Status, Expr::value(to))
+        .col_expr(rideentity::Column::UpdatedAt, Expr::value(now));
+
+    match to {
+        RideStatus::InProgress => {
+            update = update.col_expr(rideentity::Column::StartTime, Expr::value(now));
+        }
+        RideStatus::Completed => {
+            update = update.col_expr(rideentity::Column::EndTime, Expr::value(now));
+        }
+        RideStatus::Cancelled => {
+            update = update.col_expr(rideentity::Column::CancelReason, Expr::value(cancel_reason));
+        }
+        _ => {}
+    }
+
+    let result = update
+        .filter(rideentity::Column::Id.eq(ride_id))
+        .filter(rideentity::Column::Status.eq(from))
+        .exec(conn)
+        .await?;
+    if result.rows_affected == 0 {
+        return Ok(None);
+    }
+
+    rideentity::Entity::find_by_id(ride_id).one(conn).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sea_orm::Iterable;
+
+    #[test]
+    fn legal_transitions() {
+        let legal = [
+            (RideAction::Accept, RideStatus::Requested, RideStatus::Accepted),
+            (RideAction::Arrive, RideStatus::Accepted, RideStatus::DriverArrived),
+            (RideAction::Start, RideStatus::DriverArrived, RideStatus::InProgress),
+            (RideAction::Complete, RideStatus::InProgress, RideStatus::Completed),
+            (RideAction::Cancel, RideStatus::Requested, RideStatus::Cancelled),
+            (RideAction::Cancel, RideStatus::Accepted, RideStatus::Cancelled),
+            (RideAction::Cancel, RideStatus::DriverArrived, RideStatus::Cancelled),
+        ];
+        for (action, from, to) in legal {
+            assert_eq!(action.transition(from), Some(to), "{} from {:?}", action.as_str(), from);
+        }
+    }
+
+    #[test]
+    fn illegal_transitions() {
+        let illegal = [
+            (RideAction::Cancel, RideStatus::Completed),
+            (RideAction::Cancel, RideStatus::InProgress),
+            (RideAction::Cancel, RideStatus::Cancelled),
+            (RideAction::Complete, RideStatus::Requested),
+            (RideAction::Complete, RideStatus::Accepted),
+            (RideAction::Complete, RideStatus::DriverArrived),
+            (RideAction::Complete, RideStatus::Completed),
+            (RideAction::Start, RideStatus::Requested),
+            (RideAction::Start, RideStatus::InProgress),
+            (RideAction::Arrive, RideStatus::Requested),
+            (RideAction::Accept, RideStatus::Accepted),
+            (RideAction::Accept, RideStatus::Cancelled),
+        ];
+        for (action, from) in illegal {
+            assert_eq!(action.transition(from), None, "{} from {:?}", action.as_str(), from);
+        }
+    }
+
+    #[test]
+    fn finished_rides_are_final() {
+        let actions = [
+            RideAction::Accept,
+            RideAction::Arrive,
+            RideAction::Start,
+            RideAction::Complete,
+            RideAction::Cancel,
+        ];
+        for from in RideStatus::iter().filter(|status| !ACTIVE_STATUSES.contains(status)) {
+            for action in actions {
+                assert_eq!(action.transition(from), None, "{} from {:?}", action.as_str(), from);
+            }
+        }
+    }
+}
@@ -0,0 +1,77 @@
+//! Trip estimates for quoting. The provider is chosen with `ROUTING_PROVIDER`; only the
+//! built-in great-circle estimate exists today, but a road-network service can be
+//! plugged in behind the same trait.
+
+use async_trait::async_trait;
+use std::env;
+use std::sync::Arc;
+use crate::geo;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Coordinates {
+    pub lat: f64,
+    pub lng: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RouteEstimate {
+    pub distance_km: f64,
+    pub duration_minutes: f64,
+}
+
+/// Estimates distance and driving time between two points. Handlers take it as
+/// `web::Data<dyn RoutingProvider>`.
+#[async_trait]
+pub trait RoutingProvider: Send + Sync {
+    async fn estimate(&self, from: Coordinates, to: Coordinates) -> Result<RouteEstimate, String>;
+}
+
+/// Straight-line distance stretched by a detour factor, driven at a fixed average speed.
+pub struct GreatCircleRouter {
+    detour_factor: f64,
+    average_speed_kmh: f64,
+}
+
+impl GreatCircleRouter {
+    pub fn from_env() -> Result<Self, String> {
+        let detour_factor = env_f64("ROUTING_DETOUR_FACTOR", 1.3)?;
+        let average_speed_kmh = env_f64("ROUTING_AVERAGE_SPEED_KMH", 30.0)?;
+        if detour_factor < 1.0 {
+            return Err("ROUTING_DETOUR_FACTOR must be at least 1".to_string());
+        }
+        if average_speed_kmh <= 0.0 {
+            return Err("ROUTING_AVERAGE_SPEED_KMH must be positive".to_string());
+        }
+        Ok(GreatCircleRouter { detour_factor, average_speed_kmh })
+    }
+}
+
+fn env_f64(name: &str, default: f64) -> Result<f64, String> {
+    match env::var(name) {
+        Ok(value) => value
+            .parse::<f64>()
+            .ok()
+            .filter(|value| value.is_finite())
+            .ok_or_else(|| format!("{} must be a number", name)),
+        Err(_) => Ok(default),
+    }
+}
+
+#[async_trait]
+impl RoutingProvider for GreatCircleRouter {
+    async fn estimate(&self, from: Coordinates, to: Coordinates) -> Result<RouteEstimate, String> {
+        let distance_km = geo::great_circle_km(from.lat, from.lng, to.lat, to.lng) * self.detour_factor;
+        Ok(RouteEstimate {
+            distance_km,
+            duration_minutes: distance_km / self.average_speed_kmh * 60.0,
+        })
+    }
+}
+
+/// Picks the provider named by `ROUTING_PROVIDER` (default `great_circle`).
+pub fn router_from_env() -> Result<Arc<dyn RoutingProvider>, String> {
+    match env::var("ROUTING_PROVIDER").unwrap_or_else(|_| "great_circle".to_string()).as_str() {
+        "great_circle" => Ok(Arc::new(GreatCircleRouter::from_env()?)),
+        other => Err(format!("Unknown ROUTING_PROVIDER '{}'", other)),
+    }
+}
@@ -0,0 +1,29 @@
+use async_trait::async_trait;
+use log::info;
+use std::env;
+use std::sync::Arc;
+
+/// Outgoing SMS delivery. Handlers take it as `web::Data<dyn SmsSender>`.
+#[async_trait]
+pub trait SmsSender: Send + Sync {
+    async fn send(&self, to: &str, message: &str) -> Result<(), String>;
+}
+
+/// Prints messages to the log instead of sending them, for local development.
+pub struct ConsoleSmsSender;
+
+#[async_trait]
+impl SmsSender for ConsoleSmsSender {
+    async fn send(&self, to: &str, message: &str) -> Result<(), String> {
+        info!("SMS to {}: {}", to, message);
+        Ok(())
+    }
+}
+
+/// Picks the SMS sender from `SMS_SENDER` (only `console` for now, the default).
+pub fn sms_sender_from_env() -> Result<Arc<dyn SmsSender>, String> {
+    match env::var("SMS_SENDER").unwrap_or_else(|_| "console".to_string()).as_str() {
+        "console" => Ok(Arc::new(ConsoleSmsSender)),
+        other => Err(format!("Unknown SMS_SENDER '{}'", other)),
+    }
+}
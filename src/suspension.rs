@@ -0,0 +1,46 @@
+use chrono::Utc;
+use sea_orm::{ColumnTrait, Condition, ConnectionTrait, DbErr, EntityTrait, QueryFilter};
+use crate::entities::{accountsuspension, driverentity};
+
+pub const STATUS_ACTIVE: &str = "active";
+pub const STATUS_LIFTED: &str = "lifted";
+
+/// Matches suspensions that are currently in effect.
+pub fn in_effect() -> Condition {
+    Condition::all()
+        .add(accountsuspension::Column::Status.eq(STATUS_ACTIVE))
+        .add(
+            Condition::any()
+                .add(accountsuspension::Column::ExpiresAt.is_null())
+                .add(accountsuspension::Column::ExpiresAt.gt(Utc::now().naive_utc())),
+        )
+}
+
+/// The suspension in effect for `user_id`, if any. When several overlap, the one
+/// that lasts longest is returned.
+pub async fn active_suspension<C: ConnectionTrait>(
+    conn: &C,
+    user_id: i32,
+) -> Result<Option<accountsuspension::Model>, DbErr> {
+    let suspensions = accountsuspension::Entity::find()
+        .filter(accountsuspension::Column::UserId.eq(user_id))
+        .filter(in_effect())
+        .all(conn)
+        .await?;
+
+    Ok(suspensions
+        .into_iter()
+        .max_by_key(|suspension| suspension.expires_at.map_or((1, None), |at| (0, Some(at)))))
+}
+
+/// Whether the account behind `driver` is suspended. Dispatch must skip such drivers
+/// whatever their `availability_status` says.
+pub async fn is_driver_suspended<C: ConnectionTrait>(
+    conn: &C,
+    driver: &driverentity::Model,
+) -> Result<bool, DbErr> {
+    match driver.user_id {
+        Some(user_id) => Ok(active_suspension(conn, user_id).await?.is_some()),
+        None => Ok(false),
+    }
+}
@@ -0,0 +1,146 @@
+use actix_web::HttpRequest;
+use chrono::{Duration, NaiveDateTime, Utc};
+use log::warn;
+use sea_orm::{ActiveModelTrait, ColumnTrait, ConnectionTrait, DbErr, EntityTrait, QueryFilter, Set};
+use std::env;
+use crate::entities::loginattempt;
+
+/// Failed logins allowed against one account before it is locked.
+pub const MAX_ACCOUNT_FAILURES: i32 = 5;
+/// Failed logins allowed from one IP before it is locked. Higher than the account
+/// limit because many users can share an address behind NAT.
+pub const MAX_IP_FAILURES: i32 = 20;
+const LOCKOUT_MINUTES: i64 = 15;
+/// Failures older than this no longer count towards a lockout.
+const FAILURE_WINDOW_MINUTES: i64 = 15;
+const BACKOFF_BASE_SECONDS: i64 = 1;
+const BACKOFF_MAX_SECONDS: i64 = 60;
+
+pub fn account_key(email: &str) -> String {
+    format!("account:{}", email.trim().to_lowercase())
+}
+
+pub fn ip_key(ip: &str) -> String {
+    format!("ip:{}", ip)
+}
+
+/// The caller's IP. Proxy headers are only honoured with `TRUST_PROXY_HEADERS=true`,
+/// since clients can set them freely.
+pub fn client_ip(req: &HttpRequest) -> String {
+    let trust_proxy = env::var("TRUST_PROXY_HEADERS").map(|v| v == "true").unwrap_or(false);
+    if trust_proxy {
+        if let Some(ip) = req.connection_info().realip_remote_addr() {
+            return ip.to_string();
+        }
+    }
+
+    req.peer_addr()
+        .map(|addr| addr.ip().to_string())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+/// Delay before the next attempt is allowed: 1s, 2s, 4s, ... capped at a minute.
+fn backoff(failed_count: i32) -> Duration {
+    let exponent = (failed_count - 1).clamp(0, 16) as u32;
+    Duration::seconds((BACKOFF_BASE_SECONDS << exponent).min(BACKOFF_MAX_SECONDS))
+}
+
+/// Whether a row's failures have aged out and it should be treated as a clean slate.
+fn is_stale(row: &loginattempt::Model, now: NaiveDateTime) -> bool {
+    match row.locked_until {
+        Some(locked_until) => locked_until <= now,
+        None => row.last_failed_at + Duration::minutes(FAILURE_WINDOW_MINUTES) <= now,
+    }
+}
+
+/// Seconds the caller must wait before another attempt against any of `keys`,
+/// or `None` when a login may proceed.
+pub async fn retry_after<C: ConnectionTrait>(conn: &C, keys: &[String]) -> Result<Option<i64>, DbErr> {
+    let rows = loginattempt::Entity::find()
+        .filter(loginattempt::Column::ThrottleKey.is_in(keys.iter().cloned()))
+        .all(conn)
+        .await?;
+
+    let now = Utc::now().naive_utc();
+    let wait = rows
+        .iter()
+        .filter(|row| !is_stale(row, now) && row.failed_count > 0)
+        .map(|row| {
+            let allowed_at = row
+                .locked_until
+                .unwrap_or_else(|| row.last_failed_at + backoff(row.failed_count));
+            (allowed_at - now).num_seconds()
+        })
+        .filter(|seconds| *seconds > 0)
+        .max();
+
+    Ok(wait)
+}
+
+/// Counts a failed login against `key`, locking it once `max_failures` is reached.
+pub async fn record_failure<C: ConnectionTrait>(
+    conn: &C,
+    key: &str,
+    user_id: Option<i32>,
+    max_failures: i32,
+) -> Result<(), DbErr> {
+    let now = Utc::now().naive_utc();
+    let existing = loginattempt::Entity::find()
+        .filter(loginattempt::Column::ThrottleKey.eq(key))
+        .one(conn)
+        .await?;
+
+    let failed_count = match &existing {
+        Some(row) if !is_stale(row, now) => row.failed_count + 1,
+        _ => 1,
+    };
+    let locked_until = if failed_count >= max_failures {
+        Some(now + Duration::minutes(LOCKOUT_MINUTES))
+    } else {
+        None
+    };
+
+    if failed_count == max_failures {
+        match user_id {
+            Some(user_id) => warn!(
+                "Login locked for user {} ({}) after {} failed attempts",
+                user_id, key, failed_count
+            ),
+            None => warn!("Login locked for {} after {} failed attempts", key, failed_count),
+        }
+    }
+
+    match existing {
+        Some(row) => {
+            let mut active: loginattempt::ActiveModel = row.into();
+            active.user_id = Set(user_id);
+            active.failed_count = Set(failed_count);
+            active.last_failed_at = Set(now);
+            active.locked_until = Set(locked_until);
+            active.update(conn).await?;
+        }
+        None => {
+            loginattempt::ActiveModel {
+                throttle_key: Set(key.to_string()),
+                user_id: Set(user_id),
+                failed_count: Set(failed_count),
+                last_failed_at: Set(now),
+                locked_until: Set(locked_until),
+                ..Default::default()
+            }
+            .insert(conn)
+            .await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Forgets all failures recorded against `key`.
+pub async fn clear<C: ConnectionTrait>(conn: &C, key: &str) -> Result<u64, DbErr> {
+    let result = loginattempt::Entity::delete_many()
+        .filter(loginattempt::Column::ThrottleKey.eq(key))
+        .exec(conn)
+        .await?;
+    Ok(result.rows_affected)
+}
@@ -0,0 +1,77 @@
+use rand::{Rng, RngCore};
+use std::env;
+use totp_rs::{Algorithm, Secret, TOTP};
+
+const DIGITS: usize = 6;
+const STEP_SECONDS: u64 = 30;
+/// Codes from one step either side of the current one are accepted, for clock drift.
+const ALLOWED_SKEW_STEPS: i64 = 1;
+const RECOVERY_CODE_COUNT: usize = 10;
+const DEFAULT_ISSUER: &str = "Arrively";
+
+/// Issuer shown in authenticator apps, from `MFA_ISSUER`.
+fn issuer() -> String {
+    env::var("MFA_ISSUER").unwrap_or_else(|_| DEFAULT_ISSUER.to_string())
+}
+
+fn totp(secret: &str, account: &str) -> Result<TOTP, String> {
+    let bytes = Secret::Encoded(secret.to_string())
+        .to_bytes()
+        .map_err(|e| format!("Invalid TOTP secret: {:?}", e))?;
+    TOTP::new(Algorithm::SHA1, DIGITS, 0, STEP_SECONDS, bytes, Some(issuer()), account.to_string())
+        .map_err(|e| format!("Invalid TOTP configuration: {}", e))
+}
+
+/// Returns a new random 160-bit secret, base32 encoded.
+pub fn generate_secret() -> String {
+    let mut bytes = [0u8; 20];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    Secret::Raw(bytes.to_vec()).to_encoded().to_string()
+}
+
+/// The `otpauth://` URI authenticator apps scan from a QR code.
+pub fn provisioning_uri(secret: &str, account: &str) -> Result<String, String> {
+    Ok(totp(secret, account)?.get_url())
+}
+
+/// Checks `code` against `secret` and returns the time step it matched.
+///
+/// Steps at or before `last_used_step` are rejected so a code cannot be replayed.
+pub fn verify(secret: &str, code: &str, last_used_step: Option<i64>) -> Result<Option<i64>, String> {
+    let totp = totp(secret, "")?;
+    let code = code.trim();
+    let current_step = chrono::Utc::now().timestamp() / STEP_SECONDS as i64;
+
+    for offset in -ALLOWED_SKEW_STEPS..=ALLOWED_SKEW_STEPS {
+        let step = current_step + offset;
+        if last_used_step.is_some_and(|last| step <= last) {
+            continue;
+        }
+        if totp.generate((step as u64) * STEP_SECONDS) == code {
+            return Ok(Some(step));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Single-use recovery codes in `xxxxx-xxxxx` form. Only their hashes are stored.
+pub fn generate_recovery_codes() -> Vec<String> {
+    const ALPHABET: &[u8] = b"abcdefghjkmnpqrstuvwxyz23456789";
+    let mut rng = rand::thread_rng();
+
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let mut chars: Vec<char> = (0..10)
+                .map(|_| ALPHABET[rng.gen_range(0..ALPHABET.len())] as char)
+                .collect();
+            chars.insert(5, '-');
+            chars.into_iter().collect()
+        })
+        .collect()
+}
+
+/// Normalises user-typed recovery codes before hashing.
+pub fn normalize_recovery_code(code: &str) -> String {
+    code.trim().to_lowercase()
+}